@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Benchmarks of the extraction of a rootfs. Creating the files takes most of the time on
+//! a disk, so they are better run in a tmpfs with `TMPDIR=/dev/shm` (see `just bench`).
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tar::{Builder, EntryType, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::{open_tar, unpack_tar, UnpackOptions};
+
+/// Rootfs shaped like the one of a small distribution: a few hundred directories with
+/// mostly small files and some larger ones, like libraries
+fn fixture(path: &Path) -> u64 {
+    let mut builder = Builder::new(File::create(path).unwrap());
+    let mut size = 0;
+    for dir in 0..200 {
+        let dir = format!("usr/share/pkg{dir}");
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, &dir, &[][..]).unwrap();
+        for file in 0..20 {
+            let len = if file == 0 { 64 * 1024 } else { 2 * 1024 };
+            let mut header = Header::new_gnu();
+            header.set_size(len as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            let content = vec![b'x'; len];
+            builder
+                .append_data(&mut header, format!("{dir}/file{file}"), &content[..])
+                .unwrap();
+            size += len as u64;
+        }
+    }
+    builder.finish().unwrap();
+    size
+}
+
+/// Empty root for the next iteration, removing the files of the previous one
+fn fresh_root(dir: &Path) -> PathBuf {
+    let root = dir.join("root");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+fn unpack(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join("unbox-bench-unpack");
+    std::fs::create_dir_all(&dir).unwrap();
+    let tar = dir.join("rootfs.tar");
+    let size = fixture(&tar);
+    let options = UnpackOptions::default();
+
+    let mut group = c.benchmark_group("unpack_tar");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(20);
+    // Baseline with the default buffer of BufReader, to compare with the one of open_tar
+    group.bench_function("default buffer", |b| {
+        b.iter_batched(
+            || fresh_root(&dir),
+            |root| {
+                let archive = BufReader::new(File::open(&tar).unwrap());
+                let root = root.to_str().unwrap();
+                unpack_tar(archive, None, root, &options, &NoProgress).unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("open_tar", |b| {
+        b.iter_batched(
+            || fresh_root(&dir),
+            |root| {
+                let (archive, total) = open_tar(&tar, &options.decoder).unwrap();
+                let root = root.to_str().unwrap();
+                unpack_tar(archive, total, root, &options, &NoProgress).unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(benches, unpack);
+criterion_main!(benches);
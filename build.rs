@@ -0,0 +1,32 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Build information shown by `unbox version`
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let target = std::env::var("TARGET").expect("cargo sets the target");
+    println!("cargo:rustc-env=UNBOX_TARGET={target}");
+    println!("cargo:rerun-if-changed=build.rs");
+    // The sources may not come from a git checkout, for example when installed from crates.io
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=UNBOX_GIT_COMMIT={commit}");
+        // Rebuilt when HEAD moves, either to another branch or to a new commit of the same one
+        let head = Path::new(".git/HEAD");
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Ok(head) = std::fs::read_to_string(head) {
+            if let Some(reference) = head.trim().strip_prefix("ref: ") {
+                println!("cargo:rerun-if-changed=.git/{reference}");
+            }
+        }
+    }
+}
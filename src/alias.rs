@@ -0,0 +1,207 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::borrow::Cow;
+
+use clap::{ArgGroup, Args, Subcommand};
+use color_eyre::eyre;
+use tabled::{Style, Table, Tabled};
+
+use crate::config::{GlobalConfig, Invocation};
+use crate::run::{self, Enter, Execute, Run};
+
+/// Manage shortcuts to enter or run commands in toolboxes
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Alias {
+    #[clap(subcommand)]
+    cmd: AliasCmd,
+}
+
+#[derive(Subcommand, PartialEq, Eq, Debug)]
+enum AliasCmd {
+    Add(Add),
+    #[clap(alias = "rm")]
+    Remove(Remove),
+    #[clap(alias = "ls")]
+    List(List),
+    Run(RunAlias),
+}
+
+/// Add a new alias, it can also be used as `unbox <alias>`
+#[derive(Args, PartialEq, Eq, Debug)]
+#[clap(group(ArgGroup::new("toolbox").required(true)))]
+struct Add {
+    #[clap(value_parser)]
+    /// Name of the alias
+    name: String,
+    #[clap(long, value_parser, group = "toolbox")]
+    /// Toolbox to be entered
+    enter: Option<String>,
+    #[clap(long, value_parser, group = "toolbox")]
+    /// Toolbox where the command is run
+    run: Option<String>,
+    #[clap(short, long, value_parser, conflicts_with = "run")]
+    /// Shell used to enter the toolbox instead of its default one
+    shell: Option<String>,
+    #[clap(value_parser, requires = "run", last = true)]
+    /// Command to run and its arguments
+    cmd: Vec<String>,
+}
+
+/// Remove aliases
+#[derive(Args, PartialEq, Eq, Debug)]
+struct Remove {
+    #[clap(value_parser)]
+    /// Names of the aliases to be removed
+    names: Vec<String>,
+}
+
+/// List aliases
+#[derive(Args, PartialEq, Eq, Debug)]
+struct List {}
+
+/// Run an alias
+#[derive(Args, PartialEq, Eq, Debug)]
+struct RunAlias {
+    #[clap(value_parser)]
+    /// Name of the alias
+    name: String,
+    #[clap(value_parser)]
+    /// Extra arguments for the command of the alias
+    args: Vec<String>,
+}
+
+/// Runs the alias subcommand, `reserved` are the names that can not be used as aliases
+/// because they are already subcommands
+pub fn alias(args: Alias, reserved: &[String]) -> eyre::Result<()> {
+    match args.cmd {
+        AliasCmd::Add(args) => add(args, reserved),
+        AliasCmd::Remove(args) => remove(args),
+        AliasCmd::List(_) => list(),
+        AliasCmd::Run(args) => run_alias(&args.name, args.args),
+    }
+}
+
+fn add(args: Add, reserved: &[String]) -> eyre::Result<()> {
+    eyre::ensure!(
+        !reserved.contains(&args.name),
+        "{} is already a subcommand and can not be used as an alias",
+        args.name
+    );
+    let mut config = GlobalConfig::read()?;
+    let invocation = match (args.enter, args.run) {
+        (Some(toolbox), _) => Invocation {
+            toolbox,
+            shell: args.shell,
+            cmd: Vec::new(),
+        },
+        (None, Some(toolbox)) => {
+            eyre::ensure!(
+                !args.cmd.is_empty(),
+                "A command to run has not been provided"
+            );
+            Invocation {
+                toolbox,
+                shell: None,
+                cmd: args.cmd,
+            }
+        }
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    config.aliases.insert(args.name, invocation);
+    config.write()
+}
+
+fn remove(args: Remove) -> eyre::Result<()> {
+    let mut config = GlobalConfig::read()?;
+    for name in args.names {
+        eyre::ensure!(
+            config.aliases.remove(&name).is_some(),
+            "There is no alias named {name}"
+        );
+    }
+    config.write()
+}
+
+struct Row<'a> {
+    name: &'a str,
+    invocation: &'a Invocation,
+}
+
+impl Tabled for Row<'_> {
+    const LENGTH: usize = 3;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        let action = match (&self.invocation.shell, self.invocation.cmd.is_empty()) {
+            (Some(shell), true) => format!("enter with {shell}"),
+            (None, true) => "enter".into(),
+            (_, false) => format!("run {}", self.invocation.cmd.join(" ")),
+        };
+        vec![
+            Cow::Borrowed(self.name),
+            Cow::Borrowed(&self.invocation.toolbox),
+            Cow::Owned(action),
+        ]
+    }
+    fn headers() -> Vec<Cow<'static, str>> {
+        ["name", "toolbox", "action"]
+            .into_iter()
+            .map(Cow::from)
+            .collect()
+    }
+}
+
+fn list() -> eyre::Result<()> {
+    let config = GlobalConfig::read()?;
+    if config.aliases.is_empty() {
+        println!("No aliases could be found, maybe you want to create a new one first:");
+        println!();
+        println!("\t unbox alias add <alias> --enter <toolbox>");
+        println!("\t unbox alias add <alias> --run <toolbox> -- <command> <arguments...>");
+        return Ok(());
+    }
+    let rows = config
+        .aliases
+        .iter()
+        .map(|(name, invocation)| Row { name, invocation });
+    let mut table = Table::new(rows);
+    let table = table.with(Style::modern());
+    print!("{table}");
+    Ok(())
+}
+
+/// Enters or runs the command of the alias, with the extra arguments appended to the command
+pub fn run_alias(name: &str, extra: Vec<String>) -> eyre::Result<()> {
+    let mut config = GlobalConfig::read()?;
+    let invocation = config
+        .aliases
+        .remove(name)
+        .ok_or_else(|| eyre::eyre!("There is no subcommand or alias named {name}"))?;
+    let mut cmd = invocation.cmd.into_iter();
+    let execute = match (cmd.next(), invocation.shell) {
+        (Some(cmd_name), _) => Execute::Run(Run {
+            name: invocation.toolbox,
+            cmd: cmd_name,
+            args: cmd.chain(extra).collect(),
+            interactive: true,
+            ..Default::default()
+        }),
+        (None, shell) => {
+            eyre::ensure!(extra.is_empty(), "The alias {name} does not take arguments");
+            match shell {
+                Some(shell) => Execute::Run(Run {
+                    name: invocation.toolbox,
+                    cmd: shell,
+                    interactive: true,
+                    ..Default::default()
+                }),
+                None => Execute::Enter(Enter {
+                    name: invocation.toolbox,
+                    ..Default::default()
+                }),
+            }
+        }
+    };
+    run::nsexec(execute)
+}
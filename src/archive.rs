@@ -0,0 +1,175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+#[cfg(feature = "gzip")]
+use flate2::{bufread::GzDecoder, write::GzEncoder};
+use serde::Serialize;
+
+use crate::unpack::READ_BUFFER_SIZE;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression of a tarball (none, gzip or zstd). Every format is detected, but each backend
+/// is only compiled in with the cargo feature of the same name
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression from the first bytes of a file
+    pub fn detect(header: &[u8]) -> Self {
+        if header.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The backend of the compression is compiled in
+    pub fn is_supported(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Error returned when the backend of the compression is not compiled in
+    fn unsupported(self) -> io::Error {
+        let name = self
+            .to_possible_value()
+            .map_or("this compression", |value| value.get_name());
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("this build lacks {name} support, it needs the {name} feature"),
+        )
+    }
+
+    /// Infers the compression from the extension of a file name
+    pub fn from_extension(path: &Path) -> Self {
+        let name = path.to_string_lossy();
+        if [".gz", ".tgz"].iter().any(|ext| name.ends_with(ext)) {
+            Compression::Gzip
+        } else if [".zst", ".tzst"].iter().any(|ext| name.ends_with(ext)) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Tuning of the decoders, trading memory for speed
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DecoderOptions {
+    /// Size of the buffer the archive is read into, the decoders read straight from it
+    pub buffer: usize,
+    /// Base 2 logarithm of the largest window a zstd frame may use, 27 by default. Frames
+    /// needing a larger one are rejected instead of allocating it.
+    pub zstd_window_log: Option<u32>,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        DecoderOptions {
+            buffer: READ_BUFFER_SIZE,
+            zstd_window_log: None,
+        }
+    }
+}
+
+/// Wraps the reader with the decoder matching its contents, without consuming
+/// the bytes used for the detection
+pub fn decoder<R>(
+    mut reader: R,
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))] options: &DecoderOptions,
+) -> io::Result<(Box<dyn Read>, Compression)>
+where
+    R: BufRead + 'static,
+{
+    let compression = Compression::detect(reader.fill_buf()?);
+    let decoder: Box<dyn Read> = match compression {
+        Compression::None => Box::new(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(GzDecoder::new(reader)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut decoder = zstd::Decoder::with_buffer(reader)?;
+            if let Some(window_log) = options.zstd_window_log {
+                decoder.window_log_max(window_log)?;
+            }
+            Box::new(decoder)
+        }
+        #[allow(unreachable_patterns)]
+        unsupported => return Err(unsupported.unsupported()),
+    };
+    Ok((decoder, compression))
+}
+
+/// Writer compressing its output with the selected compression
+pub enum Encoder<W: Write> {
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::None(writer),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(writer, Default::default())),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, 0)?),
+            #[allow(unreachable_patterns)]
+            unsupported => return Err(unsupported.unsupported()),
+        })
+    }
+
+    /// Writes the trailing data of the compression format, it has to be called
+    /// before dropping the encoder
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
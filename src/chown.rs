@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use nix::sched::CloneFlags;
+use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::namespaces::start_toolbox;
+
+/// Change the owner of every file of a toolbox to the current user
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Chown {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    pub name: String,
+}
+
+pub fn chown(args: Chown) -> eyre::Result<()> {
+    let config =
+        Config::read(&args.name).wrap_err("Could not get configuration for the toolbox")?;
+    eyre::ensure!(
+        config.squashfs.is_none(),
+        "{} is stored as a read-only squashfs image, its files can not be changed",
+        args.name
+    );
+    eyre::ensure!(
+        config.overlay.is_none(),
+        "{} only stores its changes over another toolbox, change the files of its base instead",
+        args.name
+    );
+
+    // Every id of the toolbox has to be mapped to be able to change it, and root is the current user
+    let mut ns = start_toolbox(CloneFlags::CLONE_NEWUSER, &config)?;
+    ns.wait()?;
+
+    let (owner, group) = (Some(Uid::from_raw(0)), Some(Gid::from_raw(0)));
+    for entry in WalkDir::new(&config.image) {
+        let entry = entry.wrap_err("Could not walk the rootfs of the toolbox")?;
+        let meta = entry
+            .metadata()
+            .wrap_err(format!("Could not read the metadata of {:?}", entry.path()))?;
+        fchownat(
+            None,
+            entry.path(),
+            owner,
+            group,
+            FchownatFlags::NoFollowSymlink,
+        )
+        .wrap_err(format!("Could not change the owner of {:?}", entry.path()))?;
+        if meta.is_file() {
+            // Changing the owner clears the setuid bits, like the ones of su and sudo
+            std::fs::set_permissions(entry.path(), meta.permissions()).wrap_err(format!(
+                "Could not restore the permissions of {:?}",
+                entry.path()
+            ))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::{create_dir, hard_link, read_link, File};
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use nix::sched::CloneFlags;
+use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::config::{parse_new_name, Config};
+use crate::namespaces::start_toolbox;
+use crate::progress::{ProgressSink, WarningsOnly};
+use crate::unpack::{resolve_in_root, UnpackStats};
+use crate::verbosity::Verbosity;
+
+/// Copy an existing toolbox with a new name
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct CloneToolbox {
+    #[clap(value_parser)]
+    /// Name of the toolbox to be copied
+    pub source: String,
+    #[clap(value_parser = parse_new_name)]
+    /// Name of the new toolbox
+    pub name: String,
+    #[clap(short, long, value_parser)]
+    /// Do not show the warnings, like the special files that are skipped
+    pub quiet: bool,
+}
+
+pub fn clone(args: CloneToolbox) -> eyre::Result<()> {
+    let mut config =
+        Config::read(&args.source).wrap_err("Could not get configuration for the toolbox")?;
+    eyre::ensure!(
+        config.squashfs.is_none(),
+        "{} is stored as a read-only squashfs image, copy the image instead",
+        args.source
+    );
+    eyre::ensure!(
+        config.overlay.is_none(),
+        "{} only stores its changes over another toolbox, create a new one over the same base",
+        args.source
+    );
+    let source = PathBuf::from(&config.image);
+    let target = Config::new(&args.name)?.image;
+    eyre::ensure!(
+        Config::read(&args.name).is_err() && !Path::new(&target).exists(),
+        "There is already a toolbox named {}",
+        args.name
+    );
+
+    // Every id of the toolbox has to be mapped to keep the owners of the files
+    let mut ns = start_toolbox(CloneFlags::CLONE_NEWUSER, &config)?;
+    ns.wait()?;
+    let options = CopyOptions {
+        mode: CopyMode::KeepOwners,
+        dereference: false,
+    };
+    let warnings = WarningsOnly(Verbosity::from_flags(args.quiet, false));
+    copy_tree(&source, Path::new(&target), options, &warnings)?;
+
+    if config.hostname == args.source {
+        config.hostname = args.name.clone();
+    }
+    config.image = target;
+    config.write(&args.name)
+}
+
+/// How [`copy_tree`] creates the files of the new tree
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CopyMode {
+    /// Copy the files with their owners, every id of the toolbox has to be mapped
+    KeepOwners,
+    /// Copy the files, which are owned by the current user
+    Copy,
+    /// Hard link the files instead of copying them, both trees share them afterwards
+    Link,
+}
+
+/// Options of [`copy_tree`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CopyOptions {
+    pub mode: CopyMode,
+    /// Copy the files that symlinks point to instead of the links, resolving them inside of
+    /// the source. Links to directories are kept, since they stay valid in the copy.
+    pub dereference: bool,
+}
+
+/// Copies the tree keeping permissions and modification times, and the owners with
+/// [`CopyMode::KeepOwners`]. The contents of the files are copied with `copy_file_range`,
+/// which shares the data on filesystems like btrfs or xfs. Special files, like device nodes,
+/// and dangling links that are dereferenced are skipped with a warning. The target itself
+/// may already exist as an empty directory.
+pub fn copy_tree(
+    source: &Path,
+    target: &Path,
+    options: CopyOptions,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<UnpackStats> {
+    let mode = options.mode;
+    let mut stats = UnpackStats::default();
+    let mut dirs = Vec::new();
+    for entry in WalkDir::new(source) {
+        let entry = entry.wrap_err("Could not walk the rootfs of the toolbox")?;
+        let relative = entry
+            .path()
+            .strip_prefix(source)
+            .expect("walked from source");
+        let path = target.join(relative);
+        let mut from = entry.path().to_path_buf();
+        let mut meta = entry
+            .path()
+            .symlink_metadata()
+            .wrap_err(format!("Could not read the metadata of {:?}", entry.path()))?;
+        stats.entries += 1;
+        if options.dereference && meta.is_symlink() {
+            let resolved = resolve_in_root(source, relative)
+                .and_then(|resolved| Ok((resolved.symlink_metadata()?, resolved)));
+            match resolved {
+                Ok((target, resolved)) if target.is_file() => (meta, from) = (target, resolved),
+                Ok(_) => {}
+                Err(_) => {
+                    progress.on_warning(&format!(
+                        "Skipping {:?}, it points to a missing file",
+                        entry.path()
+                    ));
+                    stats.skipped.push(entry.path().into());
+                    continue;
+                }
+            }
+        }
+        let kind = meta.file_type();
+        if kind.is_dir() {
+            if !(relative.as_os_str().is_empty() && path.is_dir()) {
+                create_dir(&path).wrap_err(format!("Could not create the directory {path:?}"))?;
+            }
+            // The permissions are applied at the end, in case the directory is read-only
+            dirs.push((path.clone(), meta.clone()));
+        } else if kind.is_symlink() {
+            let link = read_link(entry.path())?;
+            symlink(&link, &path).wrap_err(format!("Could not create the link {path:?}"))?;
+        } else if kind.is_file() && mode == CopyMode::Link {
+            hard_link(&from, &path).wrap_err(format!("Could not link the file {from:?}"))?;
+            // The file is shared, so it already has its metadata
+            continue;
+        } else if kind.is_file() {
+            std::fs::copy(&from, &path).wrap_err(format!("Could not copy the file {from:?}"))?;
+            set_modified(&path, &meta)?;
+            stats.bytes += meta.len();
+        } else {
+            progress.on_warning(&format!("Skipping special file {:?}", entry.path()));
+            stats.skipped.push(entry.path().into());
+            continue;
+        }
+        // The copy already has the permissions, they only have to be restored after a chown
+        if mode != CopyMode::KeepOwners {
+            continue;
+        }
+        fchownat(
+            None,
+            &path,
+            Some(Uid::from_raw(meta.uid())),
+            Some(Gid::from_raw(meta.gid())),
+            FchownatFlags::NoFollowSymlink,
+        )
+        .wrap_err(format!("Could not change the owner of {path:?}"))?;
+        if kind.is_file() {
+            // Changing the owner clears the setuid bits, so the permissions go last
+            std::fs::set_permissions(&path, meta.permissions())
+                .wrap_err(format!("Could not set the permissions of {path:?}"))?;
+        }
+    }
+    for (dir, meta) in dirs.into_iter().rev() {
+        set_modified(&dir, &meta)?;
+        let perms = std::fs::Permissions::from_mode(meta.mode() & 0o7777);
+        std::fs::set_permissions(&dir, perms)
+            .wrap_err(format!("Could not set the permissions of {dir:?}"))?;
+    }
+    Ok(stats)
+}
+
+fn set_modified(path: &Path, meta: &std::fs::Metadata) -> eyre::Result<()> {
+    let modified = meta.modified()?;
+    File::open(path)
+        .and_then(|file| file.set_modified(modified))
+        .wrap_err(format!("Could not set the modification time of {path:?}"))
+}
+
+/// What [`hash_tree`] reads of each entry of the tree
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TreeHash {
+    /// Paths, types, permissions, sizes, modification times and link targets
+    Metadata,
+    /// The same, and the contents of the files, which reads every byte of the tree
+    Contents,
+}
+
+/// Digest of the tree, walked in the order of the names so it only changes with its entries.
+/// It is prefixed by what it covers. The metadata is hashed with the 64 bits FNV-1a, like
+/// `metadata:1f2e3d4c5b6a7988`, enough to tell that a directory changed, while the contents
+/// are hashed with sha256 so they can be trusted.
+pub fn hash_tree(source: &Path, kind: TreeHash) -> eyre::Result<String> {
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut hasher = match kind {
+        TreeHash::Metadata => TreeHasher::Metadata(Fnv1a::default()),
+        TreeHash::Contents => TreeHasher::Contents(Sha256::new()),
+    };
+    let mut buffer = vec![0; 64 * 1024];
+    for entry in WalkDir::new(source).sort_by_file_name() {
+        let entry = entry.wrap_err("Could not walk the rootfs directory")?;
+        let relative = entry
+            .path()
+            .strip_prefix(source)
+            .expect("walked from source");
+        let meta = entry
+            .path()
+            .symlink_metadata()
+            .wrap_err(format!("Could not read the metadata of {:?}", entry.path()))?;
+        // Each field ends with a zero byte or has a fixed size, so they can not run together
+        hasher.write(relative.as_os_str().as_bytes());
+        hasher.write(&[0]);
+        hasher.write(&meta.mode().to_le_bytes());
+        hasher.write(&meta.len().to_le_bytes());
+        hasher.write(&meta.mtime().to_le_bytes());
+        hasher.write(&meta.mtime_nsec().to_le_bytes());
+        if meta.is_symlink() {
+            hasher.write(read_link(entry.path())?.as_os_str().as_bytes());
+            hasher.write(&[0]);
+        } else if meta.is_file() && kind == TreeHash::Contents {
+            let mut file =
+                File::open(entry.path()).wrap_err(format!("Could not open {:?}", entry.path()))?;
+            loop {
+                match file.read(&mut buffer)? {
+                    0 => break,
+                    read => hasher.write(&buffer[..read]),
+                }
+            }
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Hasher of the kind of [`TreeHash`]
+enum TreeHasher {
+    Metadata(Fnv1a),
+    Contents(Sha256),
+}
+
+impl TreeHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        use std::hash::Hasher;
+        match self {
+            TreeHasher::Metadata(fnv) => fnv.write(bytes),
+            TreeHasher::Contents(sha) => sha.update(bytes),
+        }
+    }
+
+    fn finish(self) -> String {
+        use std::hash::Hasher;
+        match self {
+            TreeHasher::Metadata(fnv) => format!("metadata:{:016x}", fnv.finish()),
+            TreeHasher::Contents(sha) => {
+                let digest: String = sha.finalize().iter().map(|b| format!("{b:02x}")).collect();
+                format!("contents:{digest}")
+            }
+        }
+    }
+}
+
+/// FNV-1a, written here since the hasher of std may change between releases
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl std::hash::Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
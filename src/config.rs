@@ -2,25 +2,68 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsString;
 use std::fs::{create_dir_all, File};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
-use clap::Args;
+use clap::{Args, Subcommand};
 use color_eyre::eyre;
 use color_eyre::eyre::WrapErr;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use toml::map::Keys;
 use toml::value::{Table, Value};
 
+use crate::namespaces::IdMap;
+use crate::os_release::Distro;
+use crate::unpack::resolve_in_root;
+
 pub const STORAGE: &str = ".local/share/unbox";
+/// Environment variable overriding where the toolboxes are stored
+pub const STORAGE_ROOT_ENV: &str = "UNBOX_ROOT";
+
+/// Overrides where the toolboxes are stored for this invocation, it is passed through the
+/// environment so the helper processes spawned by unbox use it too
+pub fn set_storage_root(root: &Path) {
+    env::set_var(STORAGE_ROOT_ENV, root);
+}
+
+/// Directory where the toolboxes are stored, the first one that is set of: `--storage-root`,
+/// `UNBOX_ROOT`, `storage_root` in the global config or `$XDG_DATA_HOME/unbox`
+pub fn storage_root() -> eyre::Result<PathBuf> {
+    match env::var_os(STORAGE_ROOT_ENV) {
+        Some(root) if !root.is_empty() => return Ok(root.into()),
+        _ => {}
+    }
+    match GlobalConfig::read()?.storage_root {
+        Some(root) => Ok(root),
+        None => default_storage(),
+    }
+}
+
+/// `$XDG_DATA_HOME/unbox` or `~/.local/share/unbox`, where the global config is always kept
+fn default_storage() -> eyre::Result<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(data) if !data.is_empty() => Ok(PathBuf::from(data).join("unbox")),
+        _ => {
+            let home = env::var("HOME").wrap_err("Could not find current home")?;
+            Ok(Path::new(&home).join(STORAGE))
+        }
+    }
+}
 
 /// Configure a toolbox creating a new meta-file if needed
 #[derive(Args, PartialEq, Eq, Debug)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Configure {
-    #[clap(value_parser)]
+    #[clap(subcommand)]
+    cmd: Option<ConfigureCmd>,
+    #[clap(value_parser, required = true)]
     /// Name of the toolbox
-    name: String,
+    name: Option<String>,
     #[clap(short, long, value_parser)]
     /// Default shell for the image
     shell: Option<String>,
@@ -30,11 +73,83 @@ pub struct Configure {
     #[clap(long, value_parser)]
     /// Default home for the image
     home: Option<String>,
+    #[clap(long, value_parser)]
+    /// Enter the toolbox with a login shell by default (true or false)
+    login: Option<bool>,
+    #[clap(long, value_name = "ARGS", value_parser = parse_shell_args, allow_hyphen_values = true)]
+    /// Arguments given to the shell when entering the toolbox, split like a shell does. An
+    /// empty string removes them
+    shell_args: Option<ShellArgs>,
+}
+
+/// Subcommands of `config`, which the name of a toolbox can not be since it could not be
+/// configured then
+const CONFIGURE_CMDS: [&str; 3] = ["schema", "get", "set"];
+
+/// Parses the name of a new toolbox
+pub fn parse_new_name(name: &str) -> Result<String, String> {
+    if CONFIGURE_CMDS.contains(&name) {
+        Err(format!(
+            "{name} can not be the name of a toolbox, it is a subcommand of config"
+        ))
+    } else {
+        Ok(name.into())
+    }
+}
+
+#[derive(Subcommand, PartialEq, Eq, Debug)]
+enum ConfigureCmd {
+    Schema(Schema),
+    Get(GetSetting),
+    Set(SetSetting),
+}
+
+/// Print the JSON Schema of the configuration of a toolbox, to validate hand-written ones
+#[derive(Args, PartialEq, Eq, Debug)]
+struct Schema {
+    #[clap(long, value_parser)]
+    /// Print the schema of the global config instead
+    global: bool,
+}
+
+/// Print a setting of a toolbox
+#[derive(Args, PartialEq, Eq, Debug)]
+struct GetSetting {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    name: String,
+    #[clap(value_parser)]
+    /// Setting to print, like shell or hostname
+    key: String,
 }
 
+/// Change a setting of a toolbox, validating the new value first
+#[derive(Args, PartialEq, Eq, Debug)]
+struct SetSetting {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    name: String,
+    #[clap(value_parser)]
+    /// Setting to change, one of shell, hostname, home or login
+    key: String,
+    #[clap(value_parser)]
+    /// New value of the setting
+    value: String,
+}
+
+/// Settings that can be changed with `configure set`, the rest are written by unbox itself
+pub const SETTABLE: [&str; 4] = ["shell", "hostname", "home", "login"];
+
 pub fn configure(args: Configure) -> eyre::Result<()> {
+    let name = match (args.cmd, args.name) {
+        (Some(ConfigureCmd::Schema(args)), _) => return schema(args),
+        (Some(ConfigureCmd::Get(args)), _) => return get_setting(args),
+        (Some(ConfigureCmd::Set(args)), _) => return set_setting(args),
+        (None, Some(name)) => name,
+        (None, None) => unreachable!("clap requires the name without a subcommand"),
+    };
     let mut config =
-        Config::read_or_new(&args.name).wrap_err("Could not get configuration for the toolbox")?;
+        Config::read_or_new(&name).wrap_err("Could not get configuration for the toolbox")?;
     if let Some(sh) = args.shell {
         config.shell = sh;
     }
@@ -44,33 +159,255 @@ pub fn configure(args: Configure) -> eyre::Result<()> {
     if let Some(home) = args.home {
         config.home = home;
     }
-    config.write(&args.name)?;
+    if let Some(login) = args.login {
+        config.login = login;
+    }
+    if let Some(ShellArgs(shell_args)) = args.shell_args {
+        config.shell_args = shell_args;
+    }
+    config.write(&name)?;
+    Ok(())
+}
+
+fn get_setting(args: GetSetting) -> eyre::Result<()> {
+    let config =
+        Config::read(&args.name).wrap_err("Could not get configuration for the toolbox")?;
+    // The settings are the properties of the schema, since the unset ones are not serialized
+    let schema = schemars::schema_for!(Config);
+    let keys: Vec<&String> = schema
+        .schema
+        .object
+        .as_ref()
+        .map(|object| object.properties.keys().collect())
+        .unwrap_or_default();
+    if !keys.contains(&&args.key) {
+        let keys: Vec<&str> = keys.iter().map(|key| key.as_str()).collect();
+        eyre::bail!(
+            "{} is not a setting of a toolbox, the valid ones are: {}",
+            args.key,
+            keys.join(", ")
+        );
+    }
+    let table = Value::try_from(&config).wrap_err("Could not serialize the configuration")?;
+    match table.get(&args.key) {
+        Some(Value::String(value)) => println!("{value}"),
+        Some(value) => println!("{value}"),
+        None => {}
+    }
+    Ok(())
+}
+
+fn set_setting(args: SetSetting) -> eyre::Result<()> {
+    let (name, value) = (&args.name, args.value);
+    let mut config = Config::read(name).wrap_err("Could not get configuration for the toolbox")?;
+    match args.key.as_str() {
+        "shell" => {
+            // The rootfs of a squashfs or overlay toolbox is only available while it is mounted
+            let root = Path::new(&config.image);
+            let mounted = config.squashfs.is_some() || config.overlay.is_some();
+            eyre::ensure!(
+                mounted || resolve_in_root(root, &value)?.is_file(),
+                "{value} does not exist in the toolbox"
+            );
+            config.shell = value;
+        }
+        "hostname" => {
+            let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'.';
+            eyre::ensure!(
+                (1..=64).contains(&value.len()) && value.bytes().all(valid),
+                "{value} is not a valid hostname"
+            );
+            config.hostname = value;
+        }
+        "home" => {
+            eyre::ensure!(
+                Path::new(&value).is_absolute(),
+                "The home has to be an absolute path"
+            );
+            config.home = value;
+        }
+        "login" => {
+            config.login = value
+                .parse()
+                .map_err(|_| eyre::eyre!("login is either true or false, not {value}"))?;
+        }
+        key => eyre::bail!(
+            "{key} can not be set, the settings that can be changed are: {}",
+            SETTABLE.join(", ")
+        ),
+    }
+    config.write(name)
+}
+
+fn schema(args: Schema) -> eyre::Result<()> {
+    let schema = if args.global {
+        schemars::schema_for!(GlobalConfig)
+    } else {
+        schemars::schema_for!(Config)
+    };
+    println!("{}", serde_json::to_string_pretty(&schema)?);
     Ok(())
 }
 
 pub fn setup() -> eyre::Result<()> {
-    use std::env::var;
-    let home = var("HOME").wrap_err("Could not find current home")?;
-    let meta = format!("{home}/{}/meta/", STORAGE);
-    let images = format!("{home}/{}/images/", STORAGE);
+    let storage = storage_root()?;
+    let meta = storage.join("meta");
+    let images = storage.join("images");
     create_dir_all(&meta).wrap_err("Could not create meta directory")?;
     create_dir_all(&images).wrap_err("Could not create images directory")
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Configuration shared by every toolbox
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct GlobalConfig {
+    /// Directory where the toolboxes are stored instead of the default one
+    #[serde(default)]
+    pub storage_root: Option<PathBuf>,
+    /// Tag of the images given without a tag or a digest, instead of latest
+    #[serde(default)]
+    pub default_tag: Option<String>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Invocation>,
+}
+
+/// Named invocation of `enter` or `run`
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Invocation {
+    pub toolbox: String,
+    /// Shell used instead of the default one of the toolbox when entering it
+    pub shell: Option<String>,
+    /// Command to run, the toolbox is entered if it is empty
+    #[serde(default)]
+    pub cmd: Vec<String>,
+}
+
+impl GlobalConfig {
+    pub fn read() -> eyre::Result<Self> {
+        let storage = default_storage()?.join("config.toml");
+        match std::fs::read_to_string(storage) {
+            Ok(content) => toml::from_str(&content).wrap_err("Global config file is corrupted"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GlobalConfig::default()),
+            Err(e) => Err(e).wrap_err("Could not read global config file"),
+        }
+    }
+
+    pub fn write(&self) -> eyre::Result<()> {
+        use std::io::prelude::*;
+        let storage = default_storage()?;
+        create_dir_all(&storage).wrap_err("Could not create the storage directory")?;
+        let storage = storage.join("config.toml");
+        let content = toml::to_string(self).expect("valid toml config");
+        let mut file = File::create(storage).wrap_err("Could not create global config file")?;
+        file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Configuration of a toolbox, stored in its meta file
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct Config {
+    /// Shell used to enter the toolbox
     pub shell: String,
     pub hostname: String,
+    /// Home directory inside of the toolbox
     pub home: String,
+    /// Directory with the rootfs of the toolbox
     pub image: String,
+    /// User added to the passwd and group files of the rootfs
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Commands run inside of the toolbox after creating it
+    #[serde(default)]
+    pub post_create: Vec<String>,
+    /// The shell is started as a login shell when entering the toolbox
+    #[serde(default)]
+    pub login: bool,
+    /// Arguments given to the shell when entering the toolbox, like `--noprofile`
+    #[serde(default)]
+    pub shell_args: Vec<String>,
+    /// The whiteouts of the image have been resolved while creating it
+    #[serde(default)]
+    pub flattened: bool,
+    /// Nameservers written to the resolv.conf of the toolbox
+    #[serde(default)]
+    pub dns: Vec<IpAddr>,
+    /// Image or tarball used to create the toolbox
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Hash of the `--rootfs-dir` directory when it was copied, to tell if it changed since
+    #[serde(default)]
+    pub source_hash: Option<String>,
+    /// Digest the image resolved to when the toolbox was created, to pin it to the same image
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// User set by the image, commands are run as this user unless another one is requested
+    #[serde(default)]
+    pub image_user: Option<String>,
+    /// Directories created in the rootfs besides the default ones
+    #[serde(default)]
+    pub extra_dirs: Vec<String>,
+    /// Squashfs image mounted read-only as the rootfs, over the empty directory of `image`
+    #[serde(default)]
+    pub squashfs: Option<String>,
+    /// Layers of the overlay mounted as the rootfs, over the empty directory of `image`
+    #[serde(default)]
+    pub overlay: Option<Overlay>,
+    /// Distribution of the rootfs read from its os-release file, set as UNBOX_DISTRO inside
+    /// of the toolbox
+    #[serde(default)]
+    pub distro: Option<Distro>,
+    /// Labels describing the toolbox, like the version of its image
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Annotations of the OCI images exported from the toolbox. Exports are plain tarballs for
+    /// now, so they are only recorded
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    /// Environment variables set when entering the toolbox or running a command in it
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Mappings of the users when entering the toolbox, instead of the default ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uid_map: Vec<IdMap>,
+    /// Mappings of the groups when entering the toolbox, the ones of the users by default
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gid_map: Vec<IdMap>,
+    /// Bind mounts of the toolbox, from the path inside of it to the one on the host
+    #[schemars(with = "BTreeMap<String, String>")]
     mounts: Table,
 }
 
+/// Layers of a toolbox sharing the rootfs of another one, which only stores its own changes
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Overlay {
+    /// Toolbox whose rootfs is the read-only lower layer
+    pub base: String,
+    /// Rootfs of the base
+    pub lower: String,
+    /// Directory with the changes made inside of this toolbox
+    pub upper: String,
+    /// Directory needed by overlayfs, in the same filesystem as `upper`
+    pub work: String,
+}
+
+impl Overlay {
+    /// Layers of a new toolbox over `base`, stored in `overlays/<name>` of the storage root
+    pub fn new(name: &str, base: &str, lower: &str) -> eyre::Result<Self> {
+        let layer = storage_root()?.join("overlays").join(name);
+        Ok(Overlay {
+            base: base.into(),
+            lower: lower.into(),
+            upper: layer.join("upper").display().to_string(),
+            work: layer.join("work").display().to_string(),
+        })
+    }
+}
+
 impl Config {
     pub fn new(name: &str) -> eyre::Result<Self> {
         use std::env::var;
         let shell = var("SHELL").wrap_err("Could not find current shell")?;
-        let home = var("HOME").wrap_err("Could not find current home")?;
+        let storage = storage_root()?;
         let user = users::get_current_username()
             .expect("the user still exits")
             .into_string()
@@ -79,14 +416,32 @@ impl Config {
             shell,
             hostname: name.to_string(),
             home: format!("/home/{user}"),
-            image: format!("{home}/{}/images/{name}", STORAGE),
+            image: format!("{}/images/{name}", storage.display()),
+            user: None,
+            post_create: Vec::new(),
+            login: false,
+            shell_args: Vec::new(),
+            flattened: false,
+            dns: Vec::new(),
+            source: None,
+            source_hash: None,
+            digest: None,
+            image_user: None,
+            extra_dirs: Vec::new(),
+            squashfs: None,
+            overlay: None,
+            distro: None,
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            env: BTreeMap::new(),
+            uid_map: Vec::new(),
+            gid_map: Vec::new(),
             mounts: Config::default_mounts(),
         })
     }
 
     pub fn read(name: &str) -> eyre::Result<Self> {
-        let home = env::var("HOME").wrap_err("Could not find current home")?;
-        let storage = format!("{home}/{}/meta/{name}.toml", STORAGE);
+        let storage = meta_path(name)?;
         let meta = std::fs::read_to_string(storage).wrap_err("Could not read meta file")?;
         let config: Config = toml::from_str(&meta).wrap_err("Meta file is corrupted")?;
         Ok(config)
@@ -101,8 +456,7 @@ impl Config {
 
     pub fn write(&self, name: &str) -> eyre::Result<()> {
         use std::io::prelude::*;
-        let home = env::var("HOME").wrap_err("Could not find current home")?;
-        let storage = format!("{home}/{}/meta/{name}.toml", STORAGE);
+        let storage = meta_path(name)?;
         let content = toml::to_string(self).expect("valid toml config");
         let mut file = File::create(storage).wrap_err("Could not create meta file")?;
         file.write_all(content.as_bytes())?;
@@ -116,10 +470,13 @@ impl Config {
         }
     }
 
+    /// Stops mounting anything at `target`
+    pub fn remove_mount(&mut self, target: &str) {
+        self.mounts.remove(target);
+    }
+
     fn default_mounts() -> Table {
         [
-            ("/proc", "/host/proc"),
-            ("/sys", "/host/sys"),
             ("/tmp", "/host/tmp"),
             ("/dev", "/host/dev"),
             ("/run", "/host/run"),
@@ -133,6 +490,80 @@ impl Config {
     }
 }
 
+/// Checks if the name can be used for an environment variable in a shell
+pub fn is_env_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    matches!(bytes.next(), Some(b) if b.is_ascii_alphabetic() || b == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Arguments of a shell given as a single string
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ShellArgs(pub Vec<String>);
+
+/// Splits the string into arguments at the unquoted whitespace like a shell does. Single
+/// quotes keep everything inside of them, while in double quotes and outside of quotes a
+/// backslash escapes the next character.
+pub fn parse_shell_args(args: &str) -> Result<ShellArgs, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => match chars.next() {
+                Some(next) => word.get_or_insert_with(String::new).push(next),
+                None => return Err(format!("{args} ends with an escape")),
+            },
+            '\'' | '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') if c == '"' => match chars.next() {
+                            Some(next) => word.push(next),
+                            None => return Err(format!("{args} ends with an escape")),
+                        },
+                        Some(inside) => word.push(inside),
+                        None => return Err(format!("{args} has an unterminated {c}")),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(ShellArgs(words))
+}
+
+/// Parses a dotenv file with a `KEY=VALUE` per line, where empty lines and the ones starting
+/// with `#` are ignored. Lines can start with `export`, and values can be quoted with `"` or
+/// `'`, which are removed.
+pub fn parse_env_file(contents: &str) -> eyre::Result<Vec<(String, String)>> {
+    let mut env = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if is_env_name(key.trim_end()) => (key.trim_end(), value.trim()),
+            _ => eyre::bail!("Line {} is not like KEY=VALUE: {line}", i + 1),
+        };
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote));
+        env.push((key.to_string(), unquoted.unwrap_or(value).to_string()));
+    }
+    Ok(env)
+}
+
+/// Path of the meta file with the configuration of a toolbox
+pub fn meta_path(name: &str) -> eyre::Result<PathBuf> {
+    Ok(storage_root()?.join("meta").join(format!("{name}.toml")))
+}
+
 pub struct Mounts<'a> {
     keys: Keys<'a>,
     table: &'a Table,
@@ -2,192 +2,1291 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::ffi::OsStr;
-use std::fmt::Display;
-use std::fs::File;
+use std::fs::{symlink_metadata, File};
+use std::io::IsTerminal;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::process::Output;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use clap::builder::BoolishValueParser;
 use clap::{Args, ValueEnum};
 use color_eyre::eyre;
 use color_eyre::eyre::WrapErr;
-use indicatif::ProgressBar;
 use nix::sched::CloneFlags;
+use rustix::fs::{renameat_with, RenameFlags, CWD};
+use serde::Serialize;
 use std::fs::create_dir_all;
-use tar::Archive;
 
-use crate::config::Config;
-use crate::namespaces::{Mapping, Namespace};
+use crate::archive::{Compression, DecoderOptions};
+use crate::clone::{copy_tree, hash_tree, CopyMode, CopyOptions, TreeHash};
+use crate::config::{
+    is_env_name, meta_path, parse_env_file, parse_new_name, parse_shell_args, Config, GlobalConfig,
+    Overlay, ShellArgs,
+};
+use crate::deadline::{is_timeout, parse_duration, Deadline};
+use crate::doctor::SubordinateIds;
+use crate::download::{download, is_url};
+pub use crate::engine::Engine;
+use crate::engine::{
+    get_image, image_digest, image_labels, image_user, resolve_image, stored_digest, CustomEngine,
+    EngineCommands, DEFAULT_TAG,
+};
+use crate::export::write_tar;
+use crate::hooks::Hooks;
+use crate::namespaces::{check_id_maps, parse_id_map, start_toolbox, IdMap};
+use crate::os_release;
+use crate::progress::{verbosity, Output, ProgressSink, Spinner};
+use crate::remove::{dependents, make_writable, remove_one};
+use crate::unpack::{
+    is_too_large, is_zip, open_tar, remove_leftover_whiteouts, remove_resume_index,
+    resolve_in_root, saved_layers, unpack_saved, unpack_tar, unpack_zip, UnpackOptions,
+    UnpackStats, DEFAULT_MAX_ENTRIES, MOUNTPOINT_DIRS, READ_BUFFER_SIZE,
+};
+use crate::user;
+use crate::verbosity::debug;
 
 /// Create a toolbox rootfs from an image
-#[derive(Args, PartialEq, Eq, Debug)]
+#[derive(Args, PartialEq, Eq, Debug, Default)]
 pub struct Create {
-    #[clap(value_parser)]
+    #[clap(value_parser = parse_new_name)]
     /// Name of the toolbox
     pub name: String,
     #[clap(short, long, value_parser)]
-    /// Path to the tarball
-    pub tar: Option<PathBuf>,
+    /// Path to the tarball or zip file, `-` to read a tarball from stdin, or an http(s) url to
+    /// download it from. It can be repeated to unpack each archive over the previous ones
+    pub tar: Vec<PathBuf>,
     #[clap(short, long, value_parser)]
     /// Url of the OCI image
     pub image: Option<String>,
-    #[clap(short, long, value_parser)]
+    #[clap(long, value_parser, requires = "image")]
+    /// Tag of the image if it is given without a tag or a digest, instead of the default_tag of
+    /// the global config or latest
+    pub default_tag: Option<String>,
+    #[clap(short, long, env = "UNBOX_ENGINE", value_parser)]
     /// OCI engine to extract the rootfs
     pub engine: Option<Engine>,
-    #[clap(short, long, value_parser)]
+    #[clap(flatten)]
+    pub custom_engine: CustomEngine,
+    #[clap(long, value_parser, conflicts_with_all = &["tar", "image", "squashfs", "user"])]
+    /// Share the rootfs of an existing toolbox, storing only the changes made in this one with
+    /// overlayfs (needs Linux 5.11 or newer to enter it)
+    pub base: Option<String>,
+    #[clap(long, value_parser, conflicts_with_all = &["tar", "image", "base"])]
+    /// Directory with an already extracted rootfs, copied into the toolbox instead of unpacking
+    /// an archive
+    pub rootfs_dir: Option<PathBuf>,
+    #[clap(long, value_parser, requires = "rootfs-dir")]
+    /// Hard link the files of --rootfs-dir instead of copying them, so they are shared with it.
+    /// Both have to be in the same filesystem
+    pub link: bool,
+    #[clap(long, value_parser, requires = "rootfs-dir")]
+    /// Copy the files that the symlinks of --rootfs-dir point to instead of the links, the
+    /// dangling ones are skipped with a warning
+    pub dereference: bool,
+    #[clap(long, value_parser, requires = "rootfs-dir")]
+    /// Hash the contents of the files of --rootfs-dir in the source_hash of the config, instead
+    /// of only their paths, sizes and modification times
+    pub deep_hash: bool,
+    #[clap(
+        long,
+        value_parser,
+        requires = "image",
+        conflicts_with = "tar",
+        group = "saved"
+    )]
+    /// Save the image with its layers instead of exporting the filesystem of a container
+    pub save: bool,
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["tar", "image", "base", "rootfs-dir"],
+        group = "saved"
+    )]
+    /// Archive written by `docker save` or `podman save`, whose layers are unpacked in the order
+    /// of its manifest.json without needing an engine
+    pub docker_archive: Option<PathBuf>,
+    #[clap(long, value_parser, requires = "saved")]
+    /// Print the layers of the saved image in the order they are applied, with their digests,
+    /// sizes and compressions, before unpacking them
+    pub show_layers: bool,
+    #[clap(long, value_parser, requires = "image")]
+    /// Only use the image if it is stored locally by the engine, without pulling it
+    pub local: bool,
+    #[clap(long, value_parser, requires = "image")]
+    /// Copy the labels of the image into the configuration of the toolbox, the ones given with
+    /// --label take precedence
+    pub import_labels: bool,
+    #[clap(long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    /// Label describing the toolbox, shown by `list --format json`. It can be repeated
+    pub labels: Vec<(String, String)>,
+    #[clap(long = "annotation", value_name = "KEY=VALUE", value_parser = parse_annotation)]
+    /// Annotation recorded in the configuration of the toolbox for the OCI images exported
+    /// from it, can be repeated
+    pub annotations: Vec<(String, String)>,
+    #[clap(long = "env", value_name = "KEY=VALUE", value_parser = parse_env)]
+    /// Environment variable set inside of the toolbox, it can be repeated and takes precedence
+    /// over --env-file
+    pub envs: Vec<(String, String)>,
+    #[clap(long, value_parser)]
+    /// Dotenv file with a KEY=VALUE per line to set inside of the toolbox, it can be repeated
+    pub env_file: Vec<PathBuf>,
+    #[clap(short, long, env = "UNBOX_SHELL", value_parser)]
     /// Default shell for the image to be created
     pub shell: Option<String>,
-    #[clap(short, long, value_parser)]
-    /// Default shell for the image to be created
+    #[clap(long, value_name = "ARGS", value_parser = parse_shell_args, allow_hyphen_values = true)]
+    /// Arguments given to the shell when entering the toolbox, split like a shell does, like
+    /// "--noprofile --norc"
+    pub shell_args: Option<ShellArgs>,
+    #[clap(
+        long,
+        value_name = "ARG",
+        value_parser,
+        allow_hyphen_values = true,
+        conflicts_with = "shell-args"
+    )]
+    /// Argument given to the shell when entering the toolbox as it is, can be repeated
+    pub shell_arg: Vec<String>,
+    #[clap(short, long, visible_alias = "no-progress", env = "UNBOX_QUIET", value_parser = BoolishValueParser::new())]
+    /// Do not show the progress, only a line with the summary once it is created
     pub quiet: bool,
+    #[clap(long, value_parser)]
+    /// Do not show the progress nor the summary, errors are still shown
+    pub silent: bool,
+    #[clap(long, value_parser, default_value = "text")]
+    /// Format of the summary, json implies --silent
+    pub output: Output,
+    #[clap(short, long, value_parser)]
+    /// User created inside of the toolbox, the current user by default
+    pub user: Option<String>,
+    #[clap(long, value_parser)]
+    /// Clear the setuid and setgid bits of the files of the image
+    pub no_setuid: bool,
+    #[clap(long, value_parser)]
+    /// Restore the extended attributes of the files, like file capabilities. The ones that can
+    /// not be set in the user namespace are skipped with a warning
+    pub xattrs: bool,
+    #[clap(long, value_parser, default_value_t = 0, conflicts_with_all = &["image", "rootfs-dir"])]
+    /// Drop this many leading components from the paths of the archive, like
+    /// `tar --strip-components`, for archives with the rootfs inside of a directory
+    pub strip_components: usize,
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    /// Size of the buffer the archives are read with, like 256K or 4M [default: 1M]. Larger
+    /// buffers use more memory but can decompress faster
+    pub decompress_buffer: Option<usize>,
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    /// Stop and remove the toolbox if the archives are larger than this once uncompressed,
+    /// like 5G, to guard against decompression bombs
+    pub max_size: Option<usize>,
+    #[clap(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+    /// Stop and remove the toolbox if the archives have more entries than this
+    /// [default: 10000000]
+    pub max_entries: Option<u64>,
+    #[clap(long, value_parser = clap::value_parser!(u32).range(10..=31))]
+    /// Largest window of a zstd archive, as a power of two [default: 27]. Lower values cap the
+    /// memory of the decoder, higher ones are needed by archives made with `zstd --long`
+    pub zstd_window_log: Option<u32>,
+    #[clap(long, value_parser)]
+    /// Resolve the whiteouts of the image layers, so only the merged rootfs remains
+    pub flatten: bool,
+    #[clap(long, value_parser, requires = "flatten")]
+    /// Also write the merged rootfs as a tarball, compressed depending on the extension
+    pub flatten_output: Option<PathBuf>,
+    #[clap(long, value_parser, conflicts_with = "post-create")]
+    /// Store the rootfs as a read-only squashfs image (needs mksquashfs, and squashfuse to enter it)
+    pub squashfs: bool,
+    #[clap(long, value_parser)]
+    /// Unpack the image even if the root directory of the toolbox is not empty. The
+    /// resolv.conf, hosts and hostname files already there are still kept, see --force-etc
+    pub force: bool,
+    #[clap(long, value_parser)]
+    /// Skip the files unpacked by a previous create of the toolbox that was interrupted,
+    /// implies --force
+    pub resume: bool,
+    #[clap(long, value_parser, conflicts_with_all = &["force", "resume", "ensure", "squashfs", "base"])]
+    /// Create the new rootfs of an existing toolbox next to the old one and swap them once it
+    /// is complete, the old toolbox is left untouched if it fails
+    pub replace: bool,
+    #[clap(
+        long,
+        value_parser,
+        env = "SOURCE_DATE_EPOCH",
+        alias = "source-date-epoch"
+    )]
+    /// Modification time, in seconds since the epoch, given to every unpacked file instead of
+    /// the one of the image, to get reproducible rootfs
+    pub mtime: Option<u64>,
+    #[clap(long, visible_alias = "timeout", value_parser = parse_duration)]
+    /// Abort if downloading and unpacking the image take longer, like 90, 30s, 5m or 1h. The
+    /// partial rootfs is removed, unless --resume is given, and the exit code is 124
+    pub deadline: Option<Duration>,
+    #[clap(long, value_parser, value_name = "DIR")]
+    /// Keep what is needed to debug this create in an empty or new directory: the tarball
+    /// exported by the engine, the output of every engine command and the resulting config.
+    /// They may contain sensitive data from the image
+    pub debug_artifacts: Option<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Do nothing if the toolbox already exists with the same source and shell, with --force
+    /// it is re-created if they differ
+    pub ensure: bool,
+    #[clap(long, value_parser)]
+    /// Directory created inside of the rootfs besides the default ones, can be repeated
+    pub mkdir: Vec<String>,
+    #[clap(long, value_parser = parse_copy_up)]
+    /// File of the host copied into the rootfs, like ~/.gitconfig, can be repeated. Files of
+    /// the home of the host go to the home of the toolbox, the rest to the same path
+    pub copy_up: Vec<PathBuf>,
+    #[clap(long, value_name = "INSIDE:OUTSIDE:LEN", value_parser = parse_id_map)]
+    /// Mapping of users used when entering the toolbox instead of the default ones, can be
+    /// repeated. The ids outside have to be the current one or subordinate ones
+    pub uid_map: Vec<IdMap>,
+    #[clap(long, value_name = "INSIDE:OUTSIDE:LEN", value_parser = parse_id_map, requires = "uid-map")]
+    /// Mapping of groups used when entering the toolbox, the ones of --uid-map by default
+    pub gid_map: Vec<IdMap>,
+    #[clap(long, value_parser)]
+    /// Do not create /proc, /sys and /dev in the rootfs, /host is always needed to enter it
+    pub no_default_dirs: bool,
+    #[clap(long, value_parser)]
+    /// Unpack what the image has inside of /proc, /sys, /dev and /host, like device nodes,
+    /// instead of leaving them as empty mountpoints
+    pub keep_image_dev: bool,
+    #[clap(long, value_parser)]
+    /// Overwrite the resolv.conf, hosts and hostname files of the image
+    pub force_etc: bool,
+    #[clap(long, value_parser)]
+    /// Nameserver written to the resolv.conf of the toolbox instead of using the one of the host, can be repeated
+    pub dns: Vec<IpAddr>,
+    #[clap(long, value_parser, default_value = "bind", conflicts_with = "dns")]
+    /// How the resolv.conf of the host is used inside of the toolbox
+    pub resolv: Resolv,
+    #[clap(long, value_parser, conflicts_with_all = &["dns", "resolv"])]
+    /// Keep the resolv.conf of the image, the same as --resolv none
+    pub no_resolv: bool,
+    #[clap(long = "post-create-cmd", value_parser)]
+    /// Command to run inside of the toolbox after it is created, can be repeated
+    pub post_create: Vec<String>,
+    #[clap(long, value_parser)]
+    /// Do not fail the creation if a post-create command fails
+    pub ignore_hook_errors: bool,
+    #[clap(long, value_parser)]
+    /// Flush the rootfs and the config to the disk before returning, so a crash right after
+    /// the creation can not leave them partially written
+    pub sync: bool,
+}
+
+/// Ways of setting up the resolv.conf of a toolbox, unless the nameservers are given
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum Resolv {
+    /// Bind mount the one of the host when entering the toolbox, so it is always up to date
+    #[default]
+    Bind,
+    /// Copy the one of the host while creating the toolbox
+    Copy,
+    /// Keep the one of the image
+    None,
+}
+
+/// Parses a size in bytes, with an optional K, M or G suffix in any case for powers of 1024
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => 0,
+    };
+    match number.parse::<usize>() {
+        Ok(number) if number > 0 && scale > 0 => number
+            .checked_mul(scale)
+            .ok_or_else(|| format!("{size} is too large")),
+        _ => Err(format!("{size} is not a valid size, like 256K or 4M")),
+    }
+}
+
+/// Parses an annotation given as `key=value`, like a label
+fn parse_annotation(annotation: &str) -> Result<(String, String), String> {
+    parse_label(annotation).map_err(|_| format!("{annotation} is not an annotation like key=value"))
+}
+
+/// Parses a label given as `key=value`, the value may be empty
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => Err(format!("{label} is not a label like key=value")),
+    }
 }
 
-/// OCI engine to extract the rootfs (docker or podman)
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
-pub enum Engine {
-    Docker,
-    Podman,
+/// Parses the path of a file of the host, made absolute so it can be mapped into the rootfs
+fn parse_copy_up(file: &str) -> Result<PathBuf, String> {
+    let path = std::path::absolute(file).map_err(|e| format!("{file} is not valid: {e}"))?;
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(format!("{file} is not a file"))
+    }
+}
+
+fn parse_env(env: &str) -> Result<(String, String), String> {
+    match env.split_once('=') {
+        Some((key, value)) if is_env_name(key) => Ok((key.into(), value.into())),
+        _ => Err(format!(
+            "{env} is not an environment variable like KEY=VALUE"
+        )),
+    }
+}
+
+impl Create {
+    /// Arguments of the shell given with either --shell-args or --shell-arg
+    fn shell_args(&self) -> Option<Vec<String>> {
+        match (&self.shell_args, self.shell_arg.is_empty()) {
+            (Some(ShellArgs(args)), _) => Some(args.clone()),
+            (None, false) => Some(self.shell_arg.clone()),
+            (None, true) => None,
+        }
+    }
+
+    fn resolv(&self) -> Resolv {
+        if self.no_resolv {
+            Resolv::None
+        } else {
+            self.resolv
+        }
+    }
+}
+
+/// Kind of source the rootfs has been extracted from
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceKind {
+    Tarball,
+    Stdin,
+    Zip,
+    Image,
+    SavedImage,
+    Base,
+    Directory,
+}
+
+/// Summary of the extraction of the rootfs of a new toolbox
+#[derive(Debug, Serialize)]
+pub struct CreateReport {
+    pub source: SourceKind,
+    #[serde(flatten)]
+    pub stats: UnpackStats,
+    #[serde(serialize_with = "as_secs")]
+    pub elapsed: Duration,
+}
+
+impl CreateReport {
+    fn summary(&self, name: &str) -> String {
+        let source = match self.source {
+            SourceKind::Tarball => "a tarball",
+            SourceKind::Stdin => "stdin",
+            SourceKind::Zip => "a zip file",
+            SourceKind::Image => "an image",
+            SourceKind::SavedImage => "a saved image",
+            SourceKind::Base => "a base toolbox",
+            SourceKind::Directory => "a directory",
+        };
+        let source = match &self.stats.comment {
+            Some(comment) => format!("{source} ({comment})"),
+            None => source.to_string(),
+        };
+        let mut summary = format!(
+            "Created {name} from {source}: {} entries, {:.1} MiB in {:.1}s",
+            self.stats.entries,
+            self.stats.bytes as f64 / (1024.0 * 1024.0),
+            self.elapsed.as_secs_f64()
+        );
+        if !self.stats.skipped.is_empty() {
+            summary.push_str(&format!(", {} skipped", self.stats.skipped.len()));
+        }
+        summary
+    }
+}
+
+fn as_secs<S: serde::Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(elapsed.as_secs_f64())
 }
 
 pub fn create(args: Create) -> eyre::Result<()> {
+    let verbosity = verbosity(args.quiet, args.silent, args.output);
+    let (name, output) = (args.name.clone(), args.output);
+    let spinner = Spinner::new(verbosity);
+    let report = match create_with(args, &spinner)? {
+        Some(report) => report,
+        None => return Ok(()),
+    };
+    for path in &report.stats.skipped {
+        debug(format_args!("skipped {}", path.display()));
+    }
+    match output {
+        Output::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        Output::Text if verbosity.shows_result() => println!("{}", report.summary(&name)),
+        Output::Text => {}
+    }
+    Ok(())
+}
+
+/// Same as [`create`] but reporting the progress to the given sink instead of a spinner. The
+/// report is not returned if `--ensure` found an existing toolbox.
+pub fn create_with(
+    args: Create,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<Option<CreateReport>> {
+    if args.replace {
+        return replace(args, progress).map(Some);
+    }
+    if args.ensure {
+        if let Ok(existing) = Config::read(&args.name) {
+            match mismatch(&args, &existing) {
+                None => return Ok(None),
+                Some(reason) if args.force => {
+                    debug(format_args!("re-creating {} because {reason}", args.name));
+                    remove_one(args.name.clone())?;
+                }
+                Some(reason) => eyre::bail!(
+                    "The toolbox {} already exists but {reason}, use --force to re-create it",
+                    args.name
+                ),
+            }
+        }
+    }
+    if let Some(dir) = &args.rootfs_dir {
+        // Anything else, like a home directory, would be copied into the storage root by mistake
+        eyre::ensure!(
+            ["bin", "usr"].iter().any(|top| dir.join(top).exists()),
+            "{} does not look like a rootfs, it has neither /bin nor /usr",
+            dir.display()
+        );
+    }
+    if let Some(dir) = &args.debug_artifacts {
+        eyre::ensure!(
+            is_missing_or_empty(dir)?,
+            "{} is not empty, the debugging artifacts need a new directory",
+            dir.display()
+        );
+        create_dir_all(dir).wrap_err("Could not create the directory of the artifacts")?;
+        progress.on_warning(&format!(
+            "Debugging artifacts are kept in {}, they may contain sensitive data from the image",
+            dir.display()
+        ));
+    }
+    let user = current_username();
+    let gid_map = if args.gid_map.is_empty() {
+        &args.uid_map
+    } else {
+        &args.gid_map
+    };
+    for (maps, ids, id) in [
+        (
+            &args.uid_map,
+            SubordinateIds::Uids,
+            users::get_current_uid(),
+        ),
+        (gid_map, SubordinateIds::Gids, users::get_current_gid()),
+    ] {
+        // A missing file gives no subordinate ids, which is what the check reports
+        let contents = std::fs::read_to_string(ids.path()).unwrap_or_default();
+        check_id_maps(maps, &user, id, &contents)
+            .wrap_err(format!("Invalid mappings, see {}", ids.path()))?;
+    }
     let mut config = Config::new(&args.name)?;
     let new_root = &config.image;
+    // An empty directory is accepted because it may be a mountpoint prepared for the rootfs
     eyre::ensure!(
-        !Path::new(new_root).exists(),
-        "There is already an image with that name"
+        args.force || args.resume || is_missing_or_empty(Path::new(new_root))?,
+        "There is already an image with that name, use --force to unpack over it"
     );
+    // Only what this create made is removed if it stops early
+    let made = if meta_path(&args.name)?.exists() {
+        Made::Nothing
+    } else if Path::new(new_root).exists() {
+        Made::Config
+    } else {
+        Made::Toolbox
+    };
     create_dir_all(new_root).wrap_err("Could not create the new root directory")?;
-    if let Some(sh) = args.shell {
-        config.shell = sh;
+    if let Some(sh) = &args.shell {
+        config.shell = sh.clone();
+    }
+    config.shell_args = args.shell_args().unwrap_or_default();
+    config.post_create = args.post_create.clone();
+    config.source = source(&args);
+    config.dns = args.dns.clone();
+    config.extra_dirs = args.mkdir.clone();
+    config.labels = args.labels.iter().cloned().collect();
+    config.annotations = args.annotations.iter().cloned().collect();
+    for file in &args.env_file {
+        let contents = std::fs::read_to_string(file).wrap_err(format!(
+            "Could not read the environment file {}",
+            file.display()
+        ))?;
+        let env = parse_env_file(&contents).wrap_err(format!("Invalid {}", file.display()))?;
+        config.env.extend(env);
     }
+    config.env.extend(args.envs.iter().cloned());
+    config.uid_map = args.uid_map.clone();
+    config.gid_map = args.gid_map.clone();
+    if !args.dns.is_empty() || args.resolv() != Resolv::Bind {
+        config.remove_mount("/etc/resolv.conf");
+    }
+    config.user = Some(match &args.user {
+        Some(user) => user.clone(),
+        None => current_username(),
+    });
     config.write(&args.name)?;
 
-    if let Some(tar) = args.tar {
-        setup_new_root(new_root, tar, args.quiet)
-    } else if let Some(oci) = args.image {
+    let verbosity = verbosity(args.quiet, args.silent, args.output);
+    let hooks = Hooks::spawn(
+        &args.name,
+        &config.post_create,
+        args.ignore_hook_errors,
+        verbosity,
+    )?;
+    let deadline = Deadline::after(args.deadline);
+    let result = populate_root(&args, &mut config, deadline, progress);
+    progress.on_finish();
+    if let Some(dir) = &args.debug_artifacts {
+        // Also after a failure, since the config shows how far it got, which is not hidden
+        // by an error of the artifacts
+        let kept = config.write(&args.name).and_then(|()| {
+            std::fs::copy(meta_path(&args.name)?, dir.join("config.toml"))
+                .wrap_err("Could not keep the config in the debugging artifacts")
+        });
+        if let Err(e) = kept {
+            progress.on_warning(&format!("{e}"));
+        }
+    }
+    let report = match result {
+        // A resumed create can pick up the partial rootfs, otherwise it is not usable
+        Err(e) if (is_timeout(&e) || is_too_large(&e)) && !args.resume => {
+            if let Err(cleanup) = made.remove(&args.name, &config.image, progress) {
+                progress.on_warning(&format!("Could not remove the partial rootfs: {cleanup}"));
+            }
+            return Err(e);
+        }
+        result => result?,
+    };
+    // The shell may have changed after looking into the rootfs
+    config.write(&args.name)?;
+    hooks.run()?;
+    if args.sync {
+        sync_toolbox(&args.name, &config)?;
+    }
+    Ok(Some(report))
+}
+
+/// Creates the toolbox under a hidden name and then exchanges its rootfs with the one of the
+/// existing toolbox with `renameat2`, so entering it never finds a partial rootfs. The config
+/// is moved over the old one afterwards and the old rootfs is removed last.
+fn replace(args: Create, progress: &dyn ProgressSink) -> eyre::Result<CreateReport> {
+    let name = args.name.clone();
+    let old = Config::read(&name).wrap_err(format!("There is no toolbox {name} to replace"))?;
+    eyre::ensure!(
+        old.squashfs.is_none() && old.overlay.is_none(),
+        "{name} can not be replaced, its rootfs is not a plain directory"
+    );
+    let dependents = dependents(&name)?;
+    eyre::ensure!(
+        dependents.is_empty(),
+        "{name} is the base of {}, its rootfs can not be replaced under them",
+        dependents.join(", ")
+    );
+    let staging = format!(".{name}-replace");
+    // Left behind by a replace that was killed
+    if Config::read(&staging).is_ok() {
+        remove_one(staging.clone())?;
+    }
+    let created = create_with(
+        Create {
+            name: staging.clone(),
+            replace: false,
+            ..args
+        },
+        progress,
+    );
+    // A create that timed out or was too large has already removed what it made
+    let leftover = meta_path(&staging)?.exists();
+    let report = match created {
+        Ok(report) => report.expect("only --ensure skips the create"),
+        Err(e) if !leftover => return Err(e),
+        Err(e) => {
+            if let Err(cleanup) = remove_one(staging) {
+                progress.on_warning(&format!("Could not remove the new rootfs: {cleanup}"));
+            }
+            return Err(e);
+        }
+    };
+    let mut config = Config::read(&staging)?;
+    if config.hostname == staging {
+        config.hostname = name.clone();
+        let etc_hostname = Path::new(&config.image).join("etc/hostname");
+        if std::fs::read_to_string(&etc_hostname).is_ok_and(|h| h.trim_end() == staging) {
+            std::fs::write(&etc_hostname, format!("{name}\n"))
+                .wrap_err("Could not write the hostname of the new rootfs")?;
+        }
+    }
+    let (new_root, old_root) = (config.image.clone(), old.image);
+    renameat_with(CWD, &new_root, CWD, &old_root, RenameFlags::EXCHANGE).wrap_err(format!(
+        "Could not swap the rootfs of {name}, the new one is kept as {staging}"
+    ))?;
+    // The config of the staging toolbox describes the new rootfs at its final path now, and
+    // renaming it over the old one replaces the config at once too
+    config.image = old_root;
+    config.write(&staging)?;
+    std::fs::rename(meta_path(&staging)?, meta_path(&name)?)
+        .wrap_err(format!("Could not replace the config of {name}"))?;
+    make_writable(Path::new(&new_root));
+    if let Err(e) = std::fs::remove_dir_all(&new_root) {
+        progress.on_warning(&format!(
+            "Could not remove the old rootfs in {new_root}: {e}"
+        ));
+    }
+    Ok(report)
+}
+
+/// Flushes the filesystems with the rootfs and the config with `syncfs`, which writes the
+/// whole tree at once instead of each of its files
+fn sync_toolbox(name: &str, config: &Config) -> eyre::Result<()> {
+    let mut paths = vec![PathBuf::from(&config.image), meta_path(name)?];
+    paths.extend(config.squashfs.iter().map(PathBuf::from));
+    paths.extend(
+        config
+            .overlay
+            .iter()
+            .map(|overlay| PathBuf::from(&overlay.upper)),
+    );
+    for path in paths {
+        File::open(&path)
+            .and_then(|file| Ok(rustix::fs::syncfs(file)?))
+            .wrap_err(format!("Could not sync {} to the disk", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Source of the rootfs, the url of the image, the paths of the tarballs or of the directory,
+/// or the base
+fn source(args: &Create) -> Option<String> {
+    if let Some(base) = &args.base {
+        return Some(format!("toolbox {base}"));
+    }
+    if let Some(dir) = args.rootfs_dir.as_ref().or(args.docker_archive.as_ref()) {
+        return Some(dir.display().to_string());
+    }
+    if args.tar.is_empty() {
+        return args.image.clone();
+    }
+    let tars: Vec<_> = args
+        .tar
+        .iter()
+        .map(|tar| tar.display().to_string())
+        .collect();
+    Some(tars.join(", "))
+}
+
+/// Describes how the existing toolbox differs from the requested one, if it does
+fn mismatch(args: &Create, existing: &Config) -> Option<String> {
+    let requested = source(args);
+    if requested != existing.source {
+        return Some(format!(
+            "it was created from {} instead of {}",
+            existing.source.as_deref().unwrap_or("an unknown source"),
+            requested.as_deref().unwrap_or("nothing")
+        ));
+    }
+    match &args.shell {
+        Some(shell) if *shell != existing.shell => Some(format!(
+            "its shell is {} instead of {shell}",
+            existing.shell
+        )),
+        _ => None,
+    }
+}
+
+fn populate_root(
+    args: &Create,
+    config: &mut Config,
+    deadline: Deadline,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<CreateReport> {
+    if !args.tar.is_empty() {
+        eyre::ensure!(
+            args.tar.iter().filter(|tar| *tar == Path::new("-")).count() <= 1,
+            "Only one tarball can be read from stdin"
+        );
+        let mut downloads = Vec::new();
+        let mut tars = Vec::new();
+        for (i, tar) in args.tar.iter().enumerate() {
+            if !is_url(tar) {
+                tars.push(tar.clone());
+                continue;
+            }
+            // Unpacked like a local archive, so zip files and saved images work the same. It
+            // is kept next to the rootfs, where other users can not put a file before it.
+            let path =
+                Path::new(&config.image).with_file_name(format!(".{}-download-{i}", args.name));
+            let downloaded = prepare_download(&path)
+                .and_then(|()| download(&tar.to_string_lossy(), &path, deadline, progress));
+            downloads.push(path.clone());
+            if let Err(e) = downloaded {
+                downloads
+                    .iter()
+                    .for_each(|path| drop(std::fs::remove_file(path)));
+                return Err(e);
+            }
+            tars.push(path);
+        }
+        let report = setup_new_root(config, args, &tars, deadline, progress);
+        for path in downloads {
+            let _ = std::fs::remove_file(path);
+        }
+        report
+    } else if let Some(oci) = &args.image {
         // podman export $(podman create alpine) --output=alpine.tar
         let tar_file = format!("/tmp/unbox-{}-image.tar", args.name);
-        match args
+        let quiet = !verbosity(args.quiet, args.silent, args.output).shows_progress();
+        let engine = args
             .engine
-            .ok_or_else(|| eyre::eyre!("A valid engine has not been provided"))?
-        {
-            Engine::Docker => get_image("docker", &oci, &tar_file, args.quiet)?,
-            Engine::Podman => get_image("podman", &oci, &tar_file, args.quiet)?,
+            .ok_or_else(|| eyre::eyre!("A valid engine has not been provided"))?;
+        let engine = EngineCommands {
+            deadline,
+            artifacts: args.debug_artifacts.clone(),
+            ..EngineCommands::new(engine, &args.custom_engine, quiet, args.save, args.local)?
+        };
+        // Scripts get an error instead of a prompt if the name is ambiguous
+        let interactive =
+            !quiet && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+        let tag = match &args.default_tag {
+            Some(tag) => tag.clone(),
+            None => GlobalConfig::read()?
+                .default_tag
+                .unwrap_or_else(|| DEFAULT_TAG.into()),
         };
-        setup_new_root(new_root, tar_file.into(), args.quiet)
+        let oci = &resolve_image(&engine, oci, &tag, interactive)?;
+        debug(format_args!("using the image {oci}"));
+        if image_digest(oci)?.is_none() {
+            progress.on_warning(&format!(
+                "{oci} is not pinned to a digest, the toolbox may not be reproducible"
+            ));
+        }
+        get_image(&engine, oci, &tar_file, progress)?;
+        config.digest = match stored_digest(&engine, oci) {
+            Ok(digest) => digest,
+            Err(e) => {
+                progress.on_warning(&format!("Could not read the digest of the image: {e}"));
+                None
+            }
+        };
+        if let Some(dir) = &args.debug_artifacts {
+            std::fs::copy(&tar_file, dir.join("image.tar"))
+                .wrap_err("Could not keep the tarball in the debugging artifacts")?;
+        }
+        if args.import_labels {
+            for (key, value) in image_labels(&engine, oci)? {
+                config.labels.entry(key).or_insert(value);
+            }
+        }
+        // The image is still kept by the engine after removing the container
+        config.image_user = match image_user(&engine, oci) {
+            Ok(user) => user,
+            Err(e) => {
+                progress.on_warning(&format!("Could not read the user of the image: {e}"));
+                None
+            }
+        };
+        setup_new_root(config, args, &[tar_file.into()], deadline, progress)
+    } else if let Some(archive) = &args.docker_archive {
+        setup_new_root(
+            config,
+            args,
+            std::slice::from_ref(archive),
+            deadline,
+            progress,
+        )
+    } else if let Some(base) = &args.base {
+        overlay_root(config, args, base, progress)
+    } else if args.rootfs_dir.is_some() {
+        setup_new_root(config, args, &[], deadline, progress)
     } else {
         Err(eyre::eyre!(
-            "No tar archive or valid OCI arguments have been provided"
+            "No tar archive, saved image, rootfs directory or valid OCI arguments have been provided"
         ))
     }
 }
 
-struct Spinner(Option<ProgressBar>);
-
-impl Spinner {
-    fn new(quiet: bool) -> Self {
-        use indicatif::ProgressStyle;
-
-        if quiet {
-            Spinner(None)
+/// Unpacks the archives one after the other into the root of the toolbox, later ones
+/// overwriting the files of the previous ones, or copies the directory given with
+/// `--rootfs-dir`, and sets up the files needed by unbox
+fn setup_new_root(
+    config: &mut Config,
+    args: &Create,
+    archives: &[PathBuf],
+    deadline: Deadline,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<CreateReport> {
+    let start = Instant::now();
+    let new_root = config.image.clone();
+    let flags = CloneFlags::CLONE_NEWUSER;
+    // The files are unpacked with the mappings used to enter the toolbox, custom ones too, so
+    // they keep their owners, and the created user can own its home
+    let mut ns = start_toolbox(flags, config)?;
+    ns.wait()?;
+    let mut source = None;
+    let mut stats = UnpackStats::default();
+    let options = UnpackOptions {
+        deadline,
+        ..args.into()
+    };
+    if let Some(dir) = &args.rootfs_dir {
+        progress.on_phase("Copying the rootfs");
+        let mode = if args.link {
+            CopyMode::Link
+        } else {
+            CopyMode::Copy
+        };
+        let options = CopyOptions {
+            mode,
+            dereference: args.dereference,
+        };
+        // Hashed before copying it, since it is what the toolbox is created from
+        let kind = if args.deep_hash {
+            TreeHash::Contents
         } else {
-            let style = ProgressStyle::default_spinner()
-                .template("{msg} {spinner}")
-                .expect("valid template");
-            let spinner = ProgressBar::new_spinner().with_style(style);
-            spinner.enable_steady_tick(Duration::from_millis(50));
-            Spinner(Some(spinner))
+            TreeHash::Metadata
+        };
+        config.source_hash = Some(hash_tree(dir, kind)?);
+        stats = copy_tree(dir, Path::new(&new_root), options, progress)?;
+        source = Some(SourceKind::Directory);
+    }
+    for archive in archives {
+        let options = options.after(&stats);
+        let (kind, unpacked) = unpack_archive(args, archive, &new_root, &options, progress)?;
+        // The kind of the first archive is reported, since it is the base of the rootfs
+        source.get_or_insert(kind);
+        stats.add(unpacked);
+    }
+    let source = source.expect("there is at least one archive or a directory");
+    if args.resume {
+        remove_resume_index(Path::new(&new_root));
+    }
+    // The layers of a saved image always get their whiteouts applied
+    if args.flatten || args.save || args.docker_archive.is_some() {
+        let leftovers = remove_leftover_whiteouts(Path::new(&new_root))?;
+        debug(format_args!("removed {leftovers} leftover whiteouts"));
+        config.flattened = true;
+    }
+    deadline.check()?;
+    progress.on_phase("Setting up files and directories");
+    let root = Path::new(&new_root);
+    // The old root is moved to /host when pivoting, so it can not be skipped
+    create_dirs(root, &["host"])?;
+    if !args.no_default_dirs {
+        create_dirs(root, &DEFAULT_DIRS)?;
+    }
+    create_dirs(root, &config.extra_dirs)?;
+    // resolv.conf and hosts are only needed as mountpoints, unless the nameservers are given
+    // or the resolv.conf of the host is copied
+    if !config.dns.is_empty() {
+        let resolv: String = config
+            .dns
+            .iter()
+            .map(|ip| format!("nameserver {ip}\n"))
+            .collect();
+        create_etc_file(root, "etc/resolv.conf", &resolv, true)?;
+    } else {
+        match args.resolv() {
+            Resolv::Bind => create_etc_file(root, "etc/resolv.conf", "", args.force_etc)?,
+            Resolv::Copy => {
+                let host = std::fs::read_to_string("/etc/resolv.conf")
+                    .wrap_err("Could not read the resolv.conf of the host")?;
+                create_etc_file(root, "etc/resolv.conf", &host, true)?;
+            }
+            Resolv::None => {}
         }
     }
+    create_etc_file(root, "etc/hosts", "", args.force_etc)?;
+    let hostname = format!("{}\n", config.hostname);
+    create_etc_file(root, "etc/hostname", &hostname, args.force_etc)?;
+    if args.shell.is_none() {
+        probe_default_shell(root, config, progress)?;
+    }
+    config.distro = os_release::read(root);
+    if let Some(user) = &config.user {
+        create_user(root, user, &config.shell)?;
+    }
+    copy_up(root, &config.home, &args.copy_up)?;
+    if let Some(output) = &args.flatten_output {
+        progress.on_phase("Writing the merged rootfs");
+        write_tar(
+            root,
+            output,
+            Compression::from_extension(output),
+            None,
+            None,
+        )?;
+    }
+    if args.squashfs {
+        progress.on_phase("Compressing the rootfs");
+        config.squashfs = Some(squash(root, config)?);
+    }
+    Ok(CreateReport {
+        source,
+        stats,
+        elapsed: start.elapsed(),
+    })
+}
 
-    fn message(&self, msg: &'static str) {
-        if let Some(spinner) = &self.0 {
-            spinner.set_message(msg);
-        }
+/// Sets up a toolbox whose rootfs is an overlay over the one of `base`, which is mounted when
+/// entering it. The user and the files needed by unbox are already in the rootfs of the base.
+fn overlay_root(
+    config: &mut Config,
+    args: &Create,
+    base: &str,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<CreateReport> {
+    let start = Instant::now();
+    progress.on_phase("Setting up the overlay");
+    let lower =
+        Config::read(base).wrap_err(format!("Could not get the configuration of {base}"))?;
+    eyre::ensure!(
+        lower.squashfs.is_none() && lower.overlay.is_none(),
+        "{base} can not be a base, its rootfs is not a plain directory"
+    );
+    let overlay = Overlay::new(&args.name, base, &lower.image)?;
+    for layer in [&overlay.upper, &overlay.work] {
+        create_dir_all(layer).wrap_err(format!("Could not create the overlay layer {layer}"))?;
+    }
+    if args.shell.is_none() {
+        config.shell = lower.shell;
+    }
+    if args.shell_args().is_none() {
+        config.shell_args = lower.shell_args;
     }
+    config.user = lower.user;
+    config.image_user = lower.image_user;
+    config.login = lower.login;
+    config.distro = lower.distro;
+    config.overlay = Some(overlay);
+    Ok(CreateReport {
+        source: SourceKind::Base,
+        stats: UnpackStats::default(),
+        elapsed: start.elapsed(),
+    })
+}
 
-    // TODO: Drop
-    fn clear(&self) {
-        if let Some(spinner) = &self.0 {
-            spinner.finish_and_clear();
+/// Compresses the rootfs into a squashfs image next to it, leaving its directory empty to be
+/// used as the mountpoint of the image
+fn squash(root: &Path, config: &Config) -> eyre::Result<String> {
+    // Nothing can be created in the image once it is mounted, so every mountpoint has to exist
+    for mount in config.mounts() {
+        let target = resolve_in_root(root, mount?.target)?;
+        if symlink_metadata(&target).is_err() {
+            create_dir_all(&target)
+                .wrap_err(format!("Could not create the mountpoint {target:?}"))?;
         }
     }
+    let image = format!("{}.squashfs", config.image);
+    let output = std::process::Command::new("mksquashfs")
+        .arg(root)
+        .arg(&image)
+        .args(["-noappend", "-quiet"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .wrap_err("Could not execute mksquashfs, is it installed?")?;
+    eyre::ensure!(
+        output.status.success(),
+        "mksquashfs failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    std::fs::remove_dir_all(root).wrap_err("Could not remove the unpacked rootfs")?;
+    create_dir_all(root).wrap_err("Could not create the mountpoint of the squashfs image")?;
+    Ok(image)
 }
 
-fn setup_new_root(new_root: &str, tar: PathBuf, quiet: bool) -> eyre::Result<()> {
-    let flags = CloneFlags::CLONE_NEWUSER;
-    let uid = users::get_current_uid().to_string();
-    let mappings = &[Mapping {
-        inside: "0",
-        outside: &uid,
-        len: "1",
-    }];
-    let mut ns = Namespace::start(flags, mappings)?;
-    ns.wait();
-    let spinner = Spinner::new(quiet);
-    spinner.message("Unpacking tar file");
-    unpack_tar(tar, new_root)?;
-    spinner.message("Setting up files and directories");
-    let dirs = ["host", "proc", "sys", "dev"];
-    create_dirs(new_root, &dirs)?;
-    File::create(format!("{new_root}/etc/resolv.conf")).expect("path exists and is writable");
-    // TODO: create user
-    spinner.clear();
+/// Prints one line per layer of the saved image, numbered in the order they are applied
+fn show_layers(tar: &Path, progress: &dyn ProgressSink) -> eyre::Result<()> {
+    let layers = saved_layers(tar)?;
+    progress.on_info(&format!("The image has {} layers:", layers.len()));
+    for (i, layer) in layers.iter().enumerate() {
+        let name = match &layer.digest {
+            Some(digest) => digest.clone(),
+            None => layer.path.display().to_string(),
+        };
+        let compression = format!("{:?}", layer.compression).to_lowercase();
+        progress.on_info(&format!(
+            "{:>4}. {name} {:.1} MiB {compression}",
+            i + 1,
+            layer.size as f64 / (1024.0 * 1024.0)
+        ));
+    }
     Ok(())
 }
 
-fn unpack_tar(tar: PathBuf, new_root: &str) -> eyre::Result<()> {
-    let archive = File::open(tar).wrap_err("Could not open the tar file")?;
-    let mut tar = Archive::new(archive);
-    let mut dirs = Vec::new();
-    for entry in tar.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
-        if path.is_dir() {
-            dirs.push(entry);
-        } else {
-            entry
-                .unpack_in(new_root)
-                .wrap_err("Could not unpack entry")?;
+fn unpack_archive(
+    args: &Create,
+    tar: &Path,
+    new_root: &str,
+    options: &UnpackOptions,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<(SourceKind, UnpackStats)> {
+    if args.save || args.docker_archive.is_some() {
+        if args.show_layers {
+            show_layers(tar, progress)?;
         }
+        let stats = unpack_saved(tar, new_root, options, progress)?;
+        Ok((SourceKind::SavedImage, stats))
+    } else if is_zip(tar) {
+        progress.on_phase("Unpacking zip file");
+        let stats = unpack_zip(tar, new_root, options, progress)?;
+        Ok((SourceKind::Zip, stats))
+    } else {
+        progress.on_phase("Unpacking tar file");
+        let (archive, total) = open_tar(tar, &options.decoder)?;
+        let stats = unpack_tar(archive, total, new_root, options, progress)?;
+        let source = match (args.tar.is_empty(), &args.image) {
+            (true, Some(_)) => SourceKind::Image,
+            _ if tar == Path::new("-") => SourceKind::Stdin,
+            _ => SourceKind::Tarball,
+        };
+        Ok((source, stats))
     }
-    dirs.sort_unstable_by_key(|b| std::cmp::Reverse(b.path_bytes().len()));
-    for mut dir in dirs {
-        dir.unpack_in(new_root)
-            .wrap_err("Could not unpack a directory")?;
+}
+
+/// Replaces the default shell with the first one of a list of candidates if the image does not have it
+pub fn probe_default_shell(
+    root: &Path,
+    config: &mut Config,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<()> {
+    const SHELLS: [&str; 4] = ["/bin/bash", "/bin/ash", "/bin/sh", "/usr/bin/fish"];
+
+    if resolve_in_root(root, &config.shell)?.is_file() {
+        return Ok(());
     }
+    for shell in SHELLS {
+        if resolve_in_root(root, shell)?.is_file() {
+            progress.on_warning(&format!(
+                "{} does not exist in the toolbox, using {shell} as the default shell",
+                config.shell
+            ));
+            config.shell = shell.into();
+            return Ok(());
+        }
+    }
+    progress.on_warning(&format!(
+        "{} does not exist in the toolbox and no other shell could be found",
+        config.shell
+    ));
     Ok(())
 }
 
-fn get_image(engine: &str, url: &str, tar_file: &str, quiet: bool) -> eyre::Result<()> {
-    let spinner = Spinner::new(quiet);
-    spinner.message("Downloading image");
-    let cid = spawn(engine, &["create", url])?.stdout;
-    let cid = std::str::from_utf8(&cid)
-        .expect("Podman/Docker gives valid utf8 output")
-        .trim();
-    spawn(engine, &["export", cid, "--output", tar_file])?;
-    spawn(engine, &["rm", cid])?;
-    spinner.clear();
+/// Copies the files of the host into the rootfs, the ones inside of the home of the host into
+/// `home`, creating the missing directories. Unlike a mount, the copies can be edited freely.
+pub fn copy_up(root: &Path, home: &str, files: &[PathBuf]) -> eyre::Result<()> {
+    let host_home = std::env::var_os("HOME").map(PathBuf::from);
+    for file in files {
+        let inside = match host_home.as_ref().and_then(|h| file.strip_prefix(h).ok()) {
+            Some(relative) => Path::new(home).join(relative),
+            None => file.clone(),
+        };
+        let target = resolve_in_root(root, &inside)?;
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)
+                .wrap_err(format!("Could not create the directory {parent:?}"))?;
+        }
+        std::fs::copy(file, &target)
+            .wrap_err(format!("Could not copy {file:?} into the rootfs"))?;
+    }
     Ok(())
 }
 
-fn spawn<S>(cmd: S, args: &[S]) -> eyre::Result<Output>
-where
-    S: AsRef<OsStr>,
-    S: Display,
-{
-    use std::process::Command;
-    Command::new(cmd)
-        .args(args)
-        .output()
-        .wrap_err("Could not execute the provided engine")
+pub fn current_username() -> String {
+    users::get_current_username()
+        .expect("the user still exits")
+        .into_string()
+        .expect("Username is valid UTF8")
+}
+
+/// Adds the user to the passwd and group files of the rootfs, creating minimal ones if needed,
+/// and creates its home directory owned by it. The user gets the first ids from 1000 that the
+/// rootfs does not use, which are mapped to subordinate ids of the host like every id but root.
+fn create_user(root: &Path, name: &str, shell: &str) -> eyre::Result<()> {
+    let home = format!("/home/{name}");
+    let uid = user::unused_id(root, "etc/passwd")?;
+    let gid = user::unused_id(root, "etc/group")?;
+    append_etc_entry(
+        root,
+        "etc/passwd",
+        "root:x:0:0:root:/root:/bin/sh",
+        name,
+        &format!("{name}:x:{uid}:{gid}:{name}:{home}:{shell}"),
+    )?;
+    append_etc_entry(
+        root,
+        "etc/group",
+        "root:x:0:",
+        name,
+        &format!("{name}:x:{gid}:"),
+    )?;
+    // The image may already have the user, with its own ids and home
+    let credentials = user::resolve(root, name)?;
+    let home = resolve_in_root(root, credentials.home.as_deref().unwrap_or(&home))?;
+    if symlink_metadata(&home).is_ok() {
+        return Ok(());
+    }
+    create_dir_all(&home).wrap_err(format!("Could not create the home directory {home:?}"))?;
+    std::os::unix::fs::chown(&home, Some(credentials.uid), Some(credentials.gid)).wrap_err(format!(
+        "Could not give the home directory {home:?} to {name}"
+    ))
+}
+
+/// Appends the entry to a colon separated file like `/etc/passwd`, unless there is already
+/// one with the same name
+fn append_etc_entry(
+    root: &Path,
+    path: &str,
+    default: &str,
+    name: &str,
+    entry: &str,
+) -> eyre::Result<()> {
+    use std::io::Write;
+
+    let path = resolve_in_root(root, path)?;
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => format!("{default}\n"),
+        Err(e) => return Err(e).wrap_err(format!("Could not read {path:?}")),
+    };
+    if content
+        .lines()
+        .any(|line| line.split(':').next() == Some(name))
+    {
+        return Ok(());
+    }
+    let mut content = content;
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(entry);
+    content.push('\n');
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).wrap_err(format!("Could not create the directory {parent:?}"))?;
+    }
+    let mut file = File::create(&path).wrap_err(format!("Could not write {path:?}"))?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+impl From<&Create> for UnpackOptions {
+    fn from(args: &Create) -> Self {
+        UnpackOptions {
+            no_setuid: args.no_setuid,
+            whiteouts: args.flatten,
+            resume: args.resume,
+            mtime: args.mtime,
+            xattrs: args.xattrs,
+            strip_components: args.strip_components,
+            skip_dirs: match (args.keep_image_dev, args.no_default_dirs) {
+                (true, _) => &[],
+                (false, true) => &["host"],
+                (false, false) => MOUNTPOINT_DIRS,
+            },
+            max_size: args.max_size.map(|size| size as u64),
+            max_entries: Some(args.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES)),
+            deadline: Deadline::default(),
+            decoder: DecoderOptions {
+                buffer: args.decompress_buffer.unwrap_or(READ_BUFFER_SIZE),
+                zstd_window_log: args.zstd_window_log,
+            },
+        }
+    }
 }
 
-fn create_dirs(root: &str, dirs: &[&str]) -> eyre::Result<()> {
+/// Mountpoints of the filesystems of the system created in every rootfs by default
+const DEFAULT_DIRS: [&str; 3] = ["proc", "sys", "dev"];
+
+/// Creates the directories inside of the rootfs, following its symlinks without leaving it
+pub fn create_dirs<S: AsRef<Path>>(root: &Path, dirs: &[S]) -> eyre::Result<()> {
     for dir in dirs {
-        create_dir_all(format!("{root}/{dir}")).expect("path exists and is writable");
+        let dir = resolve_in_root(root, dir.as_ref())?;
+        create_dir_all(&dir).wrap_err(format!("Could not create the directory {dir:?}"))?;
     }
     Ok(())
 }
+
+/// Checks that the directory does not exist yet or has no entries
+/// What a create made before unpacking, which is removed if it can not finish
+enum Made {
+    /// The config and the rootfs directory
+    Toolbox,
+    /// The config, the rootfs directory was already there, like a mountpoint prepared for it
+    Config,
+    /// Nothing, the archives were unpacked over an existing toolbox with --force
+    Nothing,
+}
+
+impl Made {
+    fn remove(&self, name: &str, root: &str, progress: &dyn ProgressSink) -> eyre::Result<()> {
+        match self {
+            Made::Toolbox => remove_one(name.to_string()),
+            Made::Config => {
+                make_writable(Path::new(root));
+                for entry in std::fs::read_dir(root).wrap_err("Could not read the rootfs")? {
+                    let path = entry.wrap_err("Could not read the rootfs")?.path();
+                    if symlink_metadata(&path).is_ok_and(|meta| meta.is_dir()) {
+                        std::fs::remove_dir_all(&path)
+                    } else {
+                        std::fs::remove_file(&path)
+                    }
+                    .wrap_err(format!("Could not remove {path:?}"))?;
+                }
+                std::fs::remove_file(meta_path(name)?).wrap_err("Could not remove the config")
+            }
+            Made::Nothing => {
+                progress.on_warning(&format!(
+                    "The rootfs of {name} is partly unpacked over its previous one, create it again"
+                ));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Creates the directory of a download and removes what an interrupted create left at its
+/// path, without following it if it is a link
+fn prepare_download(path: &Path) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).wrap_err(format!("Could not create {parent:?}"))?;
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(eyre::Report::new(e).wrap_err(format!("Could not remove {path:?}")))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_missing_or_empty(dir: &Path) -> eyre::Result<bool> {
+    match std::fs::read_dir(dir) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e).wrap_err(format!("Could not read the directory {dir:?}")),
+    }
+}
+
+/// Writes the file with the given contents, unless it already exists and `force` is not set
+/// to preserve the files of the image or the edits of the user
+fn create_etc_file(root: &Path, path: &str, contents: &str, force: bool) -> eyre::Result<()> {
+    let path = resolve_in_root(root, path)?;
+    if !force && symlink_metadata(&path).is_ok() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).wrap_err(format!("Could not create the directory {parent:?}"))?;
+    }
+    std::fs::write(&path, contents).wrap_err(format!("Could not create the file {path:?}"))
+}
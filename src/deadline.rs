@@ -0,0 +1,84 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Overall time budget of an operation. There is no watchdog thread, since the process has to
+//! stay single-threaded to enter a user namespace, so the deadline is checked between the
+//! steps of the operation and while waiting for the engine.
+
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre;
+
+/// Exit code used when the deadline is exceeded, the same as the one of `timeout(1)`
+pub const EXIT_CODE: i32 = 124;
+
+/// Error returned when the deadline is exceeded, it can be found with `downcast_ref`
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The operation did not finish in {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Instant at which the operation is aborted, if there is one
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Deadline {
+    at: Option<(Instant, Duration)>,
+}
+
+impl Deadline {
+    /// Deadline starting now, or one that never expires if there is no budget
+    pub fn after(budget: Option<Duration>) -> Self {
+        Deadline {
+            at: budget.map(|budget| (Instant::now() + budget, budget)),
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.at.is_some()
+    }
+
+    /// Time left before the deadline, none if there is no deadline
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at
+            .map(|(at, _)| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Fails with [`TimedOut`] once the deadline has passed
+    pub fn check(&self) -> eyre::Result<()> {
+        match self.at {
+            Some((at, budget)) if Instant::now() >= at => Err(TimedOut(budget).into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checks if the error, or any of its causes, is a [`TimedOut`]
+pub fn is_timeout(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+/// Parses a duration given in seconds or with a unit, like 90, 30s, 5m or 1h
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => duration.split_at(i),
+        None => (duration, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 && scale > 0 => Ok(Duration::from_secs(number * scale)),
+        _ => Err(format!(
+            "{duration} is not a valid duration, like 90, 30s, 5m or 1h"
+        )),
+    }
+}
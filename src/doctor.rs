@@ -0,0 +1,213 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::Command;
+
+use clap::{Args, ValueEnum};
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use nix::sched::CloneFlags;
+
+use crate::create::current_username;
+use crate::namespaces::{
+    parse_id_map, IdMap, Mapping, Mappings, SUBORDINATE_COUNT, SUBORDINATE_START,
+};
+
+/// Check that the system is set up to create and enter toolboxes
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Doctor {
+    #[clap(long, value_parser)]
+    /// Offer to add the missing subordinate ids of the current user, running usermod with sudo
+    /// once it is confirmed
+    pub fix: bool,
+}
+
+/// Start a user namespace with the mappings of a toolbox and run `id` inside of it, without
+/// a rootfs
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct NsTest {
+    #[clap(long, value_name = "INSIDE:OUTSIDE:LEN", value_parser = parse_id_map)]
+    /// Mapping of users instead of the default ones of the toolboxes, can be repeated. It is
+    /// not checked, so newuidmap reports the problems
+    pub uid_map: Vec<IdMap>,
+    #[clap(long, value_name = "INSIDE:OUTSIDE:LEN", value_parser = parse_id_map, requires = "uid-map")]
+    /// Mapping of groups, the ones of --uid-map by default
+    pub gid_map: Vec<IdMap>,
+    #[clap(long, value_enum)]
+    /// Other namespace unshared with the one of the user, can be repeated
+    pub unshare: Vec<Unshare>,
+}
+
+/// Namespaces that can be unshared by `nstest` besides the one of the user
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Unshare {
+    Mount,
+    Uts,
+    Ipc,
+    Pid,
+    Net,
+}
+
+impl Unshare {
+    fn flag(self) -> CloneFlags {
+        match self {
+            Unshare::Mount => CloneFlags::CLONE_NEWNS,
+            Unshare::Uts => CloneFlags::CLONE_NEWUTS,
+            Unshare::Ipc => CloneFlags::CLONE_NEWIPC,
+            Unshare::Pid => CloneFlags::CLONE_NEWPID,
+            Unshare::Net => CloneFlags::CLONE_NEWNET,
+        }
+    }
+}
+
+/// File listing the ranges of ids each user can map in a user namespace
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SubordinateIds {
+    Uids,
+    Gids,
+}
+
+impl SubordinateIds {
+    pub fn path(self) -> &'static str {
+        match self {
+            SubordinateIds::Uids => "/etc/subuid",
+            SubordinateIds::Gids => "/etc/subgid",
+        }
+    }
+
+    /// Option of usermod adding a range to the file
+    fn usermod_flag(self) -> &'static str {
+        match self {
+            SubordinateIds::Uids => "--add-subuids",
+            SubordinateIds::Gids => "--add-subgids",
+        }
+    }
+}
+
+/// Checks if one of the `user:start:count` lines of a subordinate ids file gives the whole
+/// range to the user, who may be given by name or by id
+pub fn covers(contents: &str, user: &str, id: u32, start: u64, count: u64) -> bool {
+    let id = id.to_string();
+    contents.lines().any(|line| {
+        let mut fields = line.trim().split(':');
+        let (owner, first, len) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(owner), Some(first), Some(len)) => (owner, first, len),
+            _ => return false,
+        };
+        match (first.parse::<u64>(), len.parse::<u64>()) {
+            (Ok(first), Ok(len)) => {
+                (owner == user || owner == id) && first <= start && start + count <= first + len
+            }
+            _ => false,
+        }
+    })
+}
+
+pub fn doctor(args: Doctor) -> eyre::Result<()> {
+    let user = current_username();
+    let uid = users::get_current_uid();
+    let start: u64 = SUBORDINATE_START.parse().expect("valid start");
+    let count: u64 = SUBORDINATE_COUNT.parse().expect("valid count");
+    let range = format!("{start}-{}", start + count - 1);
+    let mut problems = 0;
+
+    for helper in ["newuidmap", "newgidmap"] {
+        if in_path(helper) {
+            println!("ok: {helper} is installed");
+        } else {
+            println!("missing: {helper}, it is usually packaged in uidmap or shadow-utils");
+            problems += 1;
+        }
+    }
+    let mut missing = Vec::new();
+    for ids in [SubordinateIds::Uids, SubordinateIds::Gids] {
+        // A missing file has no entries, which is what it is reported as
+        let contents = std::fs::read_to_string(ids.path()).unwrap_or_default();
+        if covers(&contents, &user, uid, start, count) {
+            println!("ok: {} gives {range} to {user}", ids.path());
+        } else {
+            println!("missing: {} does not give {range} to {user}", ids.path());
+            missing.push(ids);
+        }
+    }
+    if !missing.is_empty() && args.fix {
+        add_subordinate_ids(&user, &range, &missing)?;
+        missing.clear();
+    }
+    problems += missing.len();
+    eyre::ensure!(problems == 0, "Found {problems} problems, see above");
+    Ok(())
+}
+
+/// Runs usermod with sudo to add the range to the files, after showing the command and
+/// getting a confirmation, since it changes files of the system
+fn add_subordinate_ids(user: &str, range: &str, missing: &[SubordinateIds]) -> eyre::Result<()> {
+    let mut args = vec!["usermod".to_string()];
+    for ids in missing {
+        args.push(ids.usermod_flag().into());
+        args.push(range.into());
+    }
+    args.push(user.into());
+    println!();
+    println!("The following command will be run:");
+    println!("\tsudo {}", args.join(" "));
+    eyre::ensure!(
+        std::io::stdin().is_terminal(),
+        "--fix needs a terminal to confirm the command, run it manually instead"
+    );
+    let confirmed = dialoguer::Confirm::new()
+        .with_prompt("Run it?")
+        .default(false)
+        .interact()
+        .wrap_err("Could not ask for confirmation")?;
+    eyre::ensure!(confirmed, "Nothing has been changed");
+    let status = Command::new("sudo")
+        .args(&args)
+        .status()
+        .wrap_err("Could not execute sudo")?;
+    eyre::ensure!(status.success(), "usermod failed with {status}");
+    println!("fixed: the subordinate ids have been added, new sessions can use them");
+    Ok(())
+}
+
+fn in_path(program: &str) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path).any(|dir| Path::new(&dir).join(program).is_file())
+}
+
+/// Starts the namespace like `enter` does and shows the mappings given to the helpers, the
+/// ones the kernel ended up with and the ids `id` runs as. The namespaces are gone once
+/// unbox exits, so there is nothing to tear down.
+pub fn nstest(args: NsTest) -> eyre::Result<()> {
+    let flags = args
+        .unshare
+        .iter()
+        .fold(CloneFlags::CLONE_NEWUSER, |flags, ns| flags | ns.flag());
+    let mappings = Mappings::new(&args.uid_map, &args.gid_map);
+    let (uids, gids) = (mappings.uids(), mappings.gids());
+    let shown = |maps: &[Mapping<'_>]| maps.iter().map(Mapping::to_string).collect::<String>();
+    print!("uid_map to write:\n{}", shown(&uids));
+    let gid_map = if gids.is_empty() {
+        shown(&uids)
+    } else {
+        shown(&gids)
+    };
+    print!("gid_map to write:\n{gid_map}");
+    println!("unsharing {flags:?}");
+    let mut ns = mappings.start(flags)?;
+    ns.wait()?;
+    for map in ["uid_map", "gid_map"] {
+        let path = format!("/proc/self/{map}");
+        let contents = std::fs::read_to_string(&path).wrap_err(format!("Could not read {path}"))?;
+        print!("{map} of the namespace:\n{contents}");
+    }
+    let status = Command::new("id")
+        .status()
+        .wrap_err("Could not execute id inside of the namespace")?;
+    eyre::ensure!(status.success(), "id failed with {status}");
+    println!("ok: the namespace works");
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+
+use crate::deadline::Deadline;
+use crate::progress::ProgressSink;
+
+/// Checks if an archive given to `--tar` is an http or https url instead of a path
+pub fn is_url(tar: &Path) -> bool {
+    let tar = tar.to_string_lossy();
+    tar.starts_with("https://") || tar.starts_with("http://")
+}
+
+/// Downloads the archive at `url` to `output` with curl, following the redirects. The bytes
+/// are copied by unbox to report the progress, and a failed request is an error instead of
+/// an html page saved as the archive. `output` is created by unbox and must not exist, so a
+/// link put there can not redirect the download.
+pub fn download(
+    url: &str,
+    output: &Path,
+    deadline: Deadline,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<()> {
+    progress.on_phase("Downloading archive");
+    deadline.check()?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(output)
+        .wrap_err(format!("Could not create {output:?}"))?;
+    let mut curl = Command::new("curl");
+    curl.args(["--fail", "--location", "--silent", "--show-error"]);
+    // A stalled connection is only noticed by curl, as reading its output blocks meanwhile
+    if let Some(remaining) = deadline.remaining() {
+        curl.arg("--max-time")
+            .arg(format!("{:.3}", remaining.as_secs_f64().max(0.001)));
+    }
+    curl.args(["--speed-limit", "1", "--speed-time", "60", "--", url]);
+    let mut curl = curl
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err("Could not execute curl, is it installed?")?;
+    let mut stdout = curl.stdout.take().expect("stdout is piped");
+    let mut buffer = vec![0; 64 * 1024];
+    let mut done = 0;
+    let copied = loop {
+        if let Err(e) = deadline.check() {
+            let _ = curl.kill();
+            break Err(e);
+        }
+        match stdout.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(read) => {
+                if let Err(e) = file.write_all(&buffer[..read]) {
+                    let _ = curl.kill();
+                    break Err(eyre::Report::new(e).wrap_err(format!("Could not write {output:?}")));
+                }
+                done += read as u64;
+                progress.on_bytes(done, None);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                let _ = curl.kill();
+                break Err(eyre::Report::new(e).wrap_err("Could not read the download"));
+            }
+        }
+    };
+    // curl only writes a line to stderr when it fails, it can not fill the pipe
+    let mut stderr = String::new();
+    if let Some(mut pipe) = curl.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    let status = curl.wait().wrap_err("Could not wait for curl")?;
+    copied?;
+    // curl stopped at --max-time, which is reported like the other timeouts
+    deadline.check()?;
+    eyre::ensure!(
+        status.success(),
+        "Could not download {url}: {}",
+        match stderr.trim() {
+            "" => format!("curl failed with {status}"),
+            message => message.trim_start_matches("curl: ").to_string(),
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,594 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+use clap::{Args, ValueEnum};
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use crate::deadline::Deadline;
+use crate::progress::ProgressSink;
+
+/// OCI engine to extract the rootfs (docker, podman or a custom one)
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
+pub enum Engine {
+    Docker,
+    Podman,
+    Custom,
+}
+
+/// Commands of a custom engine. `{image}`, `{cid}` and `{output}` are replaced by the url
+/// of the image, the container id printed by the create command and the path of the tarball
+#[derive(Args, PartialEq, Eq, Debug, Default)]
+pub struct CustomEngine {
+    #[clap(long, value_parser)]
+    /// Program used as the custom engine
+    pub engine_cmd: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to create a container and print its id [default: "create {image}"]
+    pub engine_create: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to export the container as a tarball [default: "export {cid} --output {output}",
+    /// or "save {image} --output {output}" with --save]
+    pub engine_export: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to remove the container [default: "rm {cid}"]
+    pub engine_rm: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to print the USER of the image, it is not read if they are not given
+    pub engine_inspect: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to print the labels of the image as a JSON object, needed by --import-labels
+    pub engine_labels: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to print the digest of the image, which may follow the name and an @, to
+    /// record it in the config. It is not recorded if they are not given
+    pub engine_digest: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to print the config of the image as a JSON object, with its Env, Cmd,
+    /// Entrypoint and WorkingDir. It is not read if they are not given
+    pub engine_config: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments that exit with 1 if the image is not stored locally, needed by --local
+    pub engine_exists: Option<String>,
+    #[clap(long, value_parser)]
+    /// Arguments to print the references of the local images, one per line, to complete partial
+    /// names. They are not completed if the arguments are not given
+    pub engine_images: Option<String>,
+}
+
+/// Commands used to extract the rootfs of an image, following the contract documented in
+/// [`CustomEngine`]
+#[derive(Debug)]
+pub struct EngineCommands {
+    pub program: String,
+    pub create: String,
+    pub export: String,
+    pub remove: String,
+    pub inspect: Option<String>,
+    /// Prints the labels of the image as a JSON object, or null
+    pub labels: Option<String>,
+    /// Prints the digest of the image, possibly as `name@digest`
+    pub digest: Option<String>,
+    /// Prints the config of the image as a JSON object
+    pub config: Option<String>,
+    /// Checks if the image is stored locally, it exits with 1 when it is not
+    pub exists: Option<String>,
+    /// Lists the references of the local images, one per line
+    pub images: Option<String>,
+    /// Only use an image already stored locally, instead of pulling it
+    pub local: bool,
+    /// The commands are killed once it has passed
+    pub deadline: Deadline,
+    /// Directory where the output of every command is logged, see [`ENGINE_LOG`]
+    pub artifacts: Option<PathBuf>,
+}
+
+/// File of the debugging artifacts with the commands run by the engine and their output
+pub const ENGINE_LOG: &str = "engine.log";
+
+impl EngineCommands {
+    /// With `save` the whole image is written with its layers, instead of the filesystem of the
+    /// container. With `local` the image is never pulled.
+    pub fn new(
+        engine: Engine,
+        custom: &CustomEngine,
+        quiet: bool,
+        save: bool,
+        local: bool,
+    ) -> eyre::Result<Self> {
+        // The pull progress of the engine is captured, but it does not need to be produced either
+        let create = match (quiet, local) {
+            (true, true) => "create --quiet --pull never {image}",
+            (true, false) => "create --quiet {image}",
+            (false, true) => "create --pull never {image}",
+            (false, false) => "create {image}",
+        };
+        let export = if save {
+            "save {image} --output {output}"
+        } else {
+            "export {cid} --output {output}"
+        };
+        // Docker has no `image exists`, but inspecting a missing image also exits with 1. It
+        // only has the digests of the image in the registries it was pulled from.
+        let (program, exists, digest) = match engine {
+            Engine::Docker => (
+                "docker",
+                "image inspect --format {{.Id}} {image}",
+                "image inspect --format {{index .RepoDigests 0}} {image}",
+            ),
+            Engine::Podman => (
+                "podman",
+                "image exists {image}",
+                "image inspect --format {{.Digest}} {image}",
+            ),
+            Engine::Custom => {
+                let program = custom.engine_cmd.clone().ok_or_else(|| {
+                    eyre::eyre!("The custom engine needs a program given with --engine-cmd")
+                })?;
+                eyre::ensure!(
+                    !local || custom.engine_exists.is_some(),
+                    "--local needs --engine-exists to check the images of a custom engine"
+                );
+                return Ok(EngineCommands {
+                    program,
+                    create: template(&custom.engine_create, "create {image}"),
+                    export: template(&custom.engine_export, export),
+                    remove: template(&custom.engine_rm, "rm {cid}"),
+                    inspect: custom.engine_inspect.clone(),
+                    labels: custom.engine_labels.clone(),
+                    digest: custom.engine_digest.clone(),
+                    config: custom.engine_config.clone(),
+                    exists: custom.engine_exists.clone(),
+                    images: custom.engine_images.clone(),
+                    local,
+                    deadline: Deadline::default(),
+                    artifacts: None,
+                });
+            }
+        };
+        Ok(EngineCommands {
+            program: program.into(),
+            create: create.into(),
+            export: export.into(),
+            remove: "rm {cid}".into(),
+            inspect: Some("image inspect --format {{.Config.User}} {image}".into()),
+            labels: Some("image inspect --format {{json .Config.Labels}} {image}".into()),
+            digest: Some(digest.into()),
+            config: Some("image inspect --format {{json .Config}} {image}".into()),
+            exists: Some(exists.into()),
+            images: Some("images --format {{.Repository}}:{{.Tag}}".into()),
+            local,
+            deadline: Deadline::default(),
+            artifacts: None,
+        })
+    }
+}
+
+fn template(custom: &Option<String>, default: &str) -> String {
+    custom.as_deref().unwrap_or(default).into()
+}
+
+/// Splits the template in arguments, replacing the placeholders in each one of them
+fn expand(template: &str, image: &str, cid: &str, output: &str) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{image}", image)
+                .replace("{cid}", cid)
+                .replace("{output}", output)
+        })
+        .collect()
+}
+
+/// Tag of the images given without one when neither the flag nor the global config set it
+pub const DEFAULT_TAG: &str = "latest";
+
+/// Adds the tag to the image reference if it has neither a tag nor a digest. The port of a
+/// registry, as in `localhost:5000/alpine`, is not mistaken for a tag.
+pub fn with_default_tag(url: &str, tag: &str) -> String {
+    let name = url.rsplit('/').next().unwrap_or(url);
+    if url.contains('@') || name.contains(':') {
+        url.into()
+    } else {
+        format!("{url}:{tag}")
+    }
+}
+
+/// Digest pinning the image reference, as in `alpine@sha256:<digest>`, after validating it
+pub fn image_digest(url: &str) -> eyre::Result<Option<&str>> {
+    let digest = match url.split_once('@') {
+        Some((_, digest)) => digest,
+        None => return Ok(None),
+    };
+    let (algorithm, hex) = digest.split_once(':').unwrap_or((digest, ""));
+    let len = match algorithm {
+        "sha256" => 64,
+        "sha512" => 128,
+        _ => 0,
+    };
+    let is_hex = |b: u8| b.is_ascii_digit() || (b'a'..=b'f').contains(&b);
+    eyre::ensure!(
+        len != 0 && hex.len() == len && hex.bytes().all(is_hex),
+        "{digest} is not a valid image digest"
+    );
+    Ok(Some(digest))
+}
+
+pub fn get_image(
+    engine: &EngineCommands,
+    url: &str,
+    tar_file: &str,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<()> {
+    // The reference is passed as it is, so a digest pins the exact image
+    if engine.local {
+        eyre::ensure!(
+            image_exists(engine, url)?,
+            "{url} is not stored locally by {}, and --local does not pull it",
+            engine.program
+        );
+    }
+    progress.on_phase("Downloading image");
+    let create = expand(&engine.create, url, "", tar_file);
+    let stdout = spawn(engine, &create, engine.deadline)?.stdout;
+    let container = Container {
+        engine,
+        url,
+        output: tar_file,
+        cid: Some(container_id(&stdout)?.into()),
+    };
+    spawn(
+        engine,
+        &expand(&engine.export, url, container.id(), tar_file),
+        engine.deadline,
+    )?;
+    container.remove()
+}
+
+/// Container created to export the image. It is removed when it is dropped, so the engine is
+/// not left with it by an export that failed or ran out of time.
+struct Container<'a> {
+    engine: &'a EngineCommands,
+    url: &'a str,
+    output: &'a str,
+    /// Taken once the container has been removed
+    cid: Option<String>,
+}
+
+impl Container<'_> {
+    fn id(&self) -> &str {
+        self.cid
+            .as_deref()
+            .expect("the container has not been removed")
+    }
+
+    /// Removes the container, reporting if the engine failed to do it
+    fn remove(mut self) -> eyre::Result<()> {
+        let cid = self.cid.take().expect("the container has not been removed");
+        self.rm(&cid)
+    }
+
+    fn rm(&self, cid: &str) -> eyre::Result<()> {
+        // Without a deadline, so the container is removed even when it has already passed
+        let remove = expand(&self.engine.remove, self.url, cid, self.output);
+        spawn(self.engine, &remove, Deadline::default()).map(|_| ())
+    }
+}
+
+impl Drop for Container<'_> {
+    fn drop(&mut self) {
+        if let Some(cid) = self.cid.take() {
+            // The error that caused the early return is more useful than this one
+            let _ = self.rm(&cid);
+        }
+    }
+}
+
+/// Extracts the id of the container from the output of the create command. Engines may print
+/// pull progress or warnings before it, so only the last line is used.
+pub fn container_id(stdout: &[u8]) -> eyre::Result<&str> {
+    let stdout = std::str::from_utf8(stdout).wrap_err("The container id is not valid utf8")?;
+    let cid = stdout
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .ok_or_else(|| eyre::eyre!("The engine did not print the id of the container"))?;
+    eyre::ensure!(
+        cid.len() >= 12 && cid.bytes().all(|b| b.is_ascii_hexdigit()),
+        "The engine printed {cid:?} instead of the id of the container"
+    );
+    Ok(cid)
+}
+
+/// User the image should be run as, given by its `USER` directive. It is empty when the
+/// image does not set it.
+pub fn image_user(engine: &EngineCommands, url: &str) -> eyre::Result<Option<String>> {
+    let inspect = match &engine.inspect {
+        Some(inspect) => inspect,
+        None => return Ok(None),
+    };
+    let user = spawn(engine, &expand(inspect, url, "", ""), engine.deadline)?.stdout;
+    let user = String::from_utf8(user).wrap_err("The user of the image is not valid utf8")?;
+    let user = user.trim();
+    Ok((!user.is_empty()).then(|| user.to_string()))
+}
+
+/// Local images that the tagged `reference` is short for, the ones ending with it after a
+/// registry or a namespace, like `quay.io/fedora/fedora:39` for `fedora:39`. It is only
+/// `reference` if the engine already resolves it to a local image by itself, as it does with
+/// `docker.io/library/alpine:latest` for `alpine:latest`. Untagged images are never matched,
+/// since they can not be referenced by name.
+pub fn matching_images(references: &str, reference: &str) -> Vec<String> {
+    let references: Vec<&str> = references
+        .lines()
+        .map(str::trim)
+        .filter(|reference| !reference.is_empty() && !reference.contains("<none>"))
+        .collect();
+    let resolved = [
+        reference.to_string(),
+        format!("docker.io/{reference}"),
+        format!("docker.io/library/{reference}"),
+        format!("localhost/{reference}"),
+    ];
+    if references
+        .iter()
+        .any(|local| resolved.iter().any(|r| r == local))
+    {
+        return vec![reference.into()];
+    }
+    let suffix = format!("/{reference}");
+    let mut matches: Vec<String> = Vec::new();
+    for local in references {
+        if local.ends_with(&suffix) && !matches.iter().any(|m| m == local) {
+            matches.push(local.into());
+        }
+    }
+    matches
+}
+
+/// Adds the default tag to the image and completes it with the local images of the engine,
+/// as done by [`matching_images`]. When several match the user picks one if `interactive`,
+/// otherwise it fails listing them. Images that do not match any local image, or that are
+/// pinned with a digest, are returned with the tag so the engine pulls them.
+pub fn resolve_image(
+    engine: &EngineCommands,
+    url: &str,
+    tag: &str,
+    interactive: bool,
+) -> eyre::Result<String> {
+    let url = &with_default_tag(url, tag);
+    let images = match &engine.images {
+        Some(images) if !url.contains('@') => images,
+        _ => return Ok(url.into()),
+    };
+    let references = spawn(engine, &expand(images, url, "", ""), engine.deadline)?.stdout;
+    let references = String::from_utf8(references)
+        .wrap_err("The references of the images are not valid utf8")?;
+    let mut matches = matching_images(&references, url);
+    match matches.len() {
+        0 => Ok(url.into()),
+        1 => Ok(matches.remove(0)),
+        _ if interactive => {
+            let picked = dialoguer::Select::new()
+                .with_prompt(format!("Several local images match {url}"))
+                .items(&matches)
+                .default(0)
+                .interact_opt()
+                .wrap_err("Could not show the images to choose from")?;
+            match picked {
+                Some(picked) => Ok(matches.remove(picked)),
+                None => eyre::bail!("No image has been chosen"),
+            }
+        }
+        _ => eyre::bail!(
+            "{url} matches several local images, use one of: {}",
+            matches.join(", ")
+        ),
+    }
+}
+
+/// Labels set by the image, like `org.opencontainers.image.version`
+pub fn image_labels(engine: &EngineCommands, url: &str) -> eyre::Result<BTreeMap<String, String>> {
+    let labels = engine.labels.as_ref().ok_or_else(|| {
+        eyre::eyre!("The labels of the image can not be read without --engine-labels")
+    })?;
+    let labels = spawn(engine, &expand(labels, url, "", ""), engine.deadline)?.stdout;
+    // Images without labels print null
+    let labels: Option<BTreeMap<String, String>> =
+        serde_json::from_slice(&labels).wrap_err("The labels of the image are not valid")?;
+    Ok(labels.unwrap_or_default())
+}
+
+/// Digest of the image stored by the engine, which pins it even if it was given by a tag. It
+/// is none if the engine has no command to print it or no digest for the image.
+pub fn stored_digest(engine: &EngineCommands, url: &str) -> eyre::Result<Option<String>> {
+    let digest = match &engine.digest {
+        Some(digest) => digest,
+        None => return Ok(None),
+    };
+    let digest = spawn(engine, &expand(digest, url, "", ""), engine.deadline)?.stdout;
+    let digest = String::from_utf8(digest).wrap_err("The digest of the image is not valid utf8")?;
+    let digest = digest.trim();
+    if digest.is_empty() {
+        return Ok(None);
+    }
+    // Validated as the digest of a reference, which it may already be
+    let reference = if digest.contains('@') {
+        digest.to_string()
+    } else {
+        format!("{url}@{digest}")
+    };
+    Ok(image_digest(&reference)?.map(str::to_string))
+}
+
+/// Part of the config of an image describing how its containers are run
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ImageConfig {
+    #[serde(
+        rename(deserialize = "Env"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub env: Option<Vec<String>>,
+    #[serde(
+        rename(deserialize = "Cmd"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cmd: Option<Vec<String>>,
+    #[serde(
+        rename(deserialize = "Entrypoint"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(
+        rename(deserialize = "WorkingDir"),
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workdir: Option<String>,
+}
+
+/// Config of the image, if the engine has a command to print it
+pub fn image_config(engine: &EngineCommands, url: &str) -> eyre::Result<Option<ImageConfig>> {
+    let config = match &engine.config {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+    let config = spawn(engine, &expand(config, url, "", ""), engine.deadline)?.stdout;
+    let mut config: ImageConfig =
+        serde_json::from_slice(&config).wrap_err("The config of the image is not valid")?;
+    // An unset working directory is printed as an empty string
+    config.workdir = config.workdir.filter(|workdir| !workdir.is_empty());
+    Ok(Some(config))
+}
+
+/// Checks if the image is in the local store of the engine, without pulling it
+fn image_exists(engine: &EngineCommands, url: &str) -> eyre::Result<bool> {
+    let exists = match &engine.exists {
+        Some(exists) => exists,
+        None => return Ok(false),
+    };
+    let output = execute(engine, &expand(exists, url, "", ""), engine.deadline)?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(eyre::eyre!(
+            "{} could not check the local images: {}",
+            engine.program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+fn spawn(engine: &EngineCommands, args: &[String], deadline: Deadline) -> eyre::Result<Output> {
+    let output = execute(engine, args, deadline)?;
+    // Only stdout is used, stderr is shown when the engine fails
+    eyre::ensure!(
+        output.status.success(),
+        "{} failed: {}",
+        engine.program,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(output)
+}
+
+/// Runs the engine and logs it to the debugging artifacts, whatever its exit status is
+fn execute(engine: &EngineCommands, args: &[String], deadline: Deadline) -> eyre::Result<Output> {
+    use std::process::{Command, Stdio};
+    let cmd = &engine.program;
+    let mut command = Command::new(cmd);
+    command.args(args).stdin(Stdio::null());
+    let output = if deadline.is_set() {
+        output_before(command, deadline)?
+    } else {
+        command
+            .output()
+            .wrap_err("Could not execute the provided engine")?
+    };
+    if let Some(dir) = &engine.artifacts {
+        log_output(dir, cmd, args, &output)?;
+    }
+    Ok(output)
+}
+
+/// Appends the command and everything it printed to the engine log of the debugging artifacts
+fn log_output(dir: &Path, cmd: &str, args: &[String], output: &Output) -> eyre::Result<()> {
+    use std::io::Write;
+    let path = dir.join(ENGINE_LOG);
+    let mut log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .wrap_err(format!("Could not open {path:?}"))?;
+    writeln!(log, "$ {cmd} {}", args.join(" "))?;
+    writeln!(log, "--- stdout")?;
+    log.write_all(&output.stdout)?;
+    writeln!(log, "--- stderr")?;
+    log.write_all(&output.stderr)?;
+    writeln!(log, "--- {}\n", output.status)?;
+    Ok(())
+}
+
+/// Same as [`Command::output`](std::process::Command::output), but killing the command once
+/// the deadline has passed. The output is read by scoped threads, so they have finished when
+/// it returns and the process can still enter a namespace.
+fn output_before(mut command: std::process::Command, deadline: Deadline) -> eyre::Result<Output> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+    use std::io::Read;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    // In its own group, so the processes it started are killed with it
+    let mut child = command
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err("Could not execute the provided engine")?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    std::thread::scope(|scope| {
+        let stdout = scope.spawn(move || {
+            let mut buf = Vec::new();
+            stdout.read_to_end(&mut buf).map(|_| buf)
+        });
+        let stderr = scope.spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        });
+        let status = loop {
+            if let Some(status) = child.try_wait().wrap_err("Could not wait for the engine")? {
+                break status;
+            }
+            if let Err(e) = deadline.check() {
+                // Killing the group closes the pipes, which lets the readers finish
+                let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+                let _ = child.wait();
+                return Err(e);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        let read = |reader: std::thread::ScopedJoinHandle<'_, std::io::Result<Vec<u8>>>| {
+            reader
+                .join()
+                .expect("reading the output does not panic")
+                .wrap_err("Could not read the output of the engine")
+        };
+        Ok(Output {
+            status,
+            stdout: read(stdout)?,
+            stderr: read(stderr)?,
+        })
+    })
+}
@@ -0,0 +1,191 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use nix::sched::CloneFlags;
+use tar::{Builder, Header, HeaderMode};
+use walkdir::WalkDir;
+
+use crate::archive::{Compression, Encoder};
+use crate::config::Config;
+use crate::namespaces::start_toolbox;
+
+/// Export the rootfs of a toolbox as a tarball
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Export {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    pub name: String,
+    #[clap(short, long, value_parser)]
+    /// Path of the tarball to be created
+    pub output: PathBuf,
+    #[clap(short, long, value_parser)]
+    /// Compression of the tarball, inferred from the output extension by default
+    pub compression: Option<Compression>,
+    #[clap(short, long, value_parser)]
+    /// Gitignore-style pattern of the paths left out of the tarball, can be repeated. The
+    /// patterns of /.unboxignore in the toolbox are used too
+    pub exclude: Vec<String>,
+    #[clap(long, value_parser)]
+    /// Give every entry the owner root and the same modification time, so the same rootfs is
+    /// always exported to the same bytes
+    pub reproducible: bool,
+    #[clap(long, value_parser, alias = "source-date-epoch")]
+    /// Modification time, in seconds since the epoch, of the entries of a reproducible tarball,
+    /// SOURCE_DATE_EPOCH or 0 by default. It implies --reproducible
+    pub mtime: Option<u64>,
+}
+
+/// Modification time of the entries, if the tarball is reproducible. `SOURCE_DATE_EPOCH` is
+/// only used by a reproducible export, setting it does not make every export reproducible.
+pub fn entries_mtime(
+    mtime: Option<u64>,
+    reproducible: bool,
+    source_date_epoch: Option<&str>,
+) -> eyre::Result<Option<u64>> {
+    match (mtime, source_date_epoch) {
+        (Some(mtime), _) => Ok(Some(mtime)),
+        (None, _) if !reproducible => Ok(None),
+        (None, Some(epoch)) => epoch
+            .parse()
+            .map(Some)
+            .wrap_err(format!("Invalid SOURCE_DATE_EPOCH {epoch}")),
+        (None, None) => Ok(Some(0)),
+    }
+}
+
+/// File of the rootfs with the patterns of the paths left out when exporting it
+pub const IGNORE_FILE: &str = ".unboxignore";
+
+pub fn export(args: Export) -> eyre::Result<()> {
+    let config =
+        Config::read(&args.name).wrap_err("Could not get configuration for the toolbox")?;
+    eyre::ensure!(
+        config.squashfs.is_none(),
+        "{} is stored as a read-only squashfs image, its files can be extracted with unsquashfs",
+        args.name
+    );
+    eyre::ensure!(
+        config.overlay.is_none(),
+        "{} only stores its changes over another toolbox, it can not be exported",
+        args.name
+    );
+    let compression = args
+        .compression
+        .unwrap_or_else(|| Compression::from_extension(&args.output));
+
+    // Inside of the namespace the files have the owners they have inside of the toolbox
+    let mut ns = start_toolbox(CloneFlags::CLONE_NEWUSER, &config)?;
+    ns.wait()?;
+
+    let root = Path::new(&config.image);
+    let exclude = excludes(root, &args.exclude)?;
+    let epoch = std::env::var("SOURCE_DATE_EPOCH").ok();
+    let mtime = entries_mtime(args.mtime, args.reproducible, epoch.as_deref())?;
+    write_tar(root, &args.output, compression, Some(&exclude), mtime)
+}
+
+/// Builds the matcher of the excluded paths from the patterns and the ignore file of the rootfs,
+/// which is excluded itself
+pub fn excludes(root: &Path, patterns: &[String]) -> eyre::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    let file = root.join(IGNORE_FILE);
+    if file.is_file() {
+        if let Some(e) = builder.add(&file) {
+            return Err(e).wrap_err(format!("Could not read the patterns of /{IGNORE_FILE}"));
+        }
+        builder.add_line(None, &format!("/{IGNORE_FILE}"))?;
+    }
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .wrap_err(format!("{pattern} is not a valid pattern"))?;
+    }
+    builder
+        .build()
+        .wrap_err("Could not build the excluded patterns")
+}
+
+/// Writes the contents of `root` as a tarball in `output`, except the paths matched by
+/// `exclude`. This should be done inside of a namespace to get the same ownership as inside
+/// of the toolbox.
+///
+/// With an `mtime` every entry is owned by root and modified at that time instead, and since
+/// the entries are always sorted by name the tarball only depends on the contents of `root`.
+pub fn write_tar(
+    root: &Path,
+    output: &Path,
+    compression: Compression,
+    exclude: Option<&Gitignore>,
+    mtime: Option<u64>,
+) -> eyre::Result<()> {
+    let output = File::create(output).wrap_err("Could not create the output file")?;
+    let encoder = Encoder::new(BufWriter::new(output), compression)?;
+    let encoder = pack_tar(encoder, root, exclude, mtime)?;
+    encoder
+        .finish()
+        .wrap_err("Could not finish the compression of the tarball")?;
+    Ok(())
+}
+
+fn pack_tar<W: Write>(
+    writer: W,
+    root: &Path,
+    exclude: Option<&Gitignore>,
+    mtime: Option<u64>,
+) -> eyre::Result<W> {
+    let mut builder = Builder::new(writer);
+    builder.follow_symlinks(false);
+    // Excluded directories are not walked, so nothing inside of them is added
+    let walk = WalkDir::new(root).sort_by_file_name().into_iter();
+    let included = walk.filter_entry(|entry| match exclude {
+        Some(exclude) => !exclude
+            .matched(entry.path(), entry.file_type().is_dir())
+            .is_ignore(),
+        None => true,
+    });
+    for entry in included {
+        let entry = entry.wrap_err("Could not walk the rootfs of the toolbox")?;
+        let relative = entry.path().strip_prefix(root).expect("walked from root");
+        let name = Path::new(".").join(relative);
+        match mtime {
+            Some(mtime) => append_normalized(&mut builder, entry.path(), &name, mtime),
+            None => builder.append_path_with_name(entry.path(), name),
+        }
+        .wrap_err(format!("Could not add {:?} to the tarball", entry.path()))?;
+    }
+    builder.into_inner().wrap_err("Could not write the tarball")
+}
+
+/// Appends the file like `append_path_with_name` does, but owned by root and with the given
+/// modification time. The permissions are kept, unlike with `HeaderMode::Deterministic`.
+fn append_normalized<W: Write>(
+    builder: &mut Builder<W>,
+    path: &Path,
+    name: &Path,
+    mtime: u64,
+) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(mtime);
+    if metadata.file_type().is_symlink() {
+        header.set_size(0);
+        builder.append_link(&mut header, name, std::fs::read_link(path)?)
+    } else if metadata.is_file() {
+        builder.append_data(&mut header, name, File::open(path)?)
+    } else {
+        header.set_size(0);
+        builder.append_data(&mut header, name, io::empty())
+    }
+}
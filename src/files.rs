@@ -0,0 +1,66 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading the rootfs of a toolbox from the host, without entering it. Paths are resolved
+//! like inside of the toolbox, so neither `..` nor symlinks can leave the rootfs.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+
+use crate::config::Config;
+use crate::unpack::resolve_in_root;
+
+/// Print files of a toolbox without entering it
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Cat {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    pub name: String,
+    #[clap(value_parser, required = true)]
+    /// Paths of the files inside of the toolbox, printed one after the other
+    pub paths: Vec<PathBuf>,
+}
+
+pub fn cat(args: Cat) -> eyre::Result<()> {
+    let root = rootfs(&args.name)?;
+    let mut stdout = std::io::stdout().lock();
+    for path in &args.paths {
+        let resolved = resolve_in_root(&root, path)?;
+        let mut file = File::open(resolved).wrap_err(format!("Could not open {path:?}"))?;
+        // The bytes are copied as they are, binary files included
+        std::io::copy(&mut file, &mut stdout).wrap_err(format!("Could not read {path:?}"))?;
+    }
+    Ok(())
+}
+
+/// Names of the entries of a directory of the rootfs in order, or the path itself if it is
+/// not a directory, like `ls` does
+pub fn list_dir(root: &Path, path: &Path) -> eyre::Result<Vec<String>> {
+    let resolved = resolve_in_root(root, path)?;
+    let meta = std::fs::metadata(&resolved).wrap_err(format!("Could not find {path:?}"))?;
+    if !meta.is_dir() {
+        return Ok(vec![path.display().to_string()]);
+    }
+    let mut names = std::fs::read_dir(&resolved)
+        .wrap_err(format!("Could not list {path:?}"))?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// Directory with the rootfs of the toolbox, which has to be a plain one since squashfs
+/// images and overlays are only mounted while entering the toolbox
+pub fn rootfs(name: &str) -> eyre::Result<PathBuf> {
+    let config = Config::read(name).wrap_err("Could not get configuration for the toolbox")?;
+    eyre::ensure!(
+        config.squashfs.is_none() && config.overlay.is_none(),
+        "The rootfs of {name} is only mounted while it is entered, use unbox run instead"
+    );
+    Ok(config.image.into())
+}
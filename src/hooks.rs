@@ -0,0 +1,105 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+
+use crate::config::Config;
+use crate::verbosity::{warn, Verbosity};
+
+// Run the post-create hooks of a toolbox once the parent signals that it is ready
+/// Internal subcommand. Should not be used directly
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct RunHooks {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    name: String,
+    #[clap(long, value_parser)]
+    /// Keep running the hooks after one of them fails
+    ignore_errors: bool,
+    #[clap(long, value_parser)]
+    /// Hide the output of the hooks and the warnings, the errors are still shown
+    quiet: bool,
+}
+
+pub fn run_hooks(args: RunHooks) -> eyre::Result<()> {
+    let mut input = String::with_capacity(6);
+    // The parent closes stdin without writing anything if the creation failed
+    if std::io::stdin().read_line(&mut input)? == 0 {
+        return Ok(());
+    }
+    let config = Config::read(&args.name)?;
+    let verbosity = Verbosity::from_flags(args.quiet, false);
+    for cmd in &config.post_create {
+        // The hook is run from outside of the namespace of `create`, so it gets the full mappings of `run`,
+        // and as root to be able to install packages even if the image sets another user
+        let mut hook = Command::new("/proc/self/exe");
+        hook.args(["run", "--root", &args.name]);
+        if args.quiet {
+            hook.arg("--quiet").stdout(Stdio::null());
+        }
+        let status = hook
+            .args(["--", "/bin/sh", "-c", cmd])
+            .status()
+            .wrap_err("Could not spawn the post-create hook")?;
+        if !status.success() {
+            if args.ignore_errors {
+                warn(
+                    verbosity,
+                    format_args!("Ignoring failure of the post-create hook: {cmd}"),
+                );
+            } else {
+                eyre::bail!("The post-create hook failed: {cmd}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Post-create hooks waiting for the rootfs to be ready
+pub struct Hooks(Option<Child>);
+
+impl Hooks {
+    /// Spawns the process running the hooks, it has to be called before entering any namespace.
+    /// Unless the verbosity shows the progress, it hides what the hooks print.
+    pub fn spawn(
+        name: &str,
+        hooks: &[String],
+        ignore_errors: bool,
+        verbosity: Verbosity,
+    ) -> eyre::Result<Self> {
+        if hooks.is_empty() {
+            return Ok(Hooks(None));
+        }
+        let mut cmd = Command::new("/proc/self/exe");
+        cmd.args(["run-hooks", name]);
+        if ignore_errors {
+            cmd.arg("--ignore-errors");
+        }
+        if !verbosity.shows_progress() {
+            cmd.arg("--quiet");
+        }
+        let child = cmd
+            .stdin(Stdio::piped())
+            .spawn()
+            .wrap_err("Could not spawn child to run the post-create hooks")?;
+        Ok(Hooks(Some(child)))
+    }
+
+    /// Runs the hooks and waits for all of them to finish
+    pub fn run(self) -> eyre::Result<()> {
+        if let Some(mut child) = self.0 {
+            writeln!(&mut child.stdin.take().unwrap(), "ready").wrap_err("communication failed")?;
+            let status = child
+                .wait()
+                .wrap_err("Could not wait for the post-create hooks")?;
+            eyre::ensure!(status.success(), "The post-create hooks failed");
+        }
+        Ok(())
+    }
+}
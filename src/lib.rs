@@ -7,9 +7,34 @@
 
 // TODO: add documentation
 
+// Namespaces, mounts and pivot_root are only available in Linux
+#[cfg(not(target_os = "linux"))]
+compile_error!("unbox relies on Linux user namespaces and can only be built for Linux");
+
+pub mod alias;
+pub mod archive;
+pub mod chown;
+pub mod clone;
 pub mod config;
 pub mod create;
+pub mod deadline;
+pub mod doctor;
+pub mod download;
+pub mod engine;
+pub mod export;
+pub mod files;
+pub mod hooks;
 pub mod list;
 pub mod namespaces;
+pub mod os_release;
+pub mod probe;
+pub mod progress;
 pub mod remove;
+pub mod repair;
 pub mod run;
+pub mod sessions;
+pub mod tty;
+pub mod unpack;
+pub mod user;
+pub mod verbosity;
+pub mod version;
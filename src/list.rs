@@ -2,69 +2,148 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::{borrow::Cow, env};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
-use clap::Args;
+use clap::{Args, ValueEnum};
 use color_eyre::eyre;
-use color_eyre::eyre::WrapErr;
+use serde::Serialize;
 use tabled::{Style, Table, Tabled};
 
-use crate::config::{Config, STORAGE};
+use crate::config::{storage_root, Config};
+use crate::files::{list_dir, rootfs};
 
-/// List toolboxes
+/// List toolboxes, or the files of a directory of one of them
 #[derive(Args, PartialEq, Eq, Debug)]
-pub struct List {}
+pub struct List {
+    #[clap(short, long, value_parser, default_value = "table")]
+    /// Format of the list
+    format: Format,
+    #[clap(value_parser)]
+    /// Toolbox whose files are listed instead, without entering it
+    name: Option<String>,
+    #[clap(value_parser, requires = "name", default_value = "/")]
+    /// Directory inside of the toolbox
+    path: PathBuf,
+}
+
+/// Output format of the list
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
 
-struct Row {
-    name: String,
-    config: Config,
+/// Description of a toolbox shown by `list`
+#[derive(Debug, Serialize)]
+pub struct ToolboxSummary {
+    pub name: String,
+    pub shell: String,
+    pub hostname: String,
+    /// Name and version of the distribution, empty if the rootfs has no os-release file
+    pub distro: String,
+    pub image: String,
+    /// Only in the JSON output, they do not fit in a column
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
 }
 
-impl Row {
+impl ToolboxSummary {
     fn new(name: String) -> eyre::Result<Self> {
         let config = Config::read_or_new(&name)?;
-        Ok(Self { name, config })
+        Ok(Self {
+            name,
+            shell: config.shell,
+            hostname: config.hostname,
+            distro: config.distro.map(|d| d.to_string()).unwrap_or_default(),
+            image: config.image,
+            labels: config.labels,
+            annotations: config.annotations,
+        })
     }
 }
 
-impl Tabled for Row {
-    const LENGTH: usize = 4;
+impl Tabled for ToolboxSummary {
+    const LENGTH: usize = 5;
 
     fn fields(&self) -> Vec<Cow<'_, str>> {
         vec![
             Cow::Borrowed(&self.name),
-            Cow::Borrowed(&self.config.shell),
-            Cow::Borrowed(&self.config.hostname),
-            Cow::Borrowed(&self.config.image),
+            Cow::Borrowed(&self.shell),
+            Cow::Borrowed(&self.hostname),
+            Cow::Borrowed(&self.distro),
+            Cow::Borrowed(&self.image),
         ]
     }
     fn headers() -> Vec<Cow<'static, str>> {
-        ["name", "shell", "hostname", "image"]
+        ["name", "shell", "hostname", "distro", "image"]
             .into_iter()
             .map(Cow::from)
             .collect()
     }
 }
 
-pub fn list() -> eyre::Result<()> {
-    let home = env::var("HOME").wrap_err("Could not find current home")?;
-    let storage = format!("{home}/{STORAGE}/images");
+pub fn list(args: List) -> eyre::Result<()> {
+    if let Some(name) = &args.name {
+        let names = list_dir(&rootfs(name)?, &args.path)?;
+        match args.format {
+            Format::Json => println!("{}", serde_json::to_string_pretty(&names)?),
+            Format::Table | Format::Csv => names.iter().for_each(|name| println!("{name}")),
+        }
+        return Ok(());
+    }
+    let rows = summaries()?;
+    match args.format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+        Format::Csv => print!("{}", csv(&rows)),
+        Format::Table if rows.is_empty() => help(),
+        Format::Table => {
+            let mut table = Table::new(rows);
+            let table = table.with(Style::modern());
+            print!("{table}");
+        }
+    }
+    Ok(())
+}
+
+fn summaries() -> eyre::Result<Vec<ToolboxSummary>> {
+    let storage = storage_root()?.join("images");
     let paths = match std::fs::read_dir(storage) {
         Ok(paths) => paths,
-        Err(_) => return Ok(help()),
+        Err(_) => return Ok(Vec::new()),
     };
-    let rows: Vec<Row> = paths
+    // Hidden names are the staging toolboxes of replace and the files kept next to a rootfs
+    Ok(paths
         .filter_map(|p| p.ok()?.file_name().into_string().ok())
-        .filter_map(|p| Row::new(p).ok())
-        .collect();
-    if rows.is_empty() {
-        help();
+        .filter(|name| !name.starts_with('.'))
+        .filter_map(|p| ToolboxSummary::new(p).ok())
+        .collect())
+}
+
+/// Rows of the table as CSV, with the headers on the first line
+pub fn csv<T: Tabled>(rows: &[T]) -> String {
+    let mut csv = T::headers().join(",");
+    csv.push('\n');
+    for row in rows {
+        let fields = row.fields();
+        let fields: Vec<_> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes the field if it contains a separator, doubling the quotes inside of it
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
     } else {
-        let mut table = Table::new(rows);
-        let table = table.with(Style::modern());
-        print!("{table}");
+        Cow::Borrowed(field)
     }
-    Ok(())
 }
 
 fn help() {
@@ -4,7 +4,9 @@
 
 #![forbid(unsafe_code)]
 
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Parser, Subcommand};
 use color_eyre::eyre;
 
 use unbox::*;
@@ -13,23 +15,50 @@ use unbox::*;
 #[derive(Parser, PartialEq, Eq, Debug)]
 #[clap(version, about)]
 struct UnBox {
+    #[clap(short, long, global = true, value_parser)]
+    /// Print debugging information
+    verbose: bool,
+    #[clap(long, global = true, value_parser)]
+    /// Directory where the toolboxes are stored, overriding UNBOX_ROOT and the global config
+    storage_root: Option<PathBuf>,
     #[clap(subcommand)]
     subcommands: Subcommands,
 }
 
+// It is parsed once at startup, so the size of the biggest subcommand does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, PartialEq, Eq, Debug)]
 enum Subcommands {
     Create(create::Create),
-    #[clap(alias = "cfg")]
+    #[clap(aliases = &["cfg", "config"])]
     Configure(config::Configure),
     Enter(run::Enter),
     Run(run::Run),
+    Export(export::Export),
+    Probe(probe::Probe),
+    Chown(chown::Chown),
+    Clone(clone::CloneToolbox),
     #[clap(alias = "rm")]
     Remove(remove::Remove),
     #[clap(alias = "ls")]
     List(list::List),
+    Cat(files::Cat),
+    Ps(sessions::Ps),
+    Kill(sessions::Kill),
+    Repair(repair::Repair),
+    Doctor(doctor::Doctor),
+    #[clap(name = "nstest")]
+    NsTest(doctor::NsTest),
+    Alias(alias::Alias),
+    Version(version::Version),
     #[clap(hide = true)]
     SetMappings(namespaces::SetMappings),
+    #[clap(hide = true)]
+    RunHooks(hooks::RunHooks),
+    #[clap(hide = true)]
+    PtyExec(tty::PtyExec),
+    #[clap(external_subcommand)]
+    External(Vec<String>),
 }
 
 fn main() -> eyre::Result<()> {
@@ -37,16 +66,56 @@ fn main() -> eyre::Result<()> {
     color_eyre::config::HookBuilder::default()
         .display_env_section(false)
         .install()?;
-    config::setup()?;
     let cmd = UnBox::parse();
+    verbosity::set_verbose(cmd.verbose);
+    if let Some(root) = &cmd.storage_root {
+        config::set_storage_root(root);
+    }
+    config::setup()?;
 
     match cmd.subcommands {
-        Subcommands::Create(args) => create::create(args),
+        Subcommands::Create(args) => match create::create(args) {
+            // Automation can tell running out of time apart from a failure by the exit code
+            Err(e) if deadline::is_timeout(&e) => {
+                eprintln!("Error: {e:?}");
+                std::process::exit(deadline::EXIT_CODE)
+            }
+            result => result,
+        },
         Subcommands::Enter(args) => run::nsexec(run::Execute::Enter(args)),
         Subcommands::Run(args) => run::nsexec(run::Execute::Run(args)),
+        Subcommands::Export(args) => export::export(args),
+        Subcommands::Probe(args) => probe::probe(args),
+        Subcommands::Chown(args) => chown::chown(args),
+        Subcommands::Clone(args) => clone::clone(args),
         Subcommands::Configure(args) => config::configure(args),
         Subcommands::Remove(args) => remove::remove(args),
-        Subcommands::List(_) => list::list(),
+        Subcommands::List(args) => list::list(args),
+        Subcommands::Cat(args) => files::cat(args),
+        Subcommands::Ps(args) => sessions::ps(args),
+        Subcommands::Kill(args) => sessions::kill(args),
+        Subcommands::Repair(args) => repair::repair(args),
+        Subcommands::Doctor(args) => doctor::doctor(args),
+        Subcommands::NsTest(args) => doctor::nstest(args),
+        Subcommands::Alias(args) => alias::alias(args, &subcommand_names()),
+        Subcommands::Version(args) => version::version(args),
         Subcommands::SetMappings(args) => namespaces::set_mappings(args),
+        Subcommands::RunHooks(args) => hooks::run_hooks(args),
+        Subcommands::PtyExec(args) => tty::pty_exec(args),
+        Subcommands::External(mut args) => {
+            let name = args.remove(0);
+            alias::run_alias(&name, args)
+        }
+    }
+}
+
+/// Names and aliases of every subcommand, which can not be used as the name of an alias
+fn subcommand_names() -> Vec<String> {
+    let cmd = UnBox::command();
+    let mut names = vec!["help".to_string()];
+    for sub in cmd.get_subcommands() {
+        names.push(sub.get_name().to_string());
+        names.extend(sub.get_all_aliases().map(String::from));
     }
+    names
 }
@@ -3,20 +3,25 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::fmt::Display;
-use std::fs::{read_link, symlink_metadata};
+use std::fs::{create_dir_all, read_link, symlink_metadata, File};
 use std::io::Write;
 use std::os::unix::prelude::CommandExt;
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
 
 use clap::Args;
 use color_eyre::eyre;
 use color_eyre::eyre::WrapErr;
 use nix::sched::{unshare, CloneFlags};
-use nix::unistd::{pivot_root, sethostname};
+use nix::unistd::{chroot, pivot_root, sethostname};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::ffi::{OsStr, OsString};
 
-use crate::config::MountInfo;
+use crate::config::{Config, MountInfo, Overlay};
+use crate::doctor::covers;
+use crate::sessions::Record;
+use crate::verbosity::{debug, is_verbose, warn, Verbosity};
 
 // Setup the uid and gid mappings inside the namespace
 /// Internal subcommand. Should not be used directly
@@ -25,42 +30,64 @@ pub struct SetMappings {
     /// Command arguments
     #[clap(value_parser)]
     args: Vec<String>,
+    /// Group mappings, when they differ from the user ones in `args`
+    #[clap(long, value_parser, number_of_values = 3)]
+    gid_map: Vec<String>,
 }
 
 pub fn set_mappings(args: SetMappings) -> eyre::Result<()> {
     let mut input = String::with_capacity(7);
     // We do not care about the input, only to check that we can continue
     let _ = std::io::stdin().read_line(&mut input);
+    let gids = if args.gid_map.is_empty() {
+        args.args.clone()
+    } else {
+        [&args.args[..1], &args.gid_map].concat()
+    };
     std::thread::scope(|s| {
-        s.spawn(|| {
-            let mut uid_map =
-                match spawn("newuidmap", &args.args).wrap_err("Failure to write uid_map") {
-                    Ok(child) => child,
-                    Err(e) => {
-                        eprintln!("{e}");
-                        std::process::exit(1);
-                    }
-                };
-            uid_map.wait().expect("Failure to wait for uid_map");
-        });
-        s.spawn(|| {
-            let mut gid_map =
-                match spawn("newgidmap", &args.args).wrap_err("Failure to write gid_map") {
-                    Ok(child) => child,
-                    Err(e) => {
-                        eprintln!("{e}");
-                        std::process::exit(1);
-                    }
-                };
-            gid_map.wait().expect("Failure to wait for gid_map");
-        });
+        s.spawn(|| write_map("newuidmap", "uid_map", &args.args));
+        s.spawn(|| write_map("newgidmap", "gid_map", &gids));
     });
 
     Ok(())
 }
 
+/// Writes the mappings with the given helper, exiting if it fails because the parent would
+/// continue with an unusable namespace
+fn write_map(helper: &str, map: &str, args: &[String]) {
+    let (pid, mappings) = args.split_first().expect("the pid is always given");
+    let path = format!("/proc/{pid}/{map}");
+    let content: String = mappings
+        .chunks(3)
+        .map(|map| format!("{}\n", map.join(" ")))
+        .collect();
+    debug(format_args!(
+        "{helper} writing {path}:\n{}",
+        content.trim_end()
+    ));
+    let status = spawn(helper, args).and_then(|mut child| {
+        child
+            .wait()
+            .wrap_err(format!("Failure to wait for {helper}"))
+    });
+    let error = match status {
+        Ok(status) if status.success() => return,
+        Ok(status) => eyre::eyre!("{helper} exited with {status}"),
+        Err(e) => e,
+    };
+    eprintln!(
+        "Failure to write {path} with:\n{}\n{error:?}",
+        content.trim_end()
+    );
+    std::process::exit(1);
+}
+
 pub struct Namespace<T> {
     mapper: Child,
+    /// FUSE daemon serving the squashfs image of the rootfs, if it has one
+    fuse: Option<Child>,
+    /// Session of the toolbox, removed when the command exits if unbox is still running then
+    session: Option<(Record, Verbosity)>,
     typestate: std::marker::PhantomData<T>,
 }
 
@@ -71,35 +98,76 @@ pub struct Pivoter;
 pub struct Toolbox;
 
 impl<T> Namespace<T> {
-    pub fn wait(&mut self) {
-        self.mapper.wait().expect("interrupted");
+    /// Blocks until the helper has written the mappings, which is needed before using the ids
+    /// of the namespace. It fails if the helper did, since nothing works in a namespace
+    /// without mappings; the helper has already printed why, with what it tried to write.
+    pub fn wait(&mut self) -> eyre::Result<()> {
+        let status = self
+            .mapper
+            .wait()
+            .wrap_err("Could not wait for the mappings of the namespace")?;
+        eyre::ensure!(
+            status.success(),
+            "The mappings of the namespace could not be written, see above"
+        );
+        Ok(())
+    }
+
+    /// Same as [`Namespace::wait`] without blocking, it is `None` while the mappings are still
+    /// being written
+    pub fn status(&mut self) -> eyre::Result<Option<ExitStatus>> {
+        self.mapper
+            .try_wait()
+            .wrap_err("Could not check the mappings of the namespace")
     }
 }
 
 impl Namespace<Setup> {
     pub fn start(flags: CloneFlags, mappings: &[Mapping<'_>]) -> eyre::Result<Namespace<Pivoter>> {
+        Namespace::start_split(flags, mappings, &[])
+    }
+
+    /// Same as [`Namespace::start`] with different mappings for the groups, unless `gids` is
+    /// empty
+    pub fn start_split(
+        flags: CloneFlags,
+        uids: &[Mapping<'_>],
+        gids: &[Mapping<'_>],
+    ) -> eyre::Result<Namespace<Pivoter>> {
         let pid = std::process::id().to_string();
-        let argv = mappings_argv(&pid, mappings);
+        let argv = mappings_argv(&pid, uids, gids);
         let child = self_spawn(&argv).wrap_err("Could not spawn child to set up mappings")?;
+        debug(format_args!(
+            "unsharing {flags:?} in {pid}, mappings set up by {}",
+            child.id()
+        ));
 
-        unshare(flags).wrap_err("Could not change namespace")?;
+        unshare(flags).wrap_err(format!("Could not change namespace with {flags:?}"))?;
 
         writeln!(&mut child.stdin.as_ref().unwrap(), "unshare").wrap_err("communication failed")?;
         let next = Namespace {
             mapper: child,
+            fuse: None,
+            session: None,
             typestate: std::marker::PhantomData,
         };
         Ok(next)
     }
 }
 
-fn mappings_argv<'a>(pid: &'a str, mappings: &[Mapping<'a>]) -> Vec<&'a str> {
+fn mappings_argv<'a>(pid: &'a str, mappings: &[Mapping<'a>], gids: &[Mapping<'a>]) -> Vec<&'a str> {
     let subcmd = "set-mappings";
     let mut args = mappings
         .iter()
         .flat_map(|map| [map.inside, map.outside, map.len].into_iter())
         .collect::<Vec<&str>>();
-    let mut argv = Vec::with_capacity(args.len() + 1);
+    for map in gids {
+        args.extend(["--gid-map", map.inside, map.outside, map.len]);
+    }
+    let mut argv = Vec::with_capacity(args.len() + 3);
+    if is_verbose() {
+        argv.push("--verbose");
+    }
     argv.push(subcmd);
     argv.push(pid);
     argv.append(&mut args);
@@ -118,12 +186,96 @@ where
 }
 
 impl Namespace<Pivoter> {
-    pub fn pivot(self, new_root: &OsStr, old_root: &OsStr) -> eyre::Result<Namespace<Toolbox>> {
+    /// Mounts the squashfs image read-only over the root with `squashfuse`, since the kernel
+    /// does not allow mounting squashfs from a user namespace. The daemon is stopped once
+    /// the command of the toolbox exits.
+    pub fn squashfs(&mut self, image: &str, root: &str) -> eyre::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+        use std::time::{Duration, Instant};
+
+        // The daemon needs the mappings to access the files of the image
+        self.wait()?;
+        let parent = Path::new(root).parent().unwrap_or_else(|| Path::new("/"));
+        let unmounted = symlink_metadata(parent)?.dev();
+        let mut fuse = Command::new("squashfuse")
+            .args(["-f", "-o", "ro", image, root])
+            .stdin(Stdio::null())
+            .spawn()
+            .wrap_err("Could not execute squashfuse, is it installed?")?;
+        // The daemon does not signal when the image is mounted, so the root is polled until
+        // it is on another device than its parent
+        let start = Instant::now();
+        while symlink_metadata(root)?.dev() == unmounted {
+            if let Some(status) = fuse.try_wait()? {
+                eyre::bail!("squashfuse exited with {status} before mounting the image");
+            }
+            if start.elapsed() > Duration::from_secs(10) {
+                let _ = fuse.kill();
+                eyre::bail!("squashfuse did not mount the image in time");
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        self.fuse = Some(fuse);
+        Ok(())
+    }
+
+    /// Mounts the overlay of the rootfs of the base and the changes of the toolbox over the
+    /// root. The `userxattr` option lets overlayfs work inside of a user namespace, which
+    /// needs Linux 5.11 or newer.
+    pub fn overlay(&mut self, overlay: &Overlay, root: &str) -> eyre::Result<()> {
+        use nix::mount::MsFlags;
+
+        self.wait()?;
+        let options = format!(
+            "lowerdir={},upperdir={},workdir={},userxattr",
+            overlay.lower, overlay.upper, overlay.work
+        );
+        nix::mount::mount::<str, str, str, str>(
+            Some("overlay"),
+            root,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(&options),
+        )
+        .wrap_err(format!(
+            "Could not mount the overlay over the rootfs of {}, it needs Linux 5.11 or newer",
+            overlay.base
+        ))
+    }
+
+    /// Makes `new_root` the root, keeping the old one at `old_root`. With `fallback` it uses
+    /// chroot if pivot_root fails, like when the parent of the rootfs is a shared mount.
+    pub fn pivot(
+        self,
+        new_root: &OsStr,
+        old_root: &OsStr,
+        fallback: bool,
+        verbosity: Verbosity,
+    ) -> eyre::Result<Namespace<Toolbox>> {
         // We have to bind mount the new root to itself because it is part of the old root
         bind_mount(new_root, new_root)?;
-        pivot_root(new_root, old_root).wrap_err("Could not pivot into the new root")?;
+        match pivot_root(new_root, old_root) {
+            Ok(()) => {}
+            Err(e) if fallback => {
+                warn(
+                    verbosity,
+                    format_args!(
+                        "Could not pivot into the new root ({e}), using chroot instead. Unlike \
+                        pivot_root, it can be escaped by the processes running as root in the \
+                        toolbox, use --no-fallback to fail instead"
+                    ),
+                );
+                // The old root is still reachable at the same path as after pivoting
+                bind_mount("/".as_ref(), old_root)?;
+                chroot(new_root).wrap_err("Could not chroot into the new root")?;
+                std::env::set_current_dir("/").wrap_err("Could not change into the new root")?;
+            }
+            Err(e) => return Err(e).wrap_err("Could not pivot into the new root"),
+        }
         let next = Namespace {
             mapper: self.mapper,
+            fuse: self.fuse,
+            session: self.session,
             typestate: std::marker::PhantomData,
         };
         Ok(next)
@@ -140,17 +292,183 @@ impl Namespace<Toolbox> {
             .try_for_each(|m| bind_mount(&m.0, &m.1))
     }
 
+    /// Mounts a new proc filesystem, which is only allowed if the namespace has its own pids,
+    /// otherwise the one of the host is bind mounted
+    pub fn proc(&self) -> eyre::Result<()> {
+        use nix::mount::MsFlags;
+        let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+        match nix::mount::mount::<str, str, str, str>(
+            Some("proc"),
+            "/proc",
+            Some("proc"),
+            flags,
+            None,
+        ) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                debug(format_args!(
+                    "mounting proc failed ({e}), binding the host one"
+                ));
+                bind_mount("/host/proc".as_ref(), "/proc".as_ref())
+            }
+        }
+    }
+
+    /// Bind mounts the sysfs of the host as read-only. The flags of the host mount are locked
+    /// in a user namespace, so the remount has to keep them or it is refused.
+    pub fn sys(&self) -> eyre::Result<()> {
+        use nix::mount::MsFlags;
+        use nix::sys::statvfs::{statvfs, FsFlags};
+        bind_mount("/host/sys".as_ref(), "/sys".as_ref())?;
+        let locked = [
+            (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+            (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+            (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+            (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+            (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+            (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+        ];
+        let current = statvfs("/sys")
+            .wrap_err("Could not read the mount flags of /sys")?
+            .flags();
+        let flags = locked
+            .into_iter()
+            .filter(|(st, _)| current.contains(*st))
+            .fold(
+                MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+                |flags, (_, ms)| flags | ms,
+            );
+        match nix::mount::mount::<str, str, str, str>(None, "/sys", None, flags, None) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                debug(format_args!(
+                    "remounting /sys as read-only failed ({e}), leaving it read-write"
+                ));
+                Ok(())
+            }
+        }
+    }
+
+    /// Bind mounts a directory of the host at the same path and changes into it
+    pub fn workdir(&self, dir: &Path) -> eyre::Result<()> {
+        let source = Path::new("/host").join(dir.strip_prefix("/").unwrap_or(dir));
+        bind_mount(source.as_os_str(), dir.as_os_str())?;
+        std::env::set_current_dir(dir).wrap_err(format!("Could not change into {dir:?}"))
+    }
+
+    /// Bind mounts a device of the host at the same path, creating its mountpoint if needed
+    pub fn device(&self, device: &Path) -> eyre::Result<()> {
+        let source = Path::new("/host").join(device.strip_prefix("/").unwrap_or(device));
+        let meta = std::fs::metadata(&source)
+            .wrap_err(format!("The device {device:?} does not exist on the host"))?;
+        if symlink_metadata(device).is_err() {
+            let created = if meta.is_dir() {
+                create_dir_all(device)
+            } else {
+                device
+                    .parent()
+                    .map_or(Ok(()), create_dir_all)
+                    .and_then(|_| File::create(device).map(drop))
+            };
+            created.wrap_err(format!("Could not create the mountpoint of {device:?}"))?;
+        }
+        bind_mount(source.as_os_str(), device.as_os_str())
+    }
+
     pub fn hostname(&self, name: &str) -> eyre::Result<()> {
         sethostname(name).wrap_err("Could not change the hostname")
     }
 
-    pub fn spawn<S>(&mut self, cmd: S, args: &[S]) -> eyre::Result<()>
+    /// Changes to the user, resolved with the passwd and group files of the toolbox
+    pub fn switch_user(&self, user: &str) -> eyre::Result<()> {
+        use nix::unistd::{setgid, setgroups, setuid, Gid, Uid};
+        let credentials = crate::user::resolve(Path::new("/"), user)?;
+        debug(format_args!("switching to {user}: {credentials:?}"));
+        let gid = Gid::from_raw(credentials.gid);
+        setgroups(&[gid]).wrap_err("Could not drop the supplementary groups")?;
+        setgid(gid).wrap_err(format!("Could not change the group to {gid}"))?;
+        setuid(Uid::from_raw(credentials.uid))
+            .wrap_err(format!("Could not change the user to {user}"))?;
+        if let Some(home) = credentials.home {
+            std::env::set_var("HOME", home);
+        }
+        Ok(())
+    }
+
+    /// Executes the command, stdin is only inherited if `interactive` is set
+    pub fn spawn<S>(&mut self, cmd: S, args: &[S], interactive: bool) -> eyre::Result<()>
     where
         S: AsRef<OsStr>,
     {
-        self.wait();
-        Command::new(cmd).args(args).exec();
-        eyre::bail!("Could not execute the requested command")
+        let mut cmd = Command::new(cmd);
+        if !interactive {
+            cmd.stdin(Stdio::null());
+        }
+        cmd.args(args);
+        self.execute(cmd)
+    }
+
+    /// Executes the shell with the arguments, as a login shell if `login` is set so it reads
+    /// the profile files
+    pub fn spawn_shell(&mut self, shell: &str, args: &[String], login: bool) -> eyre::Result<()> {
+        let mut cmd = Command::new(shell);
+        cmd.args(args);
+        if login {
+            // Shells check for a leading dash in their name, as it is done by login
+            let name = Path::new(shell).file_name().unwrap_or(shell.as_ref());
+            let mut arg0 = OsString::from("-");
+            arg0.push(name);
+            cmd.arg0(arg0);
+        }
+        self.execute(cmd)
+    }
+
+    fn execute(&mut self, mut cmd: Command) -> eyre::Result<()> {
+        self.wait()?;
+        if self.fuse.is_none() {
+            let err = cmd.exec();
+            return Err(err).wrap_err("Could not execute the requested command");
+        }
+        // unbox has to outlive the command to stop the FUSE daemon, so it must not be killed
+        // before it by the signals meant for the toolbox
+        let status = cmd
+            .spawn()
+            .wrap_err("Could not execute the requested command")
+            .and_then(|mut child| {
+                crate::tty::forward_signals(child.id())?;
+                child.wait().wrap_err("Could not wait for the command")
+            });
+        self.exit(status.map(|status| status.code().unwrap_or(1)))
+    }
+
+    pub fn spawn_in_pty<S>(&mut self, cmd: S, args: &[S], interactive: bool) -> eyre::Result<()>
+    where
+        S: AsRef<OsStr>,
+    {
+        self.wait()?;
+        let code = crate::tty::run_in_pty(cmd, args, interactive);
+        self.exit(code)
+    }
+
+    /// Removes the record of the session when unbox waits for the command to exit, it is
+    /// left for `ps` to prune when unbox is replaced by the command
+    pub fn on_exit(&mut self, session: Record, verbosity: Verbosity) {
+        self.session = Some((session, verbosity));
+    }
+
+    /// Stops the FUSE daemon, if there is one, removes the record of the session and exits
+    /// with the code of the command
+    fn exit(&mut self, code: eyre::Result<i32>) -> eyre::Result<()> {
+        if let Some(mut fuse) = self.fuse.take() {
+            let _ = fuse.kill();
+            let _ = fuse.wait();
+        }
+        if let Some((session, verbosity)) = self.session.take() {
+            if let Err(e) = session.remove() {
+                warn(verbosity, format_args!("{e}"));
+            }
+        }
+        std::process::exit(code?)
     }
 }
 
@@ -160,6 +478,140 @@ pub struct Mapping<'a> {
     pub len: &'a str,
 }
 
+impl<'a> From<&'a [String; 3]> for Mapping<'a> {
+    fn from(map: &'a [String; 3]) -> Self {
+        Mapping {
+            inside: &map[0],
+            outside: &map[1],
+            len: &map[2],
+        }
+    }
+}
+
+/// Range of ids inside of a user namespace mapped to the ones of the host, given as
+/// `INSIDE:OUTSIDE:LEN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct IdMap {
+    pub inside: u32,
+    pub outside: u32,
+    pub len: u32,
+}
+
+impl IdMap {
+    pub fn strings(&self) -> [String; 3] {
+        [self.inside, self.outside, self.len].map(|id| id.to_string())
+    }
+}
+
+pub fn parse_id_map(map: &str) -> Result<IdMap, String> {
+    let ids: Vec<_> = map.split(':').map(str::parse::<u32>).collect();
+    match ids[..] {
+        [Ok(inside), Ok(outside), Ok(len)] if len > 0 => Ok(IdMap {
+            inside,
+            outside,
+            len,
+        }),
+        _ => Err(format!("{map} is not a mapping like INSIDE:OUTSIDE:LEN")),
+    }
+}
+
+/// Checks that neither the ids inside nor the ones outside of the mappings overlap, and that
+/// the ones outside are either the id of the user or given to them in the subordinate ids
+/// file, which has the `contents`
+pub fn check_id_maps(maps: &[IdMap], user: &str, id: u32, contents: &str) -> eyre::Result<()> {
+    for (side, start) in [
+        ("inside", (|m: &IdMap| m.inside) as fn(&IdMap) -> u32),
+        ("outside", |m: &IdMap| m.outside),
+    ] {
+        let mut ranges: Vec<_> = maps
+            .iter()
+            .map(|m| (start(m) as u64, start(m) as u64 + m.len as u64))
+            .collect();
+        ranges.sort_unstable();
+        for pair in ranges.windows(2) {
+            eyre::ensure!(
+                pair[0].1 <= pair[1].0,
+                "The ids {side} of the mappings overlap, from {} to {}",
+                pair[1].0,
+                pair[0].1.min(pair[1].1) - 1
+            );
+        }
+    }
+    for map in maps {
+        let own = map.outside == id && map.len == 1;
+        eyre::ensure!(
+            own || covers(contents, user, id, map.outside as u64, map.len as u64),
+            "The ids {}-{} are neither {id} nor subordinate ids of {user}",
+            map.outside,
+            map.outside as u64 + map.len as u64 - 1
+        );
+    }
+    Ok(())
+}
+
+/// Starts the namespace of a toolbox with the mappings of its config, see [`Mappings`]
+pub fn start_toolbox(flags: CloneFlags, config: &Config) -> eyre::Result<Namespace<Pivoter>> {
+    Mappings::new(&config.uid_map, &config.gid_map).start(flags)
+}
+
+/// Mappings of a toolbox given to the helpers: the ones of [`toolbox_mappings`] if no user
+/// mappings are given, and the groups get the user mappings if they have none
+pub struct Mappings {
+    uids: Vec<[String; 3]>,
+    gids: Vec<[String; 3]>,
+}
+
+impl Mappings {
+    pub fn new(uid_map: &[IdMap], gid_map: &[IdMap]) -> Self {
+        let uids = if uid_map.is_empty() {
+            let uid = users::get_current_uid().to_string();
+            toolbox_mappings(&uid)
+                .iter()
+                .map(|map| [map.inside, map.outside, map.len].map(String::from))
+                .collect()
+        } else {
+            uid_map.iter().map(IdMap::strings).collect()
+        };
+        let gids = gid_map.iter().map(IdMap::strings).collect();
+        Mappings { uids, gids }
+    }
+
+    pub fn uids(&self) -> Vec<Mapping<'_>> {
+        self.uids.iter().map(Mapping::from).collect()
+    }
+
+    /// Empty if the groups get the user mappings
+    pub fn gids(&self) -> Vec<Mapping<'_>> {
+        self.gids.iter().map(Mapping::from).collect()
+    }
+
+    pub fn start(&self, flags: CloneFlags) -> eyre::Result<Namespace<Pivoter>> {
+        Namespace::start_split(flags, &self.uids(), &self.gids())
+    }
+}
+
+/// Mappings used inside of a toolbox: root is the current user and the rest of the ids
+/// are subordinate ones
+pub fn toolbox_mappings(uid: &str) -> [Mapping<'_>; 2] {
+    [
+        Mapping {
+            inside: "0",
+            outside: uid,
+            len: "1",
+        },
+        Mapping {
+            inside: "1",
+            outside: SUBORDINATE_START,
+            len: SUBORDINATE_COUNT,
+        },
+    ]
+}
+
+/// Range of subordinate ids of the host that the ids from 1 of a toolbox are mapped to, the
+/// user needs it in /etc/subuid and /etc/subgid
+pub const SUBORDINATE_START: &str = "100000";
+pub const SUBORDINATE_COUNT: &str = "65536";
+
 impl Display for Mapping<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{} {} {}", self.inside, self.outside, self.len)
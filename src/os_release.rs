@@ -0,0 +1,62 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt::Display;
+use std::path::Path;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::parse_env_file;
+use crate::unpack::resolve_in_root;
+
+/// Files looked for in the rootfs, the second one is used when the first is missing
+const OS_RELEASE: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+/// Distribution of a rootfs, as described by its os-release file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Distro {
+    /// Lowercase identifier, like `fedora` or `ubuntu`
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Display for Distro {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {version}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Parses the `KEY=VALUE` lines of an os-release file. `ID` and `NAME` default to `linux` and
+/// `Linux` like the specification says, while the version is `VERSION_ID`, or `BUILD_ID` for
+/// rolling distributions like Arch. Malformed files give `None`.
+pub fn parse(contents: &str) -> Option<Distro> {
+    let fields = parse_env_file(contents).ok()?;
+    let field = |key: &str| {
+        fields
+            .iter()
+            .rev()
+            .find(|(k, v)| k == key && !v.is_empty())
+            .map(|(_, v)| v.clone())
+    };
+    Some(Distro {
+        id: field("ID").unwrap_or_else(|| "linux".into()),
+        name: field("NAME").unwrap_or_else(|| "Linux".into()),
+        version: field("VERSION_ID").or_else(|| field("BUILD_ID")),
+    })
+}
+
+/// Reads the distribution of the rootfs, following the symlinks inside of it since
+/// `/etc/os-release` is usually one to `/usr/lib/os-release`
+pub fn read(root: &Path) -> Option<Distro> {
+    OS_RELEASE.iter().find_map(|file| {
+        let path = resolve_in_root(root, file).ok()?;
+        parse(&std::fs::read_to_string(path).ok()?)
+    })
+}
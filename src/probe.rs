@@ -0,0 +1,223 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use serde::Serialize;
+use tar::{Archive, EntryType};
+
+use crate::archive::{decoder, Compression, DecoderOptions};
+use crate::engine::{
+    get_image, image_config, resolve_image, CustomEngine, Engine, EngineCommands, ImageConfig,
+    DEFAULT_TAG,
+};
+use crate::progress::{verbosity, Output, ProgressSink, Spinner};
+use crate::unpack::{is_zip, sanitize, MAX_LINKS};
+use crate::verbosity::debug;
+
+/// Report what an image or a tarball contains without creating a toolbox
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Probe {
+    #[clap(short, long, value_parser, required_unless_present = "image")]
+    /// Path to the tarball, or `-` to read it from stdin
+    pub tar: Option<PathBuf>,
+    #[clap(short, long, value_parser, conflicts_with = "tar")]
+    /// Url of the OCI image, exported by the engine to a temporary tarball
+    pub image: Option<String>,
+    #[clap(short, long, env = "UNBOX_ENGINE", value_parser)]
+    /// OCI engine to export the image
+    pub engine: Option<Engine>,
+    #[clap(flatten)]
+    pub custom_engine: CustomEngine,
+    #[clap(long, value_parser, default_value = "text")]
+    /// Format of the report
+    pub output: Output,
+}
+
+/// Shells looked for in the rootfs, the same candidates as the default shell of a toolbox
+const SHELLS: [&str; 5] = [
+    "/bin/sh",
+    "/bin/bash",
+    "/bin/ash",
+    "/bin/zsh",
+    "/usr/bin/fish",
+];
+
+/// What a rootfs tarball contains, and how the image it comes from is run
+#[derive(Debug, Serialize)]
+pub struct ProbeReport {
+    pub compression: Compression,
+    /// Number of entries of the tarball
+    pub entries: u64,
+    /// Size of the contents of the entries, once uncompressed
+    pub bytes: u64,
+    /// Shells found in the rootfs, following the symlinks inside of it
+    pub shells: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<ImageConfig>,
+}
+
+impl ProbeReport {
+    fn summary(&self) -> String {
+        let mut lines = vec![
+            format!(
+                "Compression: {}",
+                format!("{:?}", self.compression).to_lowercase()
+            ),
+            format!("Entries: {}", self.entries),
+            format!("Size: {:.1} MiB", self.bytes as f64 / (1024.0 * 1024.0)),
+            if self.shells.is_empty() {
+                "Shells: none".to_string()
+            } else {
+                format!("Shells: {}", self.shells.join(", "))
+            },
+        ];
+        if let Some(config) = &self.config {
+            let join = |args: &Option<Vec<String>>| args.as_ref().map(|args| args.join(" "));
+            let fields = [
+                ("Entrypoint", join(&config.entrypoint)),
+                ("Cmd", join(&config.cmd)),
+                ("Workdir", config.workdir.clone()),
+            ];
+            for (name, value) in fields {
+                if let Some(value) = value {
+                    lines.push(format!("{name}: {value}"));
+                }
+            }
+            for env in config.env.iter().flatten() {
+                lines.push(format!("Env: {env}"));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn probe(args: Probe) -> eyre::Result<()> {
+    let verbosity = verbosity(false, false, args.output);
+    let spinner = Spinner::new(verbosity);
+    let report = match (&args.tar, &args.image) {
+        (Some(tar), _) => probe_tar(tar, &DecoderOptions::default())?,
+        (None, Some(oci)) => probe_image(&args, oci, &spinner)?,
+        (None, None) => unreachable!("clap requires a tarball or an image"),
+    };
+    spinner.on_finish();
+    match args.output {
+        Output::Text => println!("{}", report.summary()),
+        Output::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(())
+}
+
+/// Exports the image to a temporary tarball that is removed once it has been probed
+fn probe_image(args: &Probe, oci: &str, progress: &dyn ProgressSink) -> eyre::Result<ProbeReport> {
+    let engine = args
+        .engine
+        .ok_or_else(|| eyre::eyre!("A valid engine has not been provided"))?;
+    let engine = EngineCommands::new(engine, &args.custom_engine, true, false, false)?;
+    let oci = &resolve_image(&engine, oci, DEFAULT_TAG, false)?;
+    debug(format_args!("probing the image {oci}"));
+    let tar_file = format!("/tmp/unbox-probe-{}.tar", std::process::id());
+    let report = get_image(&engine, oci, &tar_file, progress).and_then(|_| {
+        progress.on_phase("Reading the rootfs");
+        probe_tar(Path::new(&tar_file), &DecoderOptions::default())
+    });
+    // The engine may have written part of it before failing
+    let _ = std::fs::remove_file(&tar_file);
+    let mut report = report?;
+    report.config = image_config(&engine, oci)?;
+    Ok(report)
+}
+
+/// Reads every entry of the tarball, or of stdin if the path is `-`, without unpacking any
+pub fn probe_tar(tar: &Path, options: &DecoderOptions) -> eyre::Result<ProbeReport> {
+    let reader: Box<dyn Read> = if tar == Path::new("-") {
+        Box::new(std::io::stdin())
+    } else {
+        eyre::ensure!(!is_zip(tar), "Only tarballs can be probed, not zip files");
+        Box::new(File::open(tar).wrap_err("Could not open the tar file")?)
+    };
+    let reader = BufReader::with_capacity(options.buffer, reader);
+    let (reader, compression) = decoder(reader, options).wrap_err("Could not read the tar file")?;
+    let mut archive = Archive::new(reader);
+    let mut entries = 0;
+    let mut bytes = 0;
+    let mut files = HashSet::new();
+    let mut links = HashMap::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let kind = entry.header().entry_type();
+        if kind.is_pax_global_extensions() {
+            continue;
+        }
+        entries += 1;
+        bytes += entry.size();
+        let path = sanitize(&entry.path()?);
+        match kind {
+            EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    links.insert(path, target.into_owned());
+                }
+            }
+            EntryType::Regular | EntryType::Link | EntryType::Continuous => {
+                files.insert(path);
+            }
+            _ => {}
+        }
+    }
+    let shells = SHELLS
+        .iter()
+        .filter(|shell| resolve(Path::new(shell), &links).is_some_and(|path| files.contains(&path)))
+        .map(|shell| shell.to_string())
+        .collect();
+    Ok(ProbeReport {
+        compression,
+        entries,
+        bytes,
+        shells,
+        config: None,
+    })
+}
+
+/// Resolves the path with the symlinks of the archive, like `resolve_in_root` does with the
+/// ones of an unpacked rootfs. It gives up on loops.
+fn resolve(path: &Path, links: &HashMap<PathBuf, PathBuf>) -> Option<PathBuf> {
+    let mut pending = stack(path);
+    let mut resolved = PathBuf::new();
+    let mut followed = 0;
+    while let Some(component) = pending.pop() {
+        if component == Component::ParentDir {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(component);
+        match links.get(&candidate) {
+            Some(target) => {
+                followed += 1;
+                if followed > MAX_LINKS {
+                    return None;
+                }
+                if target.is_absolute() {
+                    resolved.clear();
+                }
+                pending.extend(stack(target));
+            }
+            None => resolved = candidate,
+        }
+    }
+    Some(resolved)
+}
+
+/// Normal and parent components of the path in reverse order, to be used as a stack
+fn stack(path: &Path) -> Vec<Component<'_>> {
+    path.components()
+        .rev()
+        .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+        .collect()
+}
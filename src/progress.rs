@@ -0,0 +1,171 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::cell::Cell;
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::verbosity::{self, Verbosity};
+
+/// Format of the result printed by a command once it finishes
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum Output {
+    /// A summary for humans
+    #[default]
+    Text,
+    /// A JSON document for scripts, nothing else is printed to stdout
+    Json,
+}
+
+/// Resolves how much a command prints from its flags. When the output is meant for scripts
+/// nothing but the JSON document is printed, as with `--silent`.
+pub fn verbosity(quiet: bool, silent: bool, output: Output) -> Verbosity {
+    match output {
+        Output::Json => Verbosity::Silent,
+        Output::Text => Verbosity::from_flags(quiet, silent),
+    }
+}
+
+/// Receives progress events from long running operations like `create`
+///
+/// The CLI uses a [`Spinner`], but library consumers can provide their own implementation
+/// to receive the events in a structured way.
+pub trait ProgressSink {
+    /// A new phase of the operation has started
+    fn on_phase(&self, name: &str);
+    /// Bytes of the source processed so far, `total` is given when it is known up front
+    fn on_bytes(&self, _done: u64, _total: Option<u64>) {}
+    /// Number of archive entries unpacked so far
+    fn on_entries(&self, _done: u64) {}
+    /// Something unexpected happened but the operation can continue
+    fn on_warning(&self, msg: &str) {
+        verbosity::warn(Verbosity::default(), msg);
+    }
+    /// Information the user asked for, like the layers of an image
+    fn on_info(&self, msg: &str) {
+        eprintln!("{msg}");
+    }
+    /// The operation has finished, successfully or not
+    fn on_finish(&self) {}
+}
+
+/// Progress sink that ignores every event
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn on_phase(&self, _name: &str) {}
+    fn on_warning(&self, _msg: &str) {}
+    fn on_info(&self, _msg: &str) {}
+}
+
+/// Progress sink that only prints the warnings, for commands without progress to report,
+/// unless the verbosity hides them
+pub struct WarningsOnly(pub Verbosity);
+
+impl ProgressSink for WarningsOnly {
+    fn on_phase(&self, _name: &str) {}
+    fn on_warning(&self, msg: &str) {
+        verbosity::warn(self.0, msg);
+    }
+}
+
+/// Terminal spinner showing the name of the current phase, which becomes a bar with the
+/// throughput and the estimated time left when the total size of the phase is known
+///
+/// It only advances with the events, because a thread ticking it would stop the process
+/// from entering a new user namespace.
+pub struct Spinner {
+    bar: Option<ProgressBar>,
+    verbosity: Verbosity,
+    /// The bar is showing bytes instead of the spinner
+    bytes: Cell<bool>,
+}
+
+impl Spinner {
+    pub fn new(verbosity: Verbosity) -> Self {
+        let bar = verbosity
+            .shows_progress()
+            .then(|| ProgressBar::new_spinner().with_style(spinner_style()));
+        Spinner {
+            bar,
+            verbosity,
+            bytes: Cell::new(false),
+        }
+    }
+
+    fn tick(&self) {
+        // The terminal is only redrawn a few times per second, so this can be called often
+        if let Some(spinner) = &self.bar {
+            spinner.tick();
+        }
+    }
+}
+
+fn spinner_style() -> ProgressStyle {
+    ProgressStyle::default_spinner()
+        .template("{msg} {spinner}")
+        .expect("valid template")
+}
+
+fn bytes_style() -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template("{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}")
+        .expect("valid template")
+        .progress_chars("=> ")
+}
+
+impl ProgressSink for Spinner {
+    fn on_phase(&self, name: &str) {
+        if let Some(spinner) = &self.bar {
+            if self.bytes.replace(false) {
+                spinner.set_style(spinner_style());
+            }
+            spinner.set_message(name.to_string());
+        }
+    }
+
+    fn on_bytes(&self, done: u64, total: Option<u64>) {
+        let (spinner, total) = match (&self.bar, total) {
+            (Some(spinner), Some(total)) => (spinner, total),
+            _ => return self.tick(),
+        };
+        if !self.bytes.replace(true) {
+            spinner.set_style(bytes_style());
+            spinner.set_length(total);
+            spinner.reset_eta();
+        }
+        spinner.set_position(done);
+    }
+
+    fn on_entries(&self, _done: u64) {
+        self.tick();
+    }
+
+    fn on_warning(&self, msg: &str) {
+        match &self.bar {
+            Some(spinner) => spinner.suspend(|| verbosity::warn(self.verbosity, msg)),
+            None => verbosity::warn(self.verbosity, msg),
+        }
+    }
+
+    fn on_info(&self, msg: &str) {
+        match &self.bar {
+            Some(spinner) => spinner.suspend(|| eprintln!("{msg}")),
+            None => eprintln!("{msg}"),
+        }
+    }
+
+    fn on_finish(&self) {
+        if let Some(spinner) = &self.bar {
+            spinner.finish_and_clear();
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.on_finish();
+    }
+}
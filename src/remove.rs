@@ -2,16 +2,16 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::env;
 use std::fs::Permissions;
 use std::os::unix::prelude::PermissionsExt;
+use std::path::Path;
 
 use clap::Args;
 use color_eyre::eyre;
 use color_eyre::eyre::WrapErr;
 use walkdir::WalkDir;
 
-use crate::config::{Config, STORAGE};
+use crate::config::{meta_path, storage_root, Config};
 
 /// Remove a toolbox
 #[derive(Args, PartialEq, Eq, Debug)]
@@ -29,21 +29,55 @@ pub fn remove(args: Remove) -> eyre::Result<()> {
 }
 
 pub fn remove_one(name: String) -> eyre::Result<()> {
-    let home = env::var("HOME").wrap_err("Could not find current home")?;
-    let meta = format!("{home}/{}/meta/{}.toml", STORAGE, name);
+    let meta = meta_path(&name)?;
     let config =
         Config::read_or_new(&name).wrap_err("Could not get configuration for the toolbox")?;
-    for entry in WalkDir::new(&config.image)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
+    let dependents = dependents(&name)?;
+    eyre::ensure!(
+        dependents.is_empty(),
+        "{name} is the base of {}, remove them first",
+        dependents.join(", ")
+    );
+    make_writable(Path::new(&config.image));
+    // The error is ignored because if the file does not exist we do not need to remove it.
+    let _ = std::fs::remove_file(meta);
+    if let Some(squashfs) = &config.squashfs {
+        std::fs::remove_file(squashfs).wrap_err("Could not remove the squashfs image")?;
+    }
+    // Only the changes of the toolbox are removed, the rootfs of its base is kept
+    if let Some(overlay) = &config.overlay {
+        for layer in [&overlay.upper, &overlay.work] {
+            make_writable(Path::new(layer));
+            std::fs::remove_dir_all(layer).wrap_err("Could not remove the overlay layers")?;
+        }
+        if let Some(parent) = Path::new(&overlay.upper).parent() {
+            let _ = std::fs::remove_dir(parent);
+        }
+    }
+    std::fs::remove_dir_all(config.image).wrap_err("Could not remove the selected toolbox")
+}
+
+/// We change the permissions on directories to avoid errors on read-only directories
+pub fn make_writable(root: &Path) {
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
         let perms = Permissions::from_mode(0o777);
-        // We change the permissions on directories to avoid errors on read-only directories
         if entry.file_type().is_dir() {
             std::fs::set_permissions(entry.path(), perms).expect("we own the files");
         }
     }
-    // The error is ignored because if the file does not exist we do not need to remove it.
-    let _ = std::fs::remove_file(meta);
-    std::fs::remove_dir_all(config.image).wrap_err("Could not remove the selected toolbox")
+}
+
+/// Toolboxes created over the rootfs of `name`, which would stop working without it
+pub fn dependents(name: &str) -> eyre::Result<Vec<String>> {
+    let images = match std::fs::read_dir(storage_root()?.join("images")) {
+        Ok(images) => images,
+        Err(_) => return Ok(Vec::new()),
+    };
+    Ok(images
+        .filter_map(|image| image.ok()?.file_name().into_string().ok())
+        .filter(|other| match Config::read(other) {
+            Ok(config) => config.overlay.is_some_and(|overlay| overlay.base == name),
+            Err(_) => false,
+        })
+        .collect())
 }
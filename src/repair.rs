@@ -0,0 +1,49 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use color_eyre::eyre;
+
+use crate::config::Config;
+use crate::create::{current_username, probe_default_shell};
+use crate::progress::WarningsOnly;
+use crate::user;
+use crate::verbosity::Verbosity;
+
+/// Write a new configuration for a toolbox whose one is missing or corrupted
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Repair {
+    #[clap(value_parser)]
+    /// Name of the toolbox
+    pub name: String,
+    #[clap(long, value_parser)]
+    /// Replace the configuration even if it can be read
+    pub force: bool,
+}
+
+pub fn repair(args: Repair) -> eyre::Result<()> {
+    let name = &args.name;
+    eyre::ensure!(
+        args.force || Config::read(name).is_err(),
+        "The configuration of {name} is valid, use --force to replace it anyway"
+    );
+    let mut config = Config::new(name)?;
+    let root = PathBuf::from(&config.image);
+    // Every rootfs created by unbox has the mountpoint of the host
+    eyre::ensure!(
+        root.join("host").is_dir(),
+        "There is no rootfs for a toolbox named {name}"
+    );
+    // Only the warnings are shown, there is no progress to report
+    probe_default_shell(&root, &mut config, &WarningsOnly(Verbosity::default()))?;
+    let username = current_username();
+    if user::resolve(&root, &username).is_ok() {
+        config.user = Some(username);
+    }
+    config.write(name)?;
+    println!("Wrote a new configuration for {name}, its settings have been reset to the defaults");
+    Ok(())
+}
@@ -8,9 +8,17 @@ use color_eyre::eyre::WrapErr;
 use nix::sched::CloneFlags;
 use std::env;
 use std::ffi::OsString;
+use std::fs::create_dir_all;
+use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 
-use crate::config::Config;
-use crate::namespaces::{Mapping, Namespace};
+use crate::config::{Config, MountInfo};
+use crate::create::create_dirs;
+use crate::namespaces::start_toolbox;
+use crate::sessions::{Record, Session};
+use crate::unpack::resolve_in_root;
+use crate::verbosity::{debug, warn, Verbosity};
 
 pub enum Execute {
     Run(Run),
@@ -18,15 +26,44 @@ pub enum Execute {
 }
 
 /// Enter a toolbox
-#[derive(Args, PartialEq, Eq, Debug)]
+#[derive(Args, PartialEq, Eq, Debug, Default)]
 pub struct Enter {
     #[clap(value_parser)]
     /// Name of the toolbox
-    name: String,
+    pub name: String,
+    #[clap(long, value_parser)]
+    /// Mount the current directory at the same path inside of the toolbox and start there
+    pub mount_cwd: bool,
+    #[clap(long, value_parser, conflicts_with = "mount-cwd")]
+    /// Directory of the toolbox to start in, instead of the one where the current directory is
+    /// mounted
+    pub workdir: Option<PathBuf>,
+    #[clap(short, long, value_parser, conflicts_with = "no-login")]
+    /// Start a login shell, which reads the profile files, instead of the default of the toolbox
+    pub login: bool,
+    #[clap(long, value_parser)]
+    /// Start a plain interactive shell instead of the default of the toolbox
+    pub no_login: bool,
+    #[clap(flatten)]
+    pub limits: Limits,
+    #[clap(flatten)]
+    pub system: SystemMounts,
+    #[clap(flatten)]
+    pub user: UserSelection,
+}
+
+impl Enter {
+    fn login(&self, config: &Config) -> bool {
+        match (self.login, self.no_login) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => config.login,
+        }
+    }
 }
 
 /// Run a command in a toolbox
-#[derive(Args, PartialEq, Eq, Debug)]
+#[derive(Args, PartialEq, Eq, Debug, Default)]
 pub struct Run {
     #[clap(value_parser)]
     /// Name of the toolbox
@@ -37,41 +74,315 @@ pub struct Run {
     /// Command arguments
     #[clap(value_parser)]
     pub args: Vec<String>,
+    #[clap(short, long, value_parser)]
+    /// Connect stdin to the command
+    pub interactive: bool,
+    #[clap(short, long, value_parser)]
+    /// Run the command in a new pseudo-terminal, for full-screen programs
+    pub tty: bool,
+    #[clap(long, value_parser)]
+    /// Hide the warnings of unbox, like the ones of the post-create hooks of a quiet create.
+    /// The command prints as usual
+    pub quiet: bool,
+    #[clap(long, value_parser)]
+    /// Mount the current directory at the same path inside of the toolbox and start there
+    pub mount_cwd: bool,
+    #[clap(long, value_parser, conflicts_with = "mount-cwd")]
+    /// Directory of the toolbox to start in, instead of the one where the current directory is
+    /// mounted
+    pub workdir: Option<PathBuf>,
+    #[clap(flatten)]
+    pub limits: Limits,
+    #[clap(flatten)]
+    pub system: SystemMounts,
+    #[clap(flatten)]
+    pub user: UserSelection,
+}
+
+/// Resource limits applied through a transient systemd scope
+#[derive(Args, PartialEq, Eq, Debug, Default)]
+pub struct Limits {
+    #[clap(long, value_parser)]
+    /// Maximum memory available to the toolbox, like 512M or 2G (needs systemd)
+    pub memory: Option<String>,
+    #[clap(long, value_parser = parse_cpus)]
+    /// Maximum number of CPUs available to the toolbox, like 1.5 (needs systemd)
+    pub cpus: Option<u32>,
+}
+
+/// Filesystems of the system mounted in every toolbox
+#[derive(Args, PartialEq, Eq, Debug, Default)]
+pub struct SystemMounts {
+    #[clap(long, value_parser)]
+    /// Do not mount /proc inside of the toolbox
+    pub no_proc: bool,
+    #[clap(long, value_parser)]
+    /// Do not mount a read-only /sys inside of the toolbox
+    pub no_sys: bool,
+    #[clap(long = "device", value_name = "DEVICE", value_parser = parse_device)]
+    /// Device of the host mounted at the same path inside of the toolbox, like /dev/dri, can be
+    /// repeated
+    pub devices: Vec<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Fail if pivot_root is not possible instead of falling back to chroot, which is easier to
+    /// escape from
+    pub no_fallback: bool,
+    #[clap(long, value_parser)]
+    /// Do not recreate the mountpoints missing from the rootfs, like /host or /proc, before
+    /// entering it
+    pub no_layout_check: bool,
+}
+
+/// User the command is run as inside of the toolbox
+#[derive(Args, PartialEq, Eq, Debug, Default)]
+pub struct UserSelection {
+    #[clap(short, long, value_parser)]
+    /// Run as this user instead of the one of the image, as `user`, `uid`, `user:group` or `uid:gid`
+    pub user: Option<String>,
+    #[clap(long, value_parser, conflicts_with = "user")]
+    /// Stay as root, which is the current user outside of the toolbox, ignoring the user of the image
+    pub root: bool,
+}
+
+impl UserSelection {
+    /// The requested user, or the one of the image, unless root is kept
+    fn resolve<'a>(&'a self, config: &'a Config) -> Option<&'a str> {
+        if self.root {
+            return None;
+        }
+        self.user.as_deref().or(config.image_user.as_deref())
+    }
+}
+
+impl Limits {
+    fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpus.is_none()
+    }
+}
+
+/// Parses the path of a device of the host, which has to be inside of /dev
+fn parse_device(device: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(device);
+    match path.strip_prefix("/dev") {
+        Ok(relative)
+            if relative.components().next().is_some()
+                && relative
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_))) =>
+        {
+            Ok(path)
+        }
+        _ => Err(format!("{device} is not a device inside of /dev")),
+    }
+}
+
+/// Parses a number of CPUs as the percentage expected by `CPUQuota`
+fn parse_cpus(cpus: &str) -> Result<u32, String> {
+    match cpus.parse::<f64>() {
+        Ok(cpus) if cpus > 0.0 => Ok((cpus * 100.0).round() as u32),
+        _ => Err(format!("{cpus} is not a valid number of CPUs")),
+    }
 }
 
 pub fn nsexec(args: Execute) -> eyre::Result<()> {
+    let (limits, system, user, mount_cwd, start_dir) = match &args {
+        Execute::Enter(args) => (
+            &args.limits,
+            &args.system,
+            &args.user,
+            args.mount_cwd,
+            &args.workdir,
+        ),
+        Execute::Run(args) => (
+            &args.limits,
+            &args.system,
+            &args.user,
+            args.mount_cwd,
+            &args.workdir,
+        ),
+    };
+    let quiet = matches!(&args, Execute::Run(run) if run.quiet);
+    let verbosity = Verbosity::from_flags(quiet, false);
+    limit_resources(limits)?;
     let flags = CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWUTS | CloneFlags::CLONE_NEWNS;
 
-    let uid = users::get_current_uid().to_string();
-    let mappings = [
-        Mapping {
-            inside: "0",
-            outside: &uid,
-            len: "1",
-        },
-        Mapping {
-            inside: "1",
-            outside: "100000",
-            len: "65536",
-        },
-    ];
-    let pivot = Namespace::start(flags, &mappings)?;
-
     let config = configuration(&args)?;
+    let record = record_session(&args, &config, verbosity);
+    let mut pivot = start_toolbox(flags, &config)?;
     let new_root = &config.image;
+    if let Some(image) = &config.squashfs {
+        pivot.squashfs(image, new_root)?;
+    }
+    if let Some(overlay) = &config.overlay {
+        pivot.overlay(overlay, new_root)?;
+    }
     let old_root = format!("{new_root}/host");
-    let mounts = config.mounts().filter_map(|m| m.ok());
+    // Older configurations bind mount /proc and /sys, but they are handled by the system mounts now
+    let mounts: Vec<_> = config
+        .mounts()
+        .filter_map(|m| m.ok())
+        .filter(|m| m.target != "/proc" && m.target != "/sys")
+        .collect();
+    // A squashfs image is read-only, and it can not lose its mountpoints anyway
+    if !system.no_layout_check && config.squashfs.is_none() {
+        pivot.wait()?;
+        check_layout(new_root.as_ref(), system, &mounts)?;
+    }
+
+    let workdir = if mount_cwd {
+        let cwd = env::current_dir().wrap_err("Could not get the current directory")?;
+        // The mountpoint has to exist inside of the rootfs before pivoting into it
+        let mountpoint = resolve_in_root(new_root.as_ref(), &cwd)?;
+        create_dir_all(&mountpoint)
+            .wrap_err(format!("Could not create the mountpoint {mountpoint:?}"))?;
+        Some(cwd)
+    } else {
+        None
+    };
+    // A current directory that is not mounted in the toolbox makes no sense inside of it
+    let start_dir = match (&workdir, start_dir) {
+        (Some(_), _) => None,
+        (None, Some(dir)) => Some(StartDir::Requested(dir.clone())),
+        (None, None) => Some(StartDir::Mounted(
+            env::current_dir()
+                .ok()
+                .and_then(|cwd| equivalent_dir(&cwd, &mounts)),
+        )),
+    };
 
     env::set_var("PATH", extend_path());
     env::set_var("HOME", &config.home);
+    // Set before the environment of the toolbox, which can override it
+    if let Some(distro) = &config.distro {
+        env::set_var("UNBOX_DISTRO", distro.to_string());
+    }
+    for (key, value) in &config.env {
+        env::set_var(key, value);
+    }
 
-    let mut toolbox = pivot.pivot(new_root.as_ref(), old_root.as_ref())?;
-    toolbox.mounts(mounts)?;
+    let fallback = !system.no_fallback;
+    let mut toolbox = pivot.pivot(new_root.as_ref(), old_root.as_ref(), fallback, verbosity)?;
+    if let Some(record) = record {
+        toolbox.on_exit(record, verbosity);
+    }
+    if !system.no_proc {
+        toolbox.proc()?;
+    }
+    if !system.no_sys {
+        toolbox.sys()?;
+    }
+    toolbox.mounts(mounts.into_iter())?;
+    // Devices go after the mounts so they are still visible when /dev is not bound from the host
+    for device in &system.devices {
+        toolbox.device(device)?;
+    }
+    if let Some(workdir) = &workdir {
+        toolbox.workdir(workdir)?;
+    }
+    match start_dir {
+        Some(StartDir::Requested(dir)) => {
+            env::set_current_dir(&dir).wrap_err(format!("Could not change into {dir:?}"))?
+        }
+        Some(StartDir::Mounted(dir)) => {
+            let changed = dir.is_some_and(|dir| env::set_current_dir(dir).is_ok());
+            if !changed && env::set_current_dir(&config.home).is_err() {
+                env::set_current_dir("/").wrap_err("Could not change into /")?;
+            }
+        }
+        None => {}
+    }
     toolbox.hostname(&config.hostname)?;
+    // Switching the user goes last, since it drops the privileges needed to set up the toolbox
+    if let Some(user) = user.resolve(&config) {
+        toolbox.switch_user(user)?;
+    }
     match args {
-        Execute::Enter(_) => toolbox.spawn(config.shell, &[]),
-        Execute::Run(args) => toolbox.spawn(args.cmd, &args.args),
+        Execute::Enter(args) => {
+            toolbox.spawn_shell(&config.shell, &config.shell_args, args.login(&config))
+        }
+        Execute::Run(args) if args.tty => {
+            toolbox.spawn_in_pty(args.cmd, &args.args, args.interactive)
+        }
+        Execute::Run(args) => toolbox.spawn(args.cmd, &args.args, args.interactive),
+    }
+}
+
+/// Records the session for `ps`, a failure only stops it from being listed
+fn record_session(args: &Execute, config: &Config, verbosity: Verbosity) -> Option<Record> {
+    let (name, command) = match args {
+        Execute::Enter(args) => (&args.name, (&config.shell, &config.shell_args)),
+        Execute::Run(args) => (&args.name, (&args.cmd, &args.args)),
+    };
+    let (cmd, args) = command;
+    let words: Vec<_> = std::iter::once(cmd).chain(args).cloned().collect();
+    let command = words.join(" ");
+    match Session::current(name, &command).and_then(|session| session.write()) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            warn(verbosity, format_args!("Could not record the session: {e}"));
+            None
+        }
+    }
+}
+
+/// Directory where the command starts when the current one is not mounted with --mount-cwd
+enum StartDir {
+    /// Given with --workdir, it has to exist
+    Requested(PathBuf),
+    /// Where the current directory is mounted, if it is, with the home and the root as
+    /// fallbacks
+    Mounted(Option<PathBuf>),
+}
+
+/// Recreates the mountpoints that have been removed from the rootfs since it was created,
+/// for the old root, the system filesystems and the directories mounted from the host. The
+/// ones of files, like /etc/hosts, are left to the image.
+pub fn check_layout(root: &Path, system: &SystemMounts, mounts: &[MountInfo]) -> eyre::Result<()> {
+    let mut dirs = vec![PathBuf::from("/host")];
+    if !system.no_proc {
+        dirs.push("/proc".into());
+    }
+    if !system.no_sys {
+        dirs.push("/sys".into());
+    }
+    dirs.extend(
+        mounts
+            .iter()
+            .filter(|mount| host_path(mount).is_some_and(|host| host.is_dir()))
+            .map(|mount| PathBuf::from(&mount.target)),
+    );
+    for dir in &dirs {
+        if !resolve_in_root(root, dir)?.exists() {
+            debug(format_args!("recreating the mountpoint {}", dir.display()));
+        }
     }
+    create_dirs(root, &dirs)
+}
+
+/// Path on the host of the source of a mount, if it is taken from the host
+fn host_path(mount: &MountInfo) -> Option<PathBuf> {
+    let host = Path::new(&mount.source).strip_prefix("/host").ok()?;
+    Some(Path::new("/").join(host))
+}
+
+/// Path inside of the toolbox of the current directory of the host, if it is under one of
+/// the mounts taken from the host, like `/home`. The deepest mount wins, as it hides the
+/// ones above it.
+pub fn equivalent_dir(cwd: &Path, mounts: &[MountInfo]) -> Option<PathBuf> {
+    mounts
+        .iter()
+        .filter_map(|mount| {
+            let host = host_path(mount)?;
+            // The current directory is canonical, /home may be a link to /var/home
+            let host = std::fs::canonicalize(&host).unwrap_or(host);
+            let relative = cwd.strip_prefix(&host).ok()?;
+            Some((
+                host.components().count(),
+                Path::new(&mount.target).join(relative),
+            ))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, dir)| dir)
 }
 
 fn configuration(args: &Execute) -> eyre::Result<Config> {
@@ -82,6 +393,36 @@ fn configuration(args: &Execute) -> eyre::Result<Config> {
     Config::read_or_new(name).wrap_err("Could not get configuration for the toolbox")
 }
 
+/// Executes the same command again inside of a systemd scope with the limits applied, the
+/// second time it only removes the marker from the environment
+fn limit_resources(limits: &Limits) -> eyre::Result<()> {
+    const SCOPE_MARKER: &str = "UNBOX_LIMITED_SCOPE";
+
+    if env::var_os(SCOPE_MARKER).is_some() {
+        env::remove_var(SCOPE_MARKER);
+        return Ok(());
+    }
+    if limits.is_empty() {
+        return Ok(());
+    }
+    eyre::ensure!(
+        Path::new("/run/systemd/system").exists(),
+        "Resource limits need systemd, but it is not running"
+    );
+    let exe = env::current_exe().wrap_err("Could not find the current executable")?;
+    let mut cmd = Command::new("systemd-run");
+    cmd.args(["--user", "--scope", "--quiet"]);
+    if let Some(memory) = &limits.memory {
+        cmd.arg("-p").arg(format!("MemoryMax={memory}"));
+    }
+    if let Some(cpus) = limits.cpus {
+        cmd.arg("-p").arg(format!("CPUQuota={cpus}%"));
+    }
+    cmd.arg("--").arg(exe).args(env::args_os().skip(1));
+    let err = cmd.env(SCOPE_MARKER, "1").exec();
+    Err(err).wrap_err("Could not execute systemd-run to apply the resource limits")
+}
+
 fn extend_path() -> OsString {
     let mut path = env::var_os("PATH").expect("PATH needs to exist");
     path.push(":/bin");
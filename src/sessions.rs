@@ -0,0 +1,266 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sessions of `enter` and `run`, each one recorded in `sessions/<pid>.json` of the storage
+//! root. The file is removed when the session ends if unbox waits for the command, with a
+//! pseudo-terminal or a squashfs image. Otherwise unbox is replaced by the command, so the
+//! files of processes that no longer exist are pruned when they are read.
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use nix::sys::signal::{kill as send, killpg, Signal};
+use nix::unistd::{getpgid, Pid};
+use rustix::fd::OwnedFd;
+use rustix::fs::{open, unlinkat, AtFlags, Mode, OFlags};
+use serde::{Deserialize, Serialize};
+use tabled::{Style, Table, Tabled};
+
+use crate::config::storage_root;
+use crate::deadline::parse_duration;
+use crate::list::{csv, Format};
+
+/// List the sessions of enter and run that are still running
+///
+/// Sessions are recorded when they start. When unbox is replaced by the command, which is the
+/// case unless it runs with -t or in a squashfs toolbox, nothing removes the record once it
+/// ends, so the records of finished sessions are only removed by ps and kill.
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Ps {
+    #[clap(short, long, value_parser, default_value = "table")]
+    /// Format of the list
+    format: Format,
+}
+
+/// Stop the running sessions of a toolbox, or the one of a pid
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Kill {
+    #[clap(value_parser)]
+    /// Name of the toolbox, or pid of the session as shown by ps
+    pub target: String,
+    #[clap(long, value_parser = parse_duration, default_value = "10s")]
+    /// Time given to the sessions to exit after SIGTERM, before sending SIGKILL
+    pub grace: Duration,
+}
+
+/// Toolbox entered by a process
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Session {
+    pub name: String,
+    pub pid: u32,
+    /// Seconds since the epoch when the session started
+    pub started: u64,
+    /// Shell or command run in the toolbox
+    pub command: String,
+    /// Start time of the process in clock ticks since boot, so a pid reused by another
+    /// process is not taken for the session
+    pub ticks: u64,
+}
+
+impl Session {
+    /// Session of the current process
+    pub fn current(name: &str, command: &str) -> eyre::Result<Self> {
+        let pid = std::process::id();
+        let ticks = start_ticks(pid).ok_or_else(|| eyre::eyre!("Could not read /proc/{pid}"))?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        Ok(Session {
+            name: name.into(),
+            pid,
+            started,
+            command: command.into(),
+            ticks,
+        })
+    }
+
+    pub fn write(&self) -> eyre::Result<Record> {
+        let dir = sessions_dir()?;
+        std::fs::create_dir_all(&dir).wrap_err("Could not create the directory of the sessions")?;
+        let file = format!("{}.json", self.pid);
+        let path = dir.join(&file);
+        // Renamed into place so ps never reads a record that is only partly written, the
+        // hidden name keeps it out of the sessions meanwhile
+        let partial = dir.join(format!(".{file}"));
+        std::fs::write(&partial, serde_json::to_string(self)?)
+            .and_then(|()| std::fs::rename(&partial, &path))
+            .wrap_err(format!("Could not record the session in {path:?}"))?;
+        let flags = OFlags::DIRECTORY | OFlags::RDONLY | OFlags::CLOEXEC;
+        let dir = open(&dir, flags, Mode::empty()).wrap_err(format!(
+            "Could not open the directory of the sessions {dir:?}"
+        ))?;
+        Ok(Record { dir, file })
+    }
+
+    /// Removes the record of the session, once it is known to have ended
+    pub fn remove(&self) -> eyre::Result<()> {
+        let path = sessions_dir()?.join(format!("{}.json", self.pid));
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).wrap_err(format!("Could not remove {path:?}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the signal to the process group of the session if it leads one, so the
+    /// processes started in the toolbox get it too, or only to its process otherwise
+    pub fn signal(&self, signal: Signal) -> eyre::Result<()> {
+        let pid = Pid::from_raw(self.pid as i32);
+        let sent = match getpgid(Some(pid)) {
+            Ok(group) if group == pid => killpg(group, signal),
+            _ => send(pid, signal),
+        };
+        sent.wrap_err(format!("Could not send {signal} to {}", self.pid))
+    }
+
+    /// The process is still the one that started the session
+    pub fn is_running(&self) -> bool {
+        start_ticks(self.pid) == Some(self.ticks)
+    }
+}
+
+/// File of a session written by the current process, with the directory kept open so it can
+/// be removed after pivoting into the toolbox, where the storage root is somewhere else
+pub struct Record {
+    dir: OwnedFd,
+    file: String,
+}
+
+impl Record {
+    /// Removes the file once the session has ended. It is not an error if ps already pruned
+    /// it, any other failure leaves it to be pruned by ps later.
+    pub fn remove(self) -> eyre::Result<()> {
+        match unlinkat(&self.dir, &self.file, AtFlags::empty()) {
+            Err(e) if e != rustix::io::Errno::NOENT => {
+                Err(e).wrap_err(format!("Could not remove the record {}", self.file))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Tabled for Session {
+    const LENGTH: usize = 4;
+
+    fn fields(&self) -> Vec<Cow<'_, str>> {
+        vec![
+            Cow::Borrowed(&self.name),
+            Cow::Owned(self.pid.to_string()),
+            Cow::Owned(elapsed(self.started)),
+            Cow::Borrowed(&self.command),
+        ]
+    }
+    fn headers() -> Vec<Cow<'static, str>> {
+        ["name", "pid", "running for", "command"]
+            .into_iter()
+            .map(Cow::from)
+            .collect()
+    }
+}
+
+pub fn ps(args: Ps) -> eyre::Result<()> {
+    let rows = active()?;
+    match args.format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+        Format::Csv => print!("{}", csv(&rows)),
+        Format::Table if rows.is_empty() => println!("No toolbox is being used"),
+        Format::Table => {
+            let mut table = Table::new(rows);
+            let table = table.with(Style::modern());
+            print!("{table}");
+        }
+    }
+    Ok(())
+}
+
+pub fn kill(args: Kill) -> eyre::Result<()> {
+    let active = active()?;
+    let mut sessions: Vec<_> = active.iter().filter(|s| s.name == args.target).collect();
+    if sessions.is_empty() {
+        if let Ok(pid) = args.target.parse::<u32>() {
+            sessions.extend(active.iter().filter(|s| s.pid == pid));
+        }
+    }
+    eyre::ensure!(
+        !sessions.is_empty(),
+        "No running session of a toolbox or with a pid matches {}",
+        args.target
+    );
+    for session in &sessions {
+        session.signal(Signal::SIGTERM)?;
+    }
+    let deadline = Instant::now() + args.grace;
+    while sessions.iter().any(|s| s.is_running()) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    for session in &sessions {
+        if session.is_running() {
+            session.signal(Signal::SIGKILL)?;
+        }
+        session.remove()?;
+    }
+    Ok(())
+}
+
+/// Sessions that are still running, oldest first. The files of the ones that ended are
+/// removed, while the ones that can not be read are left alone and skipped.
+pub fn active() -> eyre::Result<Vec<Session>> {
+    let paths = match std::fs::read_dir(sessions_dir()?) {
+        Ok(paths) => paths,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut sessions = Vec::new();
+    for path in paths.filter_map(|p| Some(p.ok()?.path())) {
+        // Records being written have a hidden name until they are complete
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        let session = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Session>(&contents).ok());
+        match session {
+            Some(session) if session.is_running() => sessions.push(session),
+            Some(_) => {
+                let _ = std::fs::remove_file(&path);
+            }
+            None => {}
+        }
+    }
+    sessions.sort_by_key(|session| (session.started, session.pid));
+    Ok(sessions)
+}
+
+fn sessions_dir() -> eyre::Result<PathBuf> {
+    Ok(storage_root()?.join("sessions"))
+}
+
+/// Field 22 of `/proc/<pid>/stat`, counted after the name since it may contain spaces
+fn start_ticks(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Time since the session started, in its largest unit
+fn elapsed(started: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    match now.saturating_sub(started) {
+        secs if secs < 60 => format!("{secs}s"),
+        secs if secs < 60 * 60 => format!("{}m", secs / 60),
+        secs if secs < 24 * 60 * 60 => format!("{}h", secs / (60 * 60)),
+        secs => format!("{}d", secs / (24 * 60 * 60)),
+    }
+}
@@ -0,0 +1,171 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use clap::Args;
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::Pid;
+use rustix::pty::{grantpt, openpt, ptsname, unlockpt, OpenptFlags};
+use rustix::termios::{isatty, tcgetattr, tcgetwinsize, tcsetattr, tcsetwinsize};
+use rustix::termios::{OptionalActions, Termios};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGWINCH};
+use signal_hook::iterator::Signals;
+
+// Make the pseudo-terminal the controlling terminal of a new session and execute the command
+/// Internal subcommand. Should not be used directly
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct PtyExec {
+    #[clap(value_parser)]
+    /// Path of the pseudo-terminal
+    tty: PathBuf,
+    #[clap(value_parser)]
+    /// Command to run
+    cmd: String,
+    #[clap(value_parser)]
+    /// Command arguments
+    args: Vec<String>,
+}
+
+pub fn pty_exec(args: PtyExec) -> eyre::Result<()> {
+    rustix::process::setsid().wrap_err("Could not create a new session")?;
+    let tty = File::options()
+        .read(true)
+        .write(true)
+        .open(&args.tty)
+        .wrap_err("Could not open the pseudo-terminal")?;
+    rustix::process::ioctl_tiocsctty(&tty).wrap_err("Could not set the controlling terminal")?;
+    let err = Command::new(&args.cmd)
+        .args(&args.args)
+        .stdin(tty.try_clone()?)
+        .stdout(tty.try_clone()?)
+        .stderr(tty)
+        .exec();
+    Err(err).wrap_err("Could not execute the requested command")
+}
+
+/// Runs the command attached to a new pseudo-terminal, relaying it to the stdio of unbox
+/// and forwarding the signals that would terminate unbox. Stdin is only relayed, and the
+/// terminal put in raw mode, if `interactive` is set. It returns the exit code of the command.
+pub fn run_in_pty<S: AsRef<OsStr>>(cmd: S, args: &[S], interactive: bool) -> eyre::Result<i32> {
+    let master = openpt(OpenptFlags::RDWR | OpenptFlags::NOCTTY | OpenptFlags::CLOEXEC)
+        .wrap_err("Could not open a pseudo-terminal")?;
+    grantpt(&master).wrap_err("Could not grant access to the pseudo-terminal")?;
+    unlockpt(&master).wrap_err("Could not unlock the pseudo-terminal")?;
+    let tty = ptsname(&master, Vec::new()).wrap_err("Could not get the pseudo-terminal name")?;
+    copy_winsize(&master);
+
+    // /proc/self/exe is still unbox after pivoting, the mounted /proc is needed for it
+    let mut child = Command::new("/proc/self/exe")
+        .arg("pty-exec")
+        .arg(OsStr::new(tty.to_str().expect("pts paths are valid utf8")))
+        .arg("--")
+        .arg(cmd)
+        .args(args)
+        .stdin(Stdio::null())
+        .spawn()
+        .wrap_err("Could not spawn the command in the pseudo-terminal, is /proc mounted?")?;
+
+    let mut master = File::from(master);
+    // Without -i the keys are not relayed, so the terminal is left as it is: Ctrl-C still
+    // sends SIGINT to unbox, which forwards it to the command below
+    let raw = interactive.then(RawMode::enable);
+    if interactive {
+        let mut input = master.try_clone()?;
+        std::thread::spawn(move || std::io::copy(&mut std::io::stdin(), &mut input));
+    }
+    let resize = master.try_clone()?;
+    // The command leads its own session, so the signals are forwarded to its process group
+    let group = Pid::from_raw(child.id() as i32);
+    let mut signals = Signals::new([SIGWINCH, SIGTERM, SIGHUP, SIGINT, SIGQUIT])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGWINCH => copy_winsize(&resize),
+                signal => {
+                    if let Ok(signal) = Signal::try_from(signal) {
+                        let _ = killpg(group, signal);
+                    }
+                }
+            }
+        }
+    });
+    // Reading fails with EIO once the command has exited and closed the pseudo-terminal
+    let mut buffer = [0; 4096];
+    let mut stdout = std::io::stdout();
+    while let Ok(n @ 1..) = master.read(&mut buffer) {
+        stdout.write_all(&buffer[..n])?;
+        stdout.flush()?;
+    }
+    let status = child.wait().wrap_err("Could not wait for the command")?;
+    drop(raw);
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Forwards the signals that would terminate unbox to the command with `pid`, which stays in
+/// the process group of unbox so it can use the terminal. The terminal already sends SIGINT and
+/// SIGQUIT to the whole group, so they are only forwarded when stdin is not one. These handlers
+/// are only installed by the process running the command of a toolbox, never by `create`,
+/// which cleans up after its own errors without handling signals.
+pub fn forward_signals(pid: u32) -> eyre::Result<()> {
+    let from_terminal = isatty(std::io::stdin());
+    let pid = Pid::from_raw(pid as i32);
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGINT, SIGQUIT])
+        .wrap_err("Could not install the signal handlers")?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if from_terminal && (signal == SIGINT || signal == SIGQUIT) {
+                continue;
+            }
+            if let Ok(signal) = Signal::try_from(signal) {
+                let _ = kill(pid, signal);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Copies the size of the terminal of unbox to the pseudo-terminal, if there is one
+fn copy_winsize<Fd: rustix::fd::AsFd>(pty: Fd) {
+    if let Ok(size) = tcgetwinsize(std::io::stdout()) {
+        let _ = tcsetwinsize(pty, size);
+    }
+}
+
+/// Puts the terminal of unbox in raw mode, so every key reaches the pseudo-terminal, and
+/// restores it when dropped. Nothing is done when stdin is not a terminal.
+struct RawMode(Option<Termios>);
+
+impl RawMode {
+    fn enable() -> Self {
+        let stdin = std::io::stdin();
+        if !isatty(&stdin) {
+            return RawMode(None);
+        }
+        match tcgetattr(&stdin) {
+            Ok(original) => {
+                let mut raw = original.clone();
+                raw.make_raw();
+                let _ = tcsetattr(&stdin, OptionalActions::Now, &raw);
+                RawMode(Some(original))
+            }
+            Err(_) => RawMode(None),
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(original) = &self.0 {
+            let _ = tcsetattr(std::io::stdin(), OptionalActions::Now, original);
+        }
+    }
+}
@@ -0,0 +1,991 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{create_dir_all, read_dir, read_link, symlink_metadata, File};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use tar::{Archive, Entry, EntryType};
+use walkdir::WalkDir;
+
+use crate::archive::{decoder, Compression, DecoderOptions};
+use crate::deadline::Deadline;
+use crate::progress::ProgressSink;
+
+/// Top-level directories of a rootfs where the filesystems of the system are mounted
+pub const MOUNTPOINT_DIRS: &[&str] = &["proc", "sys", "dev", "host"];
+
+/// Options controlling how the entries of a tarball are unpacked
+#[derive(Clone, Copy, Default, Debug)]
+pub struct UnpackOptions {
+    /// Clear the setuid and setgid bits of the unpacked files
+    pub no_setuid: bool,
+    /// Apply OCI whiteouts (`.wh.` files) to the existing contents of the root instead of unpacking them
+    pub whiteouts: bool,
+    /// Keep an index of the unpacked files, skipping the ones already unpacked by an interrupted run
+    pub resume: bool,
+    /// Modification time, in seconds since the epoch, given to every unpacked file and directory
+    /// instead of the one of its entry
+    pub mtime: Option<u64>,
+    /// Apply the extended attributes stored in the PAX records of the entries
+    pub xattrs: bool,
+    /// Leading components dropped from the path of every entry, like `tar --strip-components`.
+    /// Entries without any component left are skipped.
+    pub strip_components: usize,
+    /// Top-level directories whose contents are skipped, so they are left as empty mountpoints.
+    /// The directories themselves are still unpacked.
+    pub skip_dirs: &'static [&'static str],
+    /// Largest size of the contents of the entries, once uncompressed. The extraction fails
+    /// with [`TooLarge`] as soon as an entry goes over it
+    pub max_size: Option<u64>,
+    /// Largest number of entries, including the skipped ones, to stop archives made of
+    /// millions of tiny files. The extraction fails with [`TooLarge`] too
+    pub max_entries: Option<u64>,
+    /// Aborts the extraction once it has passed
+    pub deadline: Deadline,
+    /// Buffers of the decompression of the archives
+    pub decoder: DecoderOptions,
+}
+
+impl UnpackOptions {
+    /// Options of the next archive unpacked into the same root, with what is left of the
+    /// limits once `unpacked` has been unpacked
+    pub fn after(&self, unpacked: &UnpackStats) -> Self {
+        UnpackOptions {
+            max_size: self.max_size.map(|max| max.saturating_sub(unpacked.bytes)),
+            max_entries: self
+                .max_entries
+                .map(|max| max.saturating_sub(unpacked.entries)),
+            ..*self
+        }
+    }
+
+    /// Fails with [`TooLarge`] if the entries read so far are over one of the limits
+    fn check_limits(&self, entries: u64, bytes: u64) -> eyre::Result<()> {
+        match (self.max_entries, self.max_size) {
+            (Some(max), _) if entries > max => Err(TooLarge::Entries.into()),
+            (_, Some(max)) if bytes > max => Err(TooLarge::Size.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Entries of the archives accepted by `create` unless another limit is given, far more
+/// than the few hundred thousands of a large distribution
+pub const DEFAULT_MAX_ENTRIES: u64 = 10_000_000;
+
+/// Error returned when the archives go over one of the limits of [`UnpackOptions`], it can
+/// be found with `downcast_ref`
+#[derive(Debug)]
+pub enum TooLarge {
+    /// More bytes than [`UnpackOptions::max_size`]
+    Size,
+    /// More entries than [`UnpackOptions::max_entries`]
+    Entries,
+}
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TooLarge::Size => write!(f, "The archive exceeds max-size once uncompressed"),
+            TooLarge::Entries => write!(f, "The archive exceeds max-entries"),
+        }
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// Checks if the error, or any of its causes, is a [`TooLarge`]
+pub fn is_too_large(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| cause.is::<TooLarge>())
+}
+
+/// Summary of what has been unpacked from one or more archives
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct UnpackStats {
+    /// Number of entries read, including the skipped ones
+    pub entries: u64,
+    /// Size of the contents of the entries, once uncompressed
+    pub bytes: u64,
+    /// Entries that were not unpacked because they would end up outside of the root
+    pub skipped: Vec<PathBuf>,
+    /// Comment recorded in the global PAX header of the archive, like the name of the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl UnpackStats {
+    /// Accumulates the stats of another archive unpacked into the same root
+    pub fn add(&mut self, other: UnpackStats) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.skipped.extend(other.skipped);
+        // The comment of the base archive describes the rootfs better than the later ones
+        self.comment = self.comment.take().or(other.comment);
+    }
+}
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const PAX_XATTR_PREFIX: &[u8] = b"SCHILY.xattr.";
+/// Symbolic links followed while resolving a path before giving up, like the kernel does
+pub const MAX_LINKS: usize = 40;
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Paths relative to the root, that have been hidden by a layer
+enum Whiteout {
+    /// A single file or directory
+    Entry(PathBuf),
+    /// Every entry of the directory coming from a lower layer
+    Opaque(PathBuf),
+}
+
+impl Whiteout {
+    fn parse(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let parent = sanitize(path.parent().unwrap_or_else(|| Path::new("")));
+        if name == OPAQUE_WHITEOUT {
+            Some(Whiteout::Opaque(parent))
+        } else {
+            let hidden = name.strip_prefix(WHITEOUT_PREFIX)?;
+            // Only a name can be hidden, never `.`, `..` or nothing, which would be the parent
+            let mut components = Path::new(hidden).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => Some(Whiteout::Entry(parent.join(hidden))),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Size of the buffer used to read archives by default. Rootfs tarballs are large and mostly
+/// read in big sequential chunks, so it is much larger than the default of [`BufReader`].
+pub const READ_BUFFER_SIZE: usize = 1 << 20;
+
+/// Directories expected in a rootfs, the ones of a distribution are in the thousands
+const DIRS_HINT: usize = 4096;
+
+/// Opens the tarball, or stdin if the path is `-`, decompressing it if needed. The size is
+/// returned when it is known and comparable with the size of the entries.
+pub fn open_tar(
+    tar: &Path,
+    options: &DecoderOptions,
+) -> eyre::Result<(Box<dyn Read>, Option<u64>)> {
+    if tar == Path::new("-") {
+        let stdin = BufReader::with_capacity(options.buffer, std::io::stdin());
+        let (archive, _) =
+            decoder(stdin, options).wrap_err("Could not read the tar file from stdin")?;
+        return Ok((archive, None));
+    }
+    let archive = File::open(tar).wrap_err("Could not open the tar file")?;
+    let size = archive.metadata().ok().map(|m| m.len());
+    let archive = BufReader::with_capacity(options.buffer, archive);
+    let (archive, compression) =
+        decoder(archive, options).wrap_err("Could not read the tar file")?;
+    // The sizes of the entries can only be compared with the file if it is not compressed
+    Ok((archive, size.filter(|_| compression == Compression::None)))
+}
+
+/// Unpacks the tarball into `new_root`, directories are unpacked last so their
+/// permissions do not prevent the creation of their contents
+pub fn unpack_tar<R: Read>(
+    archive: R,
+    total: Option<u64>,
+    new_root: &str,
+    options: &UnpackOptions,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<UnpackStats> {
+    let mut tar = Archive::new(archive);
+    tar.set_preserve_permissions(true);
+    let mut dirs = Vec::with_capacity(DIRS_HINT);
+    let mut stats = UnpackStats::default();
+    // Opaque whiteouts only hide the entries of lower layers, not the ones of this archive
+    let mut layer = HashSet::new();
+    // Global PAX records apply to the following entries without their own, but an explicit
+    // mtime still wins
+    let mut mtime = options.mtime;
+    // Attributes that could not be set, so each one is only reported once
+    let mut failed_xattrs = HashSet::new();
+    let mut index = options
+        .resume
+        .then(|| ResumeIndex::open(Path::new(new_root)))
+        .transpose()?;
+    for entry in tar.entries()? {
+        options.deadline.check()?;
+        let mut entry = entry?;
+        if entry.header().entry_type().is_pax_global_extensions() {
+            let global = GlobalHeader::read(&mut entry)?;
+            stats.comment = stats.comment.or(global.comment);
+            if options.mtime.is_none() && global.mtime.is_some() {
+                mtime = global.mtime;
+            }
+            continue;
+        }
+        let mtime = match options.mtime {
+            Some(mtime) => Some(mtime),
+            None => pax_mtime(&mut entry)?.or(mtime),
+        };
+        stats.entries += 1;
+        stats.bytes += entry.size();
+        options.check_limits(stats.entries, stats.bytes)?;
+        progress.on_entries(stats.entries);
+        progress.on_bytes(stats.bytes, total);
+        let mut path = entry.path()?.into_owned();
+        if options.strip_components > 0 {
+            path = match strip_components(&path, options.strip_components) {
+                Some(path) => path,
+                None => continue,
+            };
+        }
+        if is_under(&path, options.skip_dirs) {
+            continue;
+        }
+        if options.whiteouts {
+            if let Some(whiteout) = Whiteout::parse(&path) {
+                apply_whiteout(Path::new(new_root), &whiteout, &layer)?;
+                continue;
+            }
+            layer.insert(sanitize(&path));
+        }
+        if entry.header().entry_type().is_dir() {
+            // The root itself keeps its permissions, as `unpack_in` does
+            if sanitize(&path).as_os_str().is_empty() {
+                continue;
+            }
+            match PendingDir::create(Path::new(new_root), &path, &entry, mtime)? {
+                Some(dir) => {
+                    if options.xattrs {
+                        apply_xattrs(&mut entry, &dir.path, &mut failed_xattrs, progress)?;
+                    }
+                    dirs.push(dir)
+                }
+                None => stats.skipped.push(path),
+            }
+        } else {
+            if let Some(index) = &index {
+                if index.is_unpacked(Path::new(new_root), &path, &entry, mtime)? {
+                    continue;
+                }
+            }
+            let target = if options.strip_components > 0 {
+                unpack_at(
+                    &mut entry,
+                    Path::new(new_root),
+                    &path,
+                    options.strip_components,
+                )?
+            } else {
+                // `unpack_in` skips the entry if its parent leads out of the root
+                let unpacked = entry
+                    .unpack_in(new_root)
+                    .wrap_err("Could not unpack entry")?;
+                unpacked.then(|| Path::new(new_root).join(sanitize(&path)))
+            };
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    stats.skipped.push(path);
+                    continue;
+                }
+            };
+            if options.no_setuid {
+                clear_setuid(&target, &entry)?;
+            }
+            if options.xattrs {
+                apply_xattrs(&mut entry, &target, &mut failed_xattrs, progress)?;
+            }
+            if let Some(mtime) = mtime {
+                set_mtime(&target, mtime)?;
+            }
+            if let Some(index) = &mut index {
+                index.record(Path::new(new_root), &target, &entry)?;
+            }
+        }
+    }
+    // Deepest directories first, using the full path given by the GNU or PAX extensions
+    dirs.sort_unstable_by_key(|dir| std::cmp::Reverse(dir.path.components().count()));
+    for dir in dirs {
+        dir.finish()?;
+    }
+    Ok(stats)
+}
+
+/// Drops the first `count` components of the path. Only the root and `.` are ignored, so the
+/// `..` components are kept for the checks against escaping the root.
+fn strip_components(path: &Path, count: usize) -> Option<PathBuf> {
+    let stripped: PathBuf = path
+        .components()
+        .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+        .skip(count)
+        .collect();
+    (!stripped.as_os_str().is_empty()).then_some(stripped)
+}
+
+/// Location of the entry at `path` in the root, where its parent is resolved inside of the
+/// root and the entry itself is not followed. Paths leading out of the root have none.
+fn entry_target(root: &Path, path: &Path) -> eyre::Result<Option<PathBuf>> {
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Ok(None);
+    }
+    let path = sanitize(path);
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let parent = resolve_in_root(root, path.parent().unwrap_or_else(|| Path::new("")))?;
+    Ok(Some(parent.join(name)))
+}
+
+/// Unpacks the entry at `path` instead of its own path, with the same safeguards as
+/// `unpack_in`, returning where it was unpacked. Hard links get the components of their
+/// target stripped too.
+fn unpack_at<R: Read>(
+    entry: &mut Entry<'_, R>,
+    root: &Path,
+    path: &Path,
+    strip: usize,
+) -> eyre::Result<Option<PathBuf>> {
+    let target = match entry_target(root, path)? {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent).wrap_err(format!("Could not create the directory {parent:?}"))?;
+    }
+    if !entry.header().entry_type().is_hard_link() {
+        entry.unpack(&target).wrap_err("Could not unpack entry")?;
+        return Ok(Some(target));
+    }
+    // `unpack` would resolve the target from the current directory instead of the root
+    let link = entry
+        .link_name()?
+        .and_then(|link| strip_components(&link, strip));
+    let link = match link {
+        Some(link) if !link.components().any(|c| c == Component::ParentDir) => link,
+        _ => return Ok(None),
+    };
+    remove_entry(&target)?;
+    std::fs::hard_link(resolve_in_root(root, link)?, &target)
+        .wrap_err(format!("Could not create the link {target:?}"))?;
+    Ok(Some(target))
+}
+
+/// Records of a global PAX header that unbox uses, the rest are ignored
+#[derive(Default)]
+struct GlobalHeader {
+    /// Default modification time of the following entries, in seconds
+    mtime: Option<u64>,
+    comment: Option<String>,
+}
+
+impl GlobalHeader {
+    fn read<R: Read>(entry: &mut Entry<'_, R>) -> eyre::Result<Self> {
+        let mut global = GlobalHeader::default();
+        let records = match entry.pax_extensions()? {
+            Some(records) => records,
+            None => return Ok(global),
+        };
+        for record in records {
+            let record = record.wrap_err("Could not read the global PAX header")?;
+            let value = String::from_utf8_lossy(record.value_bytes());
+            match record.key_bytes() {
+                b"mtime" => global.mtime = parse_pax_mtime(&value),
+                b"comment" => global.comment = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        Ok(global)
+    }
+}
+
+/// Modification time given by the PAX records of the entry itself, which the tar crate does
+/// not apply
+fn pax_mtime<R: Read>(entry: &mut Entry<'_, R>) -> eyre::Result<Option<u64>> {
+    let records = match entry.pax_extensions()? {
+        Some(records) => records,
+        None => return Ok(None),
+    };
+    for record in records {
+        let record = record.wrap_err("Could not read the PAX header of an entry")?;
+        if record.key_bytes() == b"mtime" {
+            return Ok(parse_pax_mtime(&String::from_utf8_lossy(
+                record.value_bytes(),
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// The time may have a fractional part, which is not kept when unpacking
+fn parse_pax_mtime(value: &str) -> Option<u64> {
+    value.split('.').next().unwrap_or_default().parse().ok()
+}
+
+/// Files unpacked into a root, stored next to it while the extraction is running so it can
+/// be resumed if it is interrupted. Each line is the size, the mtime and
+/// the path of a file.
+struct ResumeIndex {
+    unpacked: HashSet<(PathBuf, u64, u64)>,
+    file: std::io::BufWriter<File>,
+}
+
+impl ResumeIndex {
+    /// `.<name>.resume` next to the root, so it is as private as the toolbox itself
+    fn path(new_root: &Path) -> PathBuf {
+        let name = new_root.file_name().unwrap_or_default().to_string_lossy();
+        new_root.with_file_name(format!(".{name}.resume"))
+    }
+
+    fn open(new_root: &Path) -> eyre::Result<Self> {
+        use std::io::Read;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let path = ResumeIndex::path(new_root);
+        let mut file = File::options()
+            .read(true)
+            .create(true)
+            .append(true)
+            .custom_flags(nix::fcntl::OFlag::O_NOFOLLOW.bits())
+            .open(&path)
+            .wrap_err("Could not open the index of the unpacked files")?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .wrap_err("Could not read the index of the unpacked files")?;
+        let unpacked = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, ' ');
+                let size = fields.next()?.parse().ok()?;
+                let mtime = fields.next()?.parse().ok()?;
+                Some((PathBuf::from(fields.next()?), size, mtime))
+            })
+            .collect();
+        Ok(ResumeIndex {
+            unpacked,
+            file: std::io::BufWriter::new(file),
+        })
+    }
+
+    /// The file was unpacked by a previous run and it has not changed since then. `mtime` is
+    /// the one given to the file instead of the one of its header, if there is one
+    fn is_unpacked<R: Read>(
+        &self,
+        root: &Path,
+        path: &Path,
+        entry: &Entry<'_, R>,
+        mtime: Option<u64>,
+    ) -> eyre::Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        let header = entry.header();
+        let (size, recorded) = (header.size()?, header.mtime()?);
+        let target = match entry_target(root, path)? {
+            Some(target) if header.entry_type().is_file() => target,
+            _ => return Ok(false),
+        };
+        let relative = target.strip_prefix(root).unwrap_or(&target);
+        if !self
+            .unpacked
+            .contains(&(relative.to_path_buf(), size, recorded))
+        {
+            return Ok(false);
+        }
+        let mtime = mtime.unwrap_or(recorded);
+        Ok(match symlink_metadata(&target) {
+            Ok(meta) => meta.is_file() && meta.len() == size && meta.mtime() == mtime as i64,
+            Err(_) => false,
+        })
+    }
+
+    /// Records the file unpacked at `target`, by its path relative to the root
+    fn record<R: Read>(
+        &mut self,
+        root: &Path,
+        target: &Path,
+        entry: &Entry<'_, R>,
+    ) -> eyre::Result<()> {
+        use std::io::Write;
+
+        let header = entry.header();
+        let path = target.strip_prefix(root).unwrap_or(target);
+        // Paths that would not fit in a line are always unpacked again
+        match path.to_str() {
+            Some(path) if header.entry_type().is_file() && !path.contains('\n') => {
+                writeln!(self.file, "{} {} {path}", header.size()?, header.mtime()?)
+                    .wrap_err("Could not update the index of the unpacked files")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Removes the index of the files unpacked into the root once the extraction has finished
+pub fn remove_resume_index(new_root: &Path) {
+    let _ = std::fs::remove_file(ResumeIndex::path(new_root));
+}
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Checks if the file is a ZIP archive instead of a tarball
+pub fn is_zip(path: &Path) -> bool {
+    let mut header = [0; 4];
+    match File::open(path) {
+        Ok(mut file) => file.read_exact(&mut header).is_ok() && header == ZIP_MAGIC,
+        Err(_) => false,
+    }
+}
+
+/// Unpacks a ZIP archive into `new_root` with the same safeguards as [`unpack_tar`]: entries
+/// can not escape the root and directories get their permissions last
+pub fn unpack_zip(
+    zip: &Path,
+    new_root: &str,
+    options: &UnpackOptions,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<UnpackStats> {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    const FILE_TYPE: u32 = 0o170000;
+    const SYMLINK: u32 = 0o120000;
+    const SETUID_SETGID: u32 = 0o6000;
+
+    let file = File::open(zip).wrap_err("Could not open the zip file")?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).wrap_err("Invalid zip file")?;
+    let root = Path::new(new_root);
+    let mut dirs = Vec::new();
+    let mut stats = UnpackStats::default();
+    for i in 0..archive.len() {
+        options.deadline.check()?;
+        let mut entry = archive.by_index(i).wrap_err("Could not read a zip entry")?;
+        stats.entries += 1;
+        stats.bytes += entry.size();
+        options.check_limits(stats.entries, stats.bytes)?;
+        progress.on_entries(stats.entries);
+        progress.on_bytes(stats.bytes, None);
+        // Entries with absolute paths or `..` are skipped, as `unpack_in` does
+        let path = match entry.enclosed_name() {
+            Some(path) => sanitize(path),
+            None => {
+                stats.skipped.push(entry.name().into());
+                continue;
+            }
+        };
+        let path = match strip_components(&path, options.strip_components) {
+            Some(path) => path,
+            None => continue,
+        };
+        if is_under(&path, options.skip_dirs) {
+            continue;
+        }
+        let mut mode = entry.unix_mode().unwrap_or(0);
+        if entry.is_dir() {
+            // A link left by an earlier entry is followed inside of the root, as for tarballs
+            let target = resolve_in_root(root, &path)?;
+            create_dir_all(&target)
+                .wrap_err(format!("Could not create the directory {target:?}"))?;
+            dirs.push((path, mode & 0o7777));
+            continue;
+        }
+        let name = match path.file_name() {
+            Some(name) => name.to_os_string(),
+            None => continue,
+        };
+        let parent = resolve_in_root(root, path.parent().unwrap_or_else(|| Path::new("")))?;
+        create_dir_all(&parent).wrap_err(format!("Could not create the directory {parent:?}"))?;
+        let target = parent.join(name);
+        remove_entry(&target)?;
+        if mode & FILE_TYPE == SYMLINK {
+            let mut link = String::new();
+            entry.read_to_string(&mut link)?;
+            symlink(&link, &target).wrap_err(format!("Could not create the link {target:?}"))?;
+            if let Some(mtime) = options.mtime {
+                set_mtime(&target, mtime)?;
+            }
+            continue;
+        }
+        let mut output =
+            File::create(&target).wrap_err(format!("Could not create the file {target:?}"))?;
+        std::io::copy(&mut entry, &mut output)
+            .wrap_err(format!("Could not unpack the file {target:?}"))?;
+        if options.no_setuid {
+            mode &= !SETUID_SETGID;
+        }
+        if mode != 0 {
+            std::fs::set_permissions(&target, std::fs::Permissions::from_mode(mode & 0o7777))
+                .wrap_err(format!("Could not set the permissions of {target:?}"))?;
+        }
+        if let Some(mtime) = options.mtime {
+            set_mtime(&target, mtime)?;
+        }
+    }
+    dirs.sort_unstable_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+    for (path, mode) in dirs {
+        // Later entries may have replaced the directory, so it is resolved again
+        let dir = resolve_in_root(root, &path)?;
+        if !symlink_metadata(&dir).is_ok_and(|meta| meta.is_dir()) {
+            continue;
+        }
+        if let Some(mtime) = options.mtime {
+            set_mtime(&dir, mtime)?;
+        }
+        if mode != 0 {
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(mode))
+                .wrap_err(format!("Could not set the permissions of {dir:?}"))?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Image description in the `manifest.json` written by `docker save` and `podman save`
+#[derive(Deserialize)]
+struct SavedImage {
+    #[serde(rename = "Layers")]
+    layers: Vec<PathBuf>,
+}
+
+/// Layer of an image saved by the engine, as found by [`saved_layers`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SavedLayer {
+    /// Path of the layer in the saved image, after following the links to it
+    pub path: PathBuf,
+    /// Digest of the layer, when it is stored as a blob named after it
+    pub digest: Option<String>,
+    /// Size of the layer as stored in the saved image
+    pub size: u64,
+    /// Compression detected from the first bytes of the layer
+    pub compression: Compression,
+    position: u64,
+}
+
+/// Resolves the layers of an image saved by the engine, in the order they are applied,
+/// without unpacking them
+pub fn saved_layers(tar: &Path) -> eyre::Result<Vec<SavedLayer>> {
+    let file = File::open(tar).wrap_err("Could not open the saved image")?;
+    let mut archive = Archive::new(BufReader::new(file));
+    let mut manifest = None;
+    // Positions and sizes of the files, the layers are read from them afterwards
+    let mut files = HashMap::new();
+    let mut links = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = sanitize(&entry.path()?);
+        match entry.header().entry_type() {
+            EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    let target = link_target(&path, &target);
+                    links.insert(path, target);
+                }
+            }
+            EntryType::Regular if path == Path::new("manifest.json") => {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                let images: Vec<SavedImage> = serde_json::from_str(&content)
+                    .wrap_err("The manifest of the saved image is corrupted")?;
+                manifest = images.into_iter().next();
+            }
+            EntryType::Regular => {
+                files.insert(path, (entry.raw_file_position(), entry.size()));
+            }
+            _ => {}
+        }
+    }
+    let manifest =
+        manifest.ok_or_else(|| eyre::eyre!("The saved image does not have a manifest"))?;
+
+    let mut layers = Vec::with_capacity(manifest.layers.len());
+    for layer in &manifest.layers {
+        let mut path = sanitize(layer);
+        for _ in 0..MAX_LINKS {
+            match links.get(&path) {
+                Some(target) => path = target.clone(),
+                None => break,
+            }
+        }
+        let &(position, size) = files
+            .get(&path)
+            .ok_or_else(|| eyre::eyre!("The layer {path:?} is missing from the saved image"))?;
+        let mut file = File::open(tar).wrap_err("Could not open the saved image")?;
+        file.seek(SeekFrom::Start(position))?;
+        let mut magic = Vec::with_capacity(4);
+        file.take(size.min(4)).read_to_end(&mut magic)?;
+        layers.push(SavedLayer {
+            digest: blob_digest(&path),
+            compression: Compression::detect(&magic),
+            path,
+            size,
+            position,
+        });
+    }
+    Ok(layers)
+}
+
+/// Digest of a layer stored as `blobs/<algorithm>/<hex>`, like in the OCI layout
+fn blob_digest(path: &Path) -> Option<String> {
+    let mut components = path.iter().map(|c| c.to_str());
+    match (components.next()?, components.next()?, components.next()?) {
+        (Some("blobs"), Some(algorithm), Some(hex)) if components.next().is_none() => {
+            Some(format!("{algorithm}:{hex}"))
+        }
+        _ => None,
+    }
+}
+
+/// Unpacks the layers of an image saved by the engine into `new_root` in order, applying
+/// the whiteouts of each one of them to the layers below
+pub fn unpack_saved(
+    tar: &Path,
+    new_root: &str,
+    options: &UnpackOptions,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<UnpackStats> {
+    let layers = saved_layers(tar)?;
+    let options = UnpackOptions {
+        whiteouts: true,
+        ..*options
+    };
+    let count = layers.len();
+    let mut stats = UnpackStats::default();
+    for (i, layer) in layers.iter().enumerate() {
+        progress.on_phase(&format!("Unpacking layer {} of {count}", i + 1));
+        let mut file = File::open(tar).wrap_err("Could not open the saved image")?;
+        file.seek(SeekFrom::Start(layer.position))?;
+        let reader = BufReader::with_capacity(options.decoder.buffer, file.take(layer.size));
+        let (reader, _) = decoder(reader, &options.decoder)
+            .wrap_err(format!("Could not read the layer {:?}", layer.path))?;
+        let options = options.after(&stats);
+        stats.add(unpack_tar(reader, None, new_root, &options, progress)?);
+    }
+    Ok(stats)
+}
+
+/// Checks if the path is inside of one of the top-level directories, but not one of them
+fn is_under(path: &Path, dirs: &[&str]) -> bool {
+    let mut components = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)));
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(top)), Some(_)) => dirs.iter().any(|dir| top == *dir),
+        _ => false,
+    }
+}
+
+/// Resolves the target of a link inside of an archive, relative to the directory of the link
+fn link_target(link: &Path, target: &Path) -> PathBuf {
+    let mut path = link.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in target.components() {
+        match component {
+            Component::RootDir => path.clear(),
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(name) => path.push(name),
+            _ => {}
+        }
+    }
+    path
+}
+
+/// Directory created while unpacking that gets its metadata at the end, only what is
+/// needed is kept instead of the whole entry so memory stays bounded
+struct PendingDir {
+    path: PathBuf,
+    mode: u32,
+    mtime: u64,
+}
+
+impl PendingDir {
+    /// Creates the directory with the default permissions, unless it escapes the root
+    fn create<R: Read>(
+        root: &Path,
+        path: &Path,
+        entry: &Entry<'_, R>,
+        mtime: Option<u64>,
+    ) -> eyre::Result<Option<Self>> {
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Ok(None);
+        }
+        let target = resolve_in_root(root, sanitize(path))?;
+        create_dir_all(&target).wrap_err(format!("Could not create the directory {target:?}"))?;
+        Ok(Some(PendingDir {
+            path: target,
+            mode: entry.header().mode()?,
+            mtime: mtime.map_or_else(|| entry.header().mtime(), Ok)?,
+        }))
+    }
+
+    fn finish(self) -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let path = &self.path;
+        let mtime = UNIX_EPOCH + Duration::from_secs(self.mtime);
+        File::open(path)
+            .and_then(|dir| dir.set_modified(mtime))
+            .wrap_err(format!("Could not set the modification time of {path:?}"))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(self.mode & 0o7777))
+            .wrap_err(format!("Could not set the permissions of {path:?}"))
+    }
+}
+
+/// Sets the extended attributes of the entry on the unpacked path, without following it if it
+/// is a link. Some of them can not be set in a user namespace, like the `trusted.` ones, so
+/// they only produce a warning the first time each one fails.
+fn apply_xattrs<R: Read>(
+    entry: &mut Entry<'_, R>,
+    target: &Path,
+    failed: &mut HashSet<OsString>,
+    progress: &dyn ProgressSink,
+) -> eyre::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let extensions = match entry.pax_extensions()? {
+        Some(extensions) => extensions,
+        None => return Ok(()),
+    };
+    for extension in extensions {
+        let extension = extension.wrap_err("Could not read the PAX records of an entry")?;
+        let name = match extension.key_bytes().strip_prefix(PAX_XATTR_PREFIX) {
+            Some(name) => std::ffi::OsStr::from_bytes(name),
+            None => continue,
+        };
+        if let Err(e) = xattr::set(target, name, extension.value_bytes()) {
+            if failed.insert(name.to_os_string()) {
+                progress.on_warning(&format!(
+                    "Could not set the extended attribute {name:?} of {target:?}: {e}"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets the access and modification times of the path, without following it if it is a link
+fn set_mtime(path: &Path, mtime: u64) -> eyre::Result<()> {
+    use nix::sys::stat::{utimensat, UtimensatFlags};
+    use nix::sys::time::TimeSpec;
+
+    let time = TimeSpec::new(mtime as i64, 0);
+    utimensat(None, path, &time, &time, UtimensatFlags::NoFollowSymlink)
+        .wrap_err(format!("Could not set the modification time of {path:?}"))
+}
+
+/// Clears the setuid and setgid bits of the file unpacked at `target`
+fn clear_setuid<R: Read>(target: &Path, entry: &Entry<'_, R>) -> eyre::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    const SETUID_SETGID: u32 = 0o6000;
+    let mode = entry.header().mode()?;
+    if mode & SETUID_SETGID == 0 || !entry.header().entry_type().is_file() {
+        return Ok(());
+    }
+    let perms = std::fs::Permissions::from_mode(mode & 0o7777 & !SETUID_SETGID);
+    std::fs::set_permissions(target, perms)
+        .wrap_err(format!("Could not clear the setuid bits of {target:?}"))
+}
+
+fn apply_whiteout(root: &Path, whiteout: &Whiteout, layer: &HashSet<PathBuf>) -> eyre::Result<()> {
+    match whiteout {
+        Whiteout::Entry(path) => {
+            let parent = path.parent().unwrap_or_else(|| Path::new(""));
+            let name = path.file_name().expect("whiteouts have a name");
+            remove_entry(&resolve_in_root(root, parent)?.join(name))
+        }
+        Whiteout::Opaque(dir) => {
+            let entries = match read_dir(resolve_in_root(root, dir)?) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e).wrap_err(format!("Could not read the directory {dir:?}")),
+            };
+            for entry in entries {
+                let entry = entry?;
+                if !layer.contains(&dir.join(entry.file_name())) {
+                    remove_entry(&entry.path())?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Removes a file or a whole directory, without following symlinks
+fn remove_entry(path: &Path) -> eyre::Result<()> {
+    let removed = match symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    removed.wrap_err(format!("Could not remove {path:?}"))
+}
+
+/// Removes the whiteout files left in the root, returning how many there were
+pub fn remove_leftover_whiteouts(root: &Path) -> eyre::Result<usize> {
+    let leftovers: Vec<PathBuf> = WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(WHITEOUT_PREFIX))
+        .map(|e| e.into_path())
+        .collect();
+    for path in &leftovers {
+        remove_entry(path)?;
+    }
+    Ok(leftovers.len())
+}
+
+/// Keeps only the normal components of the path, the same sanitization as `unpack_in`
+pub fn sanitize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+/// Resolves `path` inside of `root` as if `root` was `/`, following symlinks without
+/// ever leaving the rootfs. Missing components are kept as they are.
+pub fn resolve_in_root<P: AsRef<Path>>(root: &Path, path: P) -> eyre::Result<PathBuf> {
+    let path = path.as_ref();
+    let mut resolved = PathBuf::new();
+    let mut pending = components(path);
+    let mut links = 0;
+    while let Some(component) = pending.pop() {
+        if component == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&component);
+        let full = root.join(&candidate);
+        match symlink_metadata(&full) {
+            Ok(meta) if meta.is_symlink() => {
+                links += 1;
+                eyre::ensure!(
+                    links <= MAX_LINKS,
+                    "Too many levels of symbolic links in {path:?}"
+                );
+                let link =
+                    read_link(&full).wrap_err(format!("Could not read the link {full:?}"))?;
+                if link.is_absolute() {
+                    resolved.clear();
+                }
+                pending.append(&mut components(&link));
+            }
+            _ => resolved = candidate,
+        }
+    }
+    Ok(root.join(resolved))
+}
+
+/// Normal and parent components of a path in reverse order, to be used as a stack
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some("..".into()),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use color_eyre::eyre;
+use color_eyre::eyre::WrapErr;
+
+use crate::unpack::resolve_in_root;
+
+/// Largest id that is mapped inside of a toolbox, see [`crate::namespaces::toolbox_mappings`]
+const MAX_MAPPED_ID: u32 = 65536;
+/// First id of the users created in a toolbox, as in most distributions
+const FIRST_USER_ID: u32 = 1000;
+
+/// Ids a command is run with inside of a toolbox
+#[derive(PartialEq, Eq, Debug)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Home directory of the user, if it is in the passwd file of the rootfs
+    pub home: Option<String>,
+}
+
+/// Resolves a user given as in the `USER` directive of an image: `user`, `uid`, `user:group`
+/// or `uid:gid`. Names are looked up in the passwd and group files of the rootfs, and the
+/// primary group of the user is used if no group is given.
+pub fn resolve(root: &Path, spec: &str) -> eyre::Result<Credentials> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    let passwd = read_etc(root, "etc/passwd")?;
+    // name:password:uid:gid:gecos:home:shell
+    let entry = passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() >= 7 && (fields[0] == user || fields[2] == user));
+    let (uid, primary, home) = match (entry, user.parse::<u32>()) {
+        (Some(fields), _) => (
+            parse_id(fields[2], spec)?,
+            Some(parse_id(fields[3], spec)?),
+            Some(fields[5].to_string()),
+        ),
+        (None, Ok(uid)) => (uid, None, None),
+        (None, Err(_)) => eyre::bail!("The user {user} does not exist in the toolbox"),
+    };
+    let gid = match (group, primary) {
+        (Some(group), _) => group_id(root, group)?,
+        (None, Some(gid)) => gid,
+        // A numeric user without an entry in passwd keeps the group of root, like docker
+        (None, None) => 0,
+    };
+    eyre::ensure!(
+        uid <= MAX_MAPPED_ID && gid <= MAX_MAPPED_ID,
+        "The ids of {spec} are not mapped inside of the toolbox"
+    );
+    Ok(Credentials { uid, gid, home })
+}
+
+/// First id from 1000 that no entry of a passwd or group file of the rootfs has, the id of
+/// both is their third field
+pub fn unused_id(root: &Path, path: &str) -> eyre::Result<u32> {
+    let used: Vec<u32> = read_etc(root, path)?
+        .lines()
+        .filter_map(|line| line.split(':').nth(2)?.parse().ok())
+        .collect();
+    (FIRST_USER_ID..=MAX_MAPPED_ID)
+        .find(|id| !used.contains(id))
+        .ok_or_else(|| eyre::eyre!("Every id mapped inside of the toolbox is used by /{path}"))
+}
+
+fn group_id(root: &Path, group: &str) -> eyre::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    // name:password:gid:members
+    read_etc(root, "etc/group")?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() >= 3 && fields[0] == group)
+        .map(|fields| parse_id(fields[2], group))
+        .unwrap_or_else(|| eyre::bail!("The group {group} does not exist in the toolbox"))
+}
+
+fn parse_id(id: &str, spec: &str) -> eyre::Result<u32> {
+    id.parse()
+        .wrap_err(format!("The entry of {spec} has an invalid id {id}"))
+}
+
+/// Contents of a file of the rootfs, which is empty if it does not exist
+fn read_etc(root: &Path, path: &str) -> eyre::Result<String> {
+    match std::fs::read_to_string(resolve_in_root(root, path)?) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).wrap_err(format!("Could not read /{path} of the toolbox")),
+    }
+}
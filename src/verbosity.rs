@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// How much a command prints besides the errors, which are always shown
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Verbosity {
+    /// Nothing, with `--silent`
+    Silent,
+    /// A final line with the result, with `--quiet`
+    Quiet,
+    /// The progress and the result
+    Normal,
+    /// The debugging messages too, with `--verbose`
+    Verbose,
+}
+
+impl Verbosity {
+    /// Resolves the level from the flags of a command, `silent` taking precedence
+    pub fn from_flags(quiet: bool, silent: bool) -> Self {
+        match (silent, quiet) {
+            (true, _) => Verbosity::Silent,
+            (_, true) => Verbosity::Quiet,
+            _ if is_verbose() => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        }
+    }
+
+    pub fn shows_progress(self) -> bool {
+        self >= Verbosity::Normal
+    }
+
+    pub fn shows_result(self) -> bool {
+        self >= Verbosity::Quiet
+    }
+
+    pub fn shows_warnings(self) -> bool {
+        self >= Verbosity::Normal
+    }
+}
+
+impl Default for Verbosity {
+    /// The level of the commands without `--quiet` or `--silent`
+    fn default() -> Self {
+        Verbosity::from_flags(false, false)
+    }
+}
+
+/// Enables or disables the debugging messages for the rest of the execution
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Prints a non-fatal warning to stderr, unless they are hidden by `--quiet` or `--silent`.
+/// Every warning goes through here, so the details about it are printed with [`debug`].
+pub fn warn(verbosity: Verbosity, msg: impl Display) {
+    if verbosity.shows_warnings() {
+        eprintln!("Warning: {msg}");
+    }
+}
+
+/// Prints a debugging message to stderr when running with `--verbose`
+pub fn debug(msg: impl Display) {
+    if is_verbose() {
+        eprintln!("debug: {msg}");
+    }
+}
@@ -0,0 +1,72 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use clap::{Args, ValueEnum};
+use color_eyre::eyre;
+use serde::Serialize;
+
+use crate::archive::Compression;
+use crate::engine::Engine;
+
+/// Show the version of unbox and what this build supports
+#[derive(Args, PartialEq, Eq, Debug)]
+pub struct Version {
+    #[clap(long, value_parser)]
+    /// Print the build information as JSON, for bug reports and scripts
+    pub json: bool,
+}
+
+/// Description of the build, to tell which capabilities are compiled in
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Commit the binary was built from, if it was built from a git checkout
+    pub commit: Option<&'static str>,
+    pub target: &'static str,
+    pub engines: Vec<String>,
+    pub compression: Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn new() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("UNBOX_GIT_COMMIT"),
+            target: env!("UNBOX_TARGET"),
+            engines: names(Engine::value_variants(), |_| true),
+            compression: names(Compression::value_variants(), |c| c.is_supported()),
+        }
+    }
+}
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        BuildInfo::new()
+    }
+}
+
+/// Names of the supported variants as they are given in the command line
+fn names<V: ValueEnum>(variants: &[V], supported: impl Fn(&V) -> bool) -> Vec<String> {
+    variants
+        .iter()
+        .filter(|v| supported(v))
+        .filter_map(|v| v.to_possible_value())
+        .map(|v| v.get_name().to_string())
+        .collect()
+}
+
+pub fn version(args: Version) -> eyre::Result<()> {
+    let info = BuildInfo::new();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    match info.commit {
+        Some(commit) => println!("unbox {} ({commit}, {})", info.version, info.target),
+        None => println!("unbox {} ({})", info.version, info.target),
+    }
+    println!("engines: {}", info.engines.join(", "));
+    println!("compression: {}", info.compression.join(", "));
+    Ok(())
+}
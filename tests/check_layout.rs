@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use unbox::config::MountInfo;
+use unbox::run::{check_layout, SystemMounts};
+
+#[test]
+fn missing_mountpoints_are_recreated() {
+    let root = TempDir::new("check-layout");
+    std::fs::create_dir_all(root.join("sys")).unwrap();
+    std::fs::create_dir_all(root.join("etc")).unwrap();
+    std::fs::write(root.join("etc/hosts"), "").unwrap();
+    let mounts: Vec<MountInfo> = vec![
+        ("/host/tmp", "/tmp").into(),
+        ("/host/etc/hosts", "/etc/hosts").into(),
+        ("/host/missing-on-the-host", "/missing").into(),
+    ];
+
+    check_layout(&root, &SystemMounts::default(), &mounts).unwrap();
+    for dir in ["host", "proc", "sys", "tmp"] {
+        assert!(root.join(dir).is_dir(), "{dir} was not recreated");
+    }
+    // Mountpoints of files and of sources missing on the host are left to the image
+    assert!(root.join("etc/hosts").is_file());
+    assert!(!root.join("missing").exists());
+
+    let root = TempDir::new("check-layout-no-proc");
+    let system = SystemMounts {
+        no_proc: true,
+        ..Default::default()
+    };
+    check_layout(&root, &system, &[]).unwrap();
+    assert!(root.join("host").is_dir());
+    assert!(!root.join("proc").exists());
+}
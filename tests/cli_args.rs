@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use clap::{Args, Command};
+use unbox::{create::Create, export::Export, run::Enter, run::Run};
+
+// The ids used by conflicts_with and requires are only checked when the command is built
+#[test]
+fn arguments_are_consistent() {
+    Create::augment_args(Command::new("create")).debug_assert();
+    Enter::augment_args(Command::new("enter")).debug_assert();
+    Run::augment_args(Command::new("run")).debug_assert();
+    Export::augment_args(Command::new("export")).debug_assert();
+}
+
+#[test]
+fn toolboxes_can_not_be_named_like_the_subcommands_of_config() {
+    let config = unbox::config::Configure::augment_args(Command::new("config"));
+    let names: Vec<_> = config.get_subcommands().map(|cmd| cmd.get_name()).collect();
+    assert!(!names.is_empty());
+    for name in names {
+        let create = Create::augment_args(Command::new("create"));
+        assert!(create.try_get_matches_from(["create", name]).is_err());
+    }
+    let create = Create::augment_args(Command::new("create"));
+    assert!(create.try_get_matches_from(["create", "dev"]).is_ok());
+}
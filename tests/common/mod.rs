@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fixtures shared by the integration tests
+
+// Every test binary compiles its own copy and uses only some of them
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tar::{Builder, EntryType, Header};
+use unbox::progress::ProgressSink;
+
+/// Empty directory of a single test, removed when it is dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// The process id and a counter keep concurrent tests and runs apart
+    pub fn new(name: &str) -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        let dir = std::env::temp_dir().join(format!("unbox-{name}-{pid}-{count}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Appends a regular file with mode 0644 and a fixed mtime
+pub fn append(builder: &mut Builder<Vec<u8>>, path: &str, content: &[u8]) {
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(1_600_000_000);
+    header.set_cksum();
+    builder.append_data(&mut header, path, content).unwrap();
+}
+
+/// Tarball of regular files given by their path and contents
+pub fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for (path, content) in files {
+        append(&mut builder, path, content.as_bytes());
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Tarball of empty files, used as the layer of an image
+pub fn layer(files: &[&str]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for file in files {
+        append(&mut builder, file, b"");
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Image in the format of `docker save`, where the second layer is a link to a blob
+pub fn saved_image() -> Vec<u8> {
+    let manifest = br#"[{"Config":"config.json","Layers":["lower/layer.tar","upper/layer.tar"]}]"#;
+    let mut builder = Builder::new(Vec::new());
+    append(&mut builder, "manifest.json", manifest);
+    append(
+        &mut builder,
+        "lower/layer.tar",
+        &layer(&["etc/old", "etc/kept"]),
+    );
+    append(
+        &mut builder,
+        "blobs/sha256/upper",
+        &layer(&["etc/.wh.old", "etc/new"]),
+    );
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_cksum();
+    builder
+        .append_link(&mut header, "upper/layer.tar", "../blobs/sha256/upper")
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+/// PAX record, whose length includes the length itself
+pub fn record(key: &str, value: &str) -> String {
+    let len = key.len() + value.len() + 3;
+    let len = len + (len + 1).to_string().len();
+    format!("{len} {key}={value}\n")
+}
+
+/// Sink keeping the warnings to check them
+#[derive(Default)]
+pub struct Warnings(pub RefCell<Vec<String>>);
+
+impl ProgressSink for Warnings {
+    fn on_phase(&self, _name: &str) {}
+    fn on_warning(&self, msg: &str) {
+        self.0.borrow_mut().push(msg.to_string());
+    }
+}
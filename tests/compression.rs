@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+use unbox::archive::*;
+
+fn roundtrip(compression: Compression) {
+    let content = b"unbox compression roundtrip".repeat(64);
+    let mut encoder = Encoder::new(Vec::new(), compression).unwrap();
+    encoder.write_all(&content).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let (mut decoder, detected) =
+        decoder(Cursor::new(compressed), &DecoderOptions::default()).unwrap();
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(detected, compression);
+    assert_eq!(decompressed, content);
+}
+
+#[test]
+fn roundtrip_none() {
+    roundtrip(Compression::None);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn roundtrip_gzip() {
+    roundtrip(Compression::Gzip);
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn roundtrip_zstd() {
+    roundtrip(Compression::Zstd);
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn zstd_windows_can_be_capped() {
+    // Streamed without a known size, so the frame keeps the window of the level
+    let content: Vec<u8> = (0..4u32 << 20).map(|i| (i * 7 % 251) as u8).collect();
+    let mut encoder = Encoder::new(Vec::new(), Compression::Zstd).unwrap();
+    encoder.write_all(&content).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let read = |options: DecoderOptions| {
+        let (mut decoder, _) = decoder(Cursor::new(compressed.clone()), &options)?;
+        decoder.read_to_end(&mut Vec::new())
+    };
+    assert_eq!(read(DecoderOptions::default()).unwrap(), content.len());
+    let capped = DecoderOptions {
+        zstd_window_log: Some(10),
+        ..Default::default()
+    };
+    assert!(read(capped).is_err());
+}
+
+#[test]
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn missing_backends_are_reported() {
+    for compression in [Compression::Gzip, Compression::Zstd] {
+        if compression.is_supported() {
+            continue;
+        }
+        let error = Encoder::new(Vec::new(), compression).err().unwrap();
+        assert!(error.to_string().contains("this build lacks"), "{error}");
+        let mut compressed = Vec::new();
+        if compression == Compression::Gzip {
+            compressed.extend_from_slice(&[0x1f, 0x8b, 0x08, 0x00]);
+        } else {
+            compressed.extend_from_slice(&[0x28, 0xb5, 0x2f, 0xfd]);
+        }
+        assert!(decoder(Cursor::new(compressed), &DecoderOptions::default()).is_err());
+    }
+}
+
+#[test]
+fn compression_from_extension() {
+    let cases = [
+        ("rootfs.tar", Compression::None),
+        ("rootfs.tar.gz", Compression::Gzip),
+        ("rootfs.tgz", Compression::Gzip),
+        ("rootfs.tar.zst", Compression::Zstd),
+    ];
+    for (name, compression) in cases {
+        assert_eq!(Compression::from_extension(Path::new(name)), compression);
+    }
+}
@@ -0,0 +1,66 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::Path;
+
+use common::TempDir;
+use unbox::clone::{copy_tree, CopyMode, CopyOptions};
+use unbox::progress::NoProgress;
+
+/// Rootfs with a file, a link to its directory and links to the file
+fn source(dir: &Path) -> std::path::PathBuf {
+    let source = dir.join("source");
+    std::fs::create_dir_all(source.join("usr/bin")).unwrap();
+    std::fs::write(source.join("usr/bin/tool"), "#!/bin/sh\n").unwrap();
+    symlink("usr/bin", source.join("bin")).unwrap();
+    symlink("/usr/bin/tool", source.join("usr/bin/absolute")).unwrap();
+    symlink("missing", source.join("usr/bin/dangling")).unwrap();
+    source
+}
+
+#[test]
+fn directories_are_copied_or_linked_into_existing_roots() {
+    let dir = TempDir::new("copy-tree");
+    let source = source(&dir);
+
+    for mode in [CopyMode::Copy, CopyMode::Link] {
+        let target = dir.join(format!("{mode:?}"));
+        std::fs::create_dir_all(&target).unwrap();
+        let options = CopyOptions {
+            mode,
+            dereference: false,
+        };
+        let stats = copy_tree(&source, &target, options, &NoProgress).unwrap();
+        assert_eq!(stats.entries, 7);
+        assert!(stats.skipped.is_empty());
+        let tool = std::fs::read_to_string(target.join("bin/tool")).unwrap();
+        assert_eq!(tool, "#!/bin/sh\n");
+        let ino = |root: &Path| std::fs::metadata(root.join("usr/bin/tool")).unwrap().ino();
+        assert_eq!(ino(&target) == ino(&source), mode == CopyMode::Link);
+        let link = std::fs::read_link(target.join("usr/bin/absolute")).unwrap();
+        assert_eq!(link, Path::new("/usr/bin/tool"));
+    }
+}
+
+#[test]
+fn links_to_files_are_dereferenced_inside_of_the_source() {
+    let dir = TempDir::new("copy-tree-dereference");
+    let source = source(&dir);
+    let target = dir.join("target");
+    let options = CopyOptions {
+        mode: CopyMode::Copy,
+        dereference: true,
+    };
+    let stats = copy_tree(&source, &target, options, &NoProgress).unwrap();
+    assert_eq!(stats.skipped, [source.join("usr/bin/dangling")]);
+    // The absolute link is resolved in the source, not in the host
+    let absolute = target.join("usr/bin/absolute");
+    assert!(absolute.symlink_metadata().unwrap().is_file());
+    assert_eq!(std::fs::read_to_string(absolute).unwrap(), "#!/bin/sh\n");
+    assert!(target.join("bin").symlink_metadata().unwrap().is_symlink());
+    assert!(target.join("usr/bin/dangling").symlink_metadata().is_err());
+}
@@ -0,0 +1,38 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use unbox::create::copy_up;
+
+#[test]
+fn host_files_are_copied_into_the_home_of_the_toolbox() {
+    let dir = TempDir::new("copy-up");
+    let host_home = dir.join("host-home");
+    std::fs::create_dir_all(host_home.join(".config/git")).unwrap();
+    std::fs::write(host_home.join(".bashrc"), "alias ll='ls -l'\n").unwrap();
+    std::fs::write(host_home.join(".config/git/config"), "[user]\n").unwrap();
+    let other = dir.join("tool.conf");
+    std::fs::write(&other, "verbose\n").unwrap();
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+
+    // Only this test of the binary reads HOME
+    std::env::set_var("HOME", &host_home);
+    let files = [
+        host_home.join(".bashrc"),
+        host_home.join(".config/git/config"),
+        other.clone(),
+    ];
+    copy_up(&root, "/home/user", &files).unwrap();
+    let read = |path: &str| std::fs::read_to_string(root.join(path)).unwrap();
+    assert_eq!(read("home/user/.bashrc"), "alias ll='ls -l'\n");
+    assert_eq!(read("home/user/.config/git/config"), "[user]\n");
+    let relative = other.strip_prefix("/").unwrap();
+    assert_eq!(
+        std::fs::read_to_string(root.join(relative)).unwrap(),
+        "verbose\n"
+    );
+}
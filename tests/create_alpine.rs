@@ -9,11 +9,12 @@ use unbox::remove::*;
 fn create_alpine() {
     let args = Create {
         name: "alpine-test".into(),
-        tar: None,
+        tar: Vec::new(),
         image: Some("docker.io/alpine:edge".into()),
         engine: Some(Engine::Podman),
         shell: None,
         quiet: true,
+        ..Default::default()
     };
     create(args).unwrap();
 
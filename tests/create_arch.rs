@@ -9,11 +9,12 @@ use unbox::remove::*;
 fn create_arch() {
     let args = Create {
         name: "arch-test".into(),
-        tar: None,
+        tar: Vec::new(),
         image: Some("docker.io/archlinux".into()),
         engine: Some(Engine::Podman),
         shell: None,
         quiet: true,
+        ..Default::default()
     };
     create(args).unwrap();
 
@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use common::{saved_image, TempDir};
+use unbox::config::{set_storage_root, Config};
+
+#[test]
+fn create_docker_archive() {
+    let storage = TempDir::new("create-docker-archive");
+    set_storage_root(&storage);
+    let archive = storage.join("image.tar");
+    std::fs::write(&archive, saved_image()).unwrap();
+
+    // A process with the threads of the tests can not enter a user namespace, the binary is
+    // run instead and gets the storage root from the environment
+    let unbox = env!("CARGO_BIN_EXE_unbox");
+    let status = Command::new(unbox)
+        .args([
+            "create",
+            "docker-archive-test",
+            "--shell",
+            "/bin/sh",
+            "--quiet",
+        ])
+        .arg("--docker-archive")
+        .arg(&archive)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let config = Config::read("docker-archive-test").unwrap();
+    let root = Path::new(&config.image);
+    assert!(config.flattened);
+    assert!(root.join("etc/kept").exists());
+    assert!(root.join("etc/new").exists());
+    assert!(!root.join("etc/old").exists());
+    assert!(!root.join("etc/.wh.old").exists());
+
+    let status = Command::new(unbox)
+        .args(["remove", "docker-archive-test"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use clap::{Args, Command, FromArgMatches};
+use unbox::create::Create;
+use unbox::engine::Engine;
+
+fn parse(args: &[&str]) -> Create {
+    let matches = Create::augment_args(Command::new("create"))
+        .try_get_matches_from(args)
+        .unwrap();
+    Create::from_arg_matches(&matches).unwrap()
+}
+
+// The variables are shared by the whole process, so every case is in a single test
+#[test]
+fn defaults_are_read_from_the_environment() {
+    std::env::set_var("UNBOX_SHELL", "/bin/zsh");
+    std::env::set_var("UNBOX_ENGINE", "docker");
+    std::env::set_var("UNBOX_QUIET", "yes");
+    let create = parse(&["create", "dev", "-t", "rootfs.tar"]);
+    assert_eq!(create.shell.as_deref(), Some("/bin/zsh"));
+    assert_eq!(create.engine, Some(Engine::Docker));
+    assert!(create.quiet);
+
+    // The flags still win over the environment
+    let create = parse(&[
+        "create",
+        "dev",
+        "-t",
+        "rootfs.tar",
+        "-s",
+        "/bin/bash",
+        "-e",
+        "podman",
+    ]);
+    assert_eq!(create.shell.as_deref(), Some("/bin/bash"));
+    assert_eq!(create.engine, Some(Engine::Podman));
+
+    std::env::set_var("UNBOX_QUIET", "off");
+    assert!(!parse(&["create", "dev", "-t", "rootfs.tar"]).quiet);
+    for name in ["UNBOX_SHELL", "UNBOX_ENGINE", "UNBOX_QUIET"] {
+        std::env::remove_var(name);
+    }
+    let create = parse(&["create", "dev", "-t", "rootfs.tar"]);
+    assert_eq!(
+        (create.shell, create.engine, create.quiet),
+        (None, None, false)
+    );
+}
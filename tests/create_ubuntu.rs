@@ -9,11 +9,12 @@ use unbox::remove::*;
 fn create_ubuntu() {
     let args = Create {
         name: "ubuntu-test".into(),
-        tar: None,
+        tar: Vec::new(),
         image: Some("docker.io/ubuntu".into()),
         engine: Some(Engine::Podman),
         shell: None,
         quiet: true,
+        ..Default::default()
     };
     create(args).unwrap();
 
@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::time::Duration;
+
+use common::TempDir;
+use tar::{Builder, Header};
+use unbox::deadline::*;
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+#[test]
+fn durations_are_parsed() {
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+    assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    for invalid in ["", "0", "m", "5d", "1.5m", "-1"] {
+        assert!(parse_duration(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn unpacking_stops_at_the_deadline() {
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "etc/motd", &b"base\n"[..])
+        .unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    let root = TempDir::new("deadline");
+    let options = UnpackOptions {
+        deadline: Deadline::after(Some(Duration::ZERO)),
+        ..Default::default()
+    };
+    let root_str = root.to_str().unwrap();
+    let error = unpack_tar(&tar[..], None, root_str, &options, &NoProgress).unwrap_err();
+    assert!(is_timeout(&error));
+    assert!(!root.join("etc/motd").exists());
+
+    let options = UnpackOptions::default();
+    unpack_tar(&tar[..], None, root_str, &options, &NoProgress).unwrap();
+    assert!(root.join("etc/motd").exists());
+}
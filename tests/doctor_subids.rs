@@ -0,0 +1,23 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::doctor::covers;
+
+#[test]
+fn the_whole_range_has_to_be_given_to_the_user() {
+    let subuid = "root:200000:65536\nalice:100000:65536\n1001:300000:1000\n";
+    assert!(covers(subuid, "alice", 1000, 100000, 65536));
+    assert!(!covers(subuid, "bob", 1001, 100000, 65536));
+    // Users can also be given by id, but only part of the range is not enough
+    assert!(covers(subuid, "bob", 1001, 300000, 1000));
+    assert!(!covers(subuid, "bob", 1001, 300000, 1001));
+    assert!(covers("alice:0:4294967295", "alice", 1000, 100000, 65536));
+    assert!(!covers(
+        "alice:100000\n# comment\n",
+        "alice",
+        1000,
+        100000,
+        1
+    ));
+}
@@ -0,0 +1,68 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::path::Path;
+
+use common::TempDir;
+use unbox::deadline::Deadline;
+use unbox::download::{download, is_url};
+use unbox::progress::NoProgress;
+
+#[test]
+fn only_http_urls_are_downloaded() {
+    assert!(is_url(Path::new("https://example.com/rootfs.tar.gz")));
+    assert!(is_url(Path::new("http://localhost:8000/rootfs.tar")));
+    assert!(!is_url(Path::new("rootfs.tar")));
+    assert!(!is_url(Path::new("-")));
+    assert!(!is_url(Path::new("ftp://example.com/rootfs.tar")));
+}
+
+#[test]
+fn failed_downloads_are_errors() {
+    // curl reads file urls the same way, which avoids a server in the tests
+    let dir = TempDir::new("download");
+    let source = dir.join("rootfs.tar");
+    std::fs::write(&source, "not really a tarball").unwrap();
+    let output = dir.join("downloaded");
+    let url = format!("file://{}", source.display());
+    download(&url, &output, Deadline::default(), &NoProgress).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "not really a tarball"
+    );
+
+    let missing = format!("file://{}", dir.join("missing.tar").display());
+    let failed = dir.join("failed");
+    let error = download(&missing, &failed, Deadline::default(), &NoProgress).unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with(&format!("Could not download {missing}: ")));
+}
+
+#[test]
+fn downloads_do_not_follow_links() {
+    let dir = TempDir::new("download-link");
+    let source = dir.join("rootfs.tar");
+    std::fs::write(&source, "not really a tarball").unwrap();
+    let target = dir.join("target");
+    std::fs::write(&target, "kept").unwrap();
+    let output = dir.join("downloaded");
+    std::os::unix::fs::symlink(&target, &output).unwrap();
+    let url = format!("file://{}", source.display());
+    assert!(download(&url, &output, Deadline::default(), &NoProgress).is_err());
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "kept");
+}
+
+#[test]
+fn stalled_downloads_time_out() {
+    // The connection is queued by the kernel but never answered
+    let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/rootfs.tar", server.local_addr().unwrap());
+    let dir = TempDir::new("download-stalled");
+    let deadline = Deadline::after(Some(std::time::Duration::from_millis(500)));
+    let error = download(&url, &dir.join("downloaded"), deadline, &NoProgress).unwrap_err();
+    assert!(unbox::deadline::is_timeout(&error));
+}
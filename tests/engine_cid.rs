@@ -0,0 +1,21 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::engine::container_id;
+
+const CID: &str = "3f4c9e8a51b2d7e06a9c1f2e8b7d4c3a2f1e0d9c8b7a6f5e4d3c2b1a0f9e8d7c";
+
+#[test]
+fn id_is_the_last_line() {
+    assert_eq!(container_id(format!("{CID}\n").as_bytes()).unwrap(), CID);
+    let output = format!("Trying to pull docker.io/library/alpine:latest...\n{CID}\n\n");
+    assert_eq!(container_id(output.as_bytes()).unwrap(), CID);
+}
+
+#[test]
+fn other_output_is_rejected() {
+    assert!(container_id(b"").is_err());
+    assert!(container_id(b"\n  \n").is_err());
+    assert!(container_id(format!("{CID}\nWARNING: image platform mismatch\n").as_bytes()).is_err());
+}
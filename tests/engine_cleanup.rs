@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use common::TempDir;
+use unbox::engine::*;
+use unbox::progress::NoProgress;
+
+#[test]
+fn container_is_removed_when_the_export_fails() {
+    let dir = TempDir::new("engine-cleanup");
+    let removed = dir.join("removed");
+    // Engine whose export always fails, recording the id of the container it removes
+    let engine = dir.join("engine");
+    let script = format!(
+        "#!/bin/sh\ncase $1 in\n\
+         create) echo 3f4c9e8a51b2d7e0 ;;\n\
+         export) echo 'no space left' >&2; exit 1 ;;\n\
+         rm) echo $2 > {} ;;\n\
+         esac\n",
+        removed.display()
+    );
+    std::fs::write(&engine, script).unwrap();
+    std::fs::set_permissions(&engine, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let custom = CustomEngine {
+        engine_cmd: Some(engine.to_str().unwrap().into()),
+        ..Default::default()
+    };
+    let engine = EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap();
+    let output = dir.join("rootfs.tar");
+    let error = get_image(&engine, "alpine", output.to_str().unwrap(), &NoProgress).unwrap_err();
+    assert!(error.to_string().contains("no space left"));
+    let cid = std::fs::read_to_string(&removed).unwrap();
+    assert_eq!(cid, "3f4c9e8a51b2d7e0\n");
+}
@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::engine::*;
+
+const IMAGES: &str = "docker.io/library/alpine:latest
+docker.io/library/alpine:edge
+<none>:<none>
+docker.io/library/alpine:latest
+quay.io/fedora/fedora:39
+";
+
+#[test]
+fn names_are_completed_on_path_boundaries() {
+    assert_eq!(
+        matching_images(IMAGES, "fedora:39"),
+        ["quay.io/fedora/fedora:39"]
+    );
+    assert_eq!(
+        matching_images(IMAGES, "fedora/fedora:39"),
+        ["quay.io/fedora/fedora:39"]
+    );
+    assert!(matching_images(IMAGES, "dora:39").is_empty());
+    assert!(matching_images(IMAGES, "fedora:latest").is_empty());
+    assert!(matching_images(IMAGES, "none").is_empty());
+}
+
+#[test]
+fn names_found_by_the_engine_are_kept() {
+    // Both tags are stored, but the engine finds alpine:latest by itself
+    assert_eq!(matching_images(IMAGES, "alpine:latest"), ["alpine:latest"]);
+    assert_eq!(
+        matching_images(IMAGES, "docker.io/library/alpine:edge"),
+        ["docker.io/library/alpine:edge"]
+    );
+    // Another tag is pulled instead of using the stored ones
+    assert!(matching_images(IMAGES, "alpine:3.19").is_empty());
+}
+
+#[test]
+fn ambiguous_names_fail_without_a_terminal() {
+    // printf repeats the format for each argument, printing one reference per line
+    let custom = CustomEngine {
+        engine_cmd: Some("printf".into()),
+        engine_images: Some(
+            "%s\\n docker.io/library/alpine:latest quay.io/tools/alpine:edge ghcr.io/alpine:edge"
+                .into(),
+        ),
+        ..Default::default()
+    };
+    let engine = EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap();
+    let error = resolve_image(&engine, "alpine:edge", DEFAULT_TAG, false).unwrap_err();
+    assert!(error.to_string().contains("quay.io/tools/alpine:edge"));
+    let image = resolve_image(&engine, "tools/alpine", "edge", false).unwrap();
+    assert_eq!(image, "quay.io/tools/alpine:edge");
+    let image = resolve_image(&engine, "alpine", DEFAULT_TAG, false).unwrap();
+    assert_eq!(image, "alpine:latest");
+    let image = resolve_image(&engine, "ubuntu", DEFAULT_TAG, false).unwrap();
+    assert_eq!(image, "ubuntu:latest");
+}
+
+#[test]
+fn default_tags_are_only_added_without_tag_or_digest() {
+    assert_eq!(with_default_tag("alpine", "stable"), "alpine:stable");
+    assert_eq!(with_default_tag("alpine:edge", "stable"), "alpine:edge");
+    assert_eq!(
+        with_default_tag("localhost:5000/tools/alpine", DEFAULT_TAG),
+        "localhost:5000/tools/alpine:latest"
+    );
+    let pinned = format!("alpine@sha256:{}", "0".repeat(64));
+    assert_eq!(with_default_tag(&pinned, "stable"), pinned);
+}
@@ -0,0 +1,49 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::engine::*;
+
+fn printing(labels: &str) -> EngineCommands {
+    let custom = CustomEngine {
+        engine_cmd: Some("printf".into()),
+        engine_labels: Some(format!("%s {labels}")),
+        ..Default::default()
+    };
+    EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap()
+}
+
+#[test]
+fn labels_are_read_from_the_engine() {
+    let engine = printing(r#"{"org.opencontainers.image.version":"3.19"}"#);
+    let labels = image_labels(&engine, "alpine").unwrap();
+    assert_eq!(labels["org.opencontainers.image.version"], "3.19");
+    assert!(image_labels(&printing("null"), "alpine")
+        .unwrap()
+        .is_empty());
+    assert!(image_labels(&printing("[]"), "alpine").is_err());
+}
+
+#[test]
+fn digests_are_read_from_the_engine() {
+    let digest = format!("sha256:{}", "a".repeat(64));
+    let custom = |format: &str| CustomEngine {
+        engine_cmd: Some("printf".into()),
+        engine_digest: Some(format!("{format} {{image}}")),
+        ..Default::default()
+    };
+    let engine = |custom| EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap();
+    let bare = engine(custom(&format!("{digest}%.0s")));
+    assert_eq!(
+        stored_digest(&bare, "alpine").unwrap(),
+        Some(digest.clone())
+    );
+    let named = engine(custom(&format!("%s@{digest}")));
+    assert_eq!(stored_digest(&named, "alpine").unwrap(), Some(digest));
+    assert!(stored_digest(&engine(custom("sha256:abc%.0s")), "alpine").is_err());
+    let none = CustomEngine {
+        engine_cmd: Some("printf".into()),
+        ..Default::default()
+    };
+    assert_eq!(stored_digest(&engine(none), "alpine").unwrap(), None);
+}
@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use unbox::engine::*;
+
+#[test]
+fn engine_commands_are_logged_in_the_artifacts() {
+    let dir = TempDir::new("engine-log");
+    let custom = CustomEngine {
+        engine_cmd: Some("printf".into()),
+        engine_labels: Some(r#"%s {"version":"3.19"}"#.into()),
+        ..Default::default()
+    };
+    let engine = EngineCommands {
+        artifacts: Some(dir.to_path_buf()),
+        ..EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap()
+    };
+    image_labels(&engine, "alpine").unwrap();
+    let log = std::fs::read_to_string(dir.join(ENGINE_LOG)).unwrap();
+    assert!(log.starts_with(r#"$ printf %s {"version":"3.19"}"#));
+    assert!(log.contains("--- stdout\n{\"version\":\"3.19\"}--- stderr\n"));
+}
+
+#[test]
+fn local_checks_are_logged_in_the_artifacts() {
+    let dir = TempDir::new("engine-log-local");
+    let custom = CustomEngine {
+        engine_cmd: Some("false".into()),
+        engine_exists: Some("exists {image}".into()),
+        ..Default::default()
+    };
+    let engine = EngineCommands {
+        artifacts: Some(dir.to_path_buf()),
+        ..EngineCommands::new(Engine::Custom, &custom, true, false, true).unwrap()
+    };
+    let output = dir.join("image.tar");
+    let error = get_image(
+        &engine,
+        "alpine",
+        output.to_str().unwrap(),
+        &unbox::progress::NoProgress,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("is not stored locally"));
+    let log = std::fs::read_to_string(dir.join(ENGINE_LOG)).unwrap();
+    assert!(log.starts_with("$ false exists alpine"));
+}
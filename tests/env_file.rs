@@ -0,0 +1,36 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::config::parse_env_file;
+
+#[test]
+fn dotenv_files_are_parsed() {
+    let contents = r#"
+# Settings of the project
+EDITOR=vim
+export PAGER = less
+GREETING="hello world"
+EMPTY=
+QUOTE='"'
+"#;
+    let env = parse_env_file(contents).unwrap();
+    let pairs: Vec<_> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    assert_eq!(
+        pairs,
+        [
+            ("EDITOR", "vim"),
+            ("PAGER", "less"),
+            ("GREETING", "hello world"),
+            ("EMPTY", ""),
+            ("QUOTE", "\""),
+        ]
+    );
+}
+
+#[test]
+fn malformed_lines_are_reported() {
+    let error = parse_env_file("EDITOR=vim\n\nnot a variable\n").unwrap_err();
+    assert!(error.to_string().contains("Line 3"));
+    assert!(parse_env_file("1ST=value").is_err());
+}
@@ -0,0 +1,38 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use unbox::config::MountInfo;
+use unbox::run::equivalent_dir;
+
+#[test]
+fn the_deepest_mount_of_the_current_directory_is_used() {
+    // Missing paths are not canonicalized, which keeps the test independent of the host
+    let mounts: Vec<MountInfo> = [
+        ("/host/unbox-missing/home", "/home"),
+        ("/host/unbox-missing/home/me/src", "/src"),
+        ("/host/unbox-missing/tmp", "/tmp"),
+        ("/opt/inside", "/opt/tools"),
+    ]
+    .into_iter()
+    .map(MountInfo::from)
+    .collect();
+    let inside = |cwd: &str| equivalent_dir(Path::new(cwd), &mounts);
+    assert_eq!(
+        inside("/unbox-missing/home/me/notes").as_deref(),
+        Some(Path::new("/home/me/notes"))
+    );
+    assert_eq!(
+        inside("/unbox-missing/home/me/src/unbox").as_deref(),
+        Some(Path::new("/src/unbox"))
+    );
+    assert_eq!(
+        inside("/unbox-missing/tmp").as_deref(),
+        Some(Path::new("/tmp"))
+    );
+    // Sources inside of the toolbox are not host paths
+    assert_eq!(inside("/opt/inside"), None);
+    assert_eq!(inside("/unbox-missing/homeless"), None);
+}
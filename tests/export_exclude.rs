@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use common::TempDir;
+use unbox::archive::Compression;
+use unbox::export::*;
+
+fn exported_paths(name: &str, patterns: &[&str]) -> Vec<PathBuf> {
+    let dir = TempDir::new(name);
+    let root = dir.join("root");
+    for path in ["etc", "var/cache/apt", "usr/lib/python"] {
+        std::fs::create_dir_all(root.join(path)).unwrap();
+    }
+    for path in [
+        "etc/motd",
+        "var/cache/apt/pkgcache.bin",
+        "usr/lib/python/mod.pyc",
+    ] {
+        std::fs::write(root.join(path), "").unwrap();
+    }
+    std::fs::write(root.join(IGNORE_FILE), "/var/cache/\n").unwrap();
+    let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+    let exclude = excludes(&root, &patterns).unwrap();
+    let output = dir.join("rootfs.tar");
+    write_tar(&root, &output, Compression::None, Some(&exclude), None).unwrap();
+    let mut archive = tar::Archive::new(File::open(&output).unwrap());
+    let paths = archive
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().into_owned())
+        .collect();
+    paths
+}
+
+#[test]
+fn matched_paths_are_left_out() {
+    let paths = exported_paths("export-exclude", &["*.pyc"]);
+    assert!(paths.contains(&PathBuf::from("etc/motd")));
+    assert!(paths.contains(&PathBuf::from("usr/lib/python")));
+    assert!(paths.contains(&PathBuf::from("var")));
+    assert!(!paths.iter().any(|path| path.starts_with("var/cache")));
+    assert!(!paths.iter().any(|path| path.ends_with("mod.pyc")));
+    assert!(!paths.contains(&PathBuf::from(IGNORE_FILE)));
+}
@@ -0,0 +1,58 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::fs::File;
+use std::os::unix::fs::symlink;
+use std::time::{Duration, SystemTime};
+
+use common::TempDir;
+use unbox::archive::Compression;
+use unbox::export::*;
+
+#[test]
+fn reproducible_tarballs_only_depend_on_the_contents() {
+    let dir = TempDir::new("export-reproducible");
+    let root = dir.join("root");
+    std::fs::create_dir_all(root.join("usr/bin")).unwrap();
+    std::fs::create_dir_all(root.join("etc")).unwrap();
+    std::fs::write(root.join("etc/motd"), "hello\n").unwrap();
+    std::fs::write(root.join("usr/bin/tool"), "#!/bin/sh\n").unwrap();
+    symlink("usr/bin", root.join("bin")).unwrap();
+
+    let export = |name: &str| {
+        let output = dir.join(name);
+        write_tar(&root, &output, Compression::Gzip, None, Some(42)).unwrap();
+        std::fs::read(output).unwrap()
+    };
+    let first = export("first.tar.gz");
+    let touched = SystemTime::now() - Duration::from_secs(3600);
+    File::options()
+        .write(true)
+        .open(root.join("etc/motd"))
+        .unwrap()
+        .set_modified(touched)
+        .unwrap();
+    let second = export("second.tar.gz");
+    assert_eq!(first, second);
+
+    let tar = dir.join("plain.tar");
+    write_tar(&root, &tar, Compression::None, None, Some(42)).unwrap();
+    let mut archive = tar::Archive::new(File::open(&tar).unwrap());
+    for entry in archive.entries().unwrap() {
+        let header = entry.unwrap().header().clone();
+        assert_eq!(header.mtime().unwrap(), 42);
+        assert_eq!((header.uid().unwrap(), header.gid().unwrap()), (0, 0));
+    }
+}
+
+#[test]
+fn source_date_epoch_is_only_used_by_reproducible_exports() {
+    assert_eq!(entries_mtime(None, false, Some("42")).unwrap(), None);
+    assert_eq!(entries_mtime(None, true, Some("42")).unwrap(), Some(42));
+    assert_eq!(entries_mtime(None, true, None).unwrap(), Some(0));
+    assert_eq!(entries_mtime(Some(7), false, Some("42")).unwrap(), Some(7));
+    assert!(entries_mtime(None, true, Some("yesterday")).is_err());
+}
@@ -0,0 +1,45 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::fs::File;
+use std::os::unix::fs::symlink;
+use std::time::SystemTime;
+
+use common::TempDir;
+use unbox::clone::{hash_tree, TreeHash};
+
+#[test]
+fn metadata_hashes_skip_the_contents() {
+    let dir = TempDir::new("hash-tree");
+    std::fs::create_dir_all(dir.join("usr/bin")).unwrap();
+    std::fs::write(dir.join("usr/bin/tool"), "#!/bin/sh\n").unwrap();
+    symlink("usr/bin", dir.join("bin")).unwrap();
+    let modified = File::open(dir.join("usr/bin/tool"))
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .modified()
+        .unwrap();
+    let hash = |kind| hash_tree(&dir, kind).unwrap();
+    let (metadata, contents) = (hash(TreeHash::Metadata), hash(TreeHash::Contents));
+    assert!(metadata.starts_with("metadata:"));
+    assert!(contents.starts_with("contents:"));
+    assert_eq!(contents.len(), "contents:".len() + 64);
+    assert_eq!(hash(TreeHash::Metadata), metadata);
+
+    // Same size and modification time, only the contents can tell them apart
+    std::fs::write(dir.join("usr/bin/tool"), "#!/bin/ls\n").unwrap();
+    let file = File::options()
+        .write(true)
+        .open(dir.join("usr/bin/tool"))
+        .unwrap();
+    file.set_modified(modified).unwrap();
+    assert_eq!(hash(TreeHash::Metadata), metadata);
+    assert_ne!(hash(TreeHash::Contents), contents);
+
+    file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+    assert_ne!(hash(TreeHash::Metadata), metadata);
+}
@@ -0,0 +1,36 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::namespaces::*;
+
+const SUBUID: &str = "alice:100000:65536\n";
+
+fn maps(maps: &[&str]) -> Vec<IdMap> {
+    maps.iter().map(|map| parse_id_map(map).unwrap()).collect()
+}
+
+#[test]
+fn mappings_are_parsed() {
+    let map = parse_id_map("0:1000:1").unwrap();
+    assert_eq!((map.inside, map.outside, map.len), (0, 1000, 1));
+    assert!(parse_id_map("0:1000").is_err());
+    assert!(parse_id_map("0:1000:0").is_err());
+    assert!(parse_id_map("0:-1:1").is_err());
+}
+
+#[test]
+fn mappings_fit_in_the_subordinate_ids() {
+    let valid = maps(&["0:1000:1", "1:100000:999", "1000:101000:1"]);
+    check_id_maps(&valid, "alice", 1000, SUBUID).unwrap();
+    let overlapping = maps(&["0:1000:1", "1:100000:10", "5:100100:10"]);
+    let error = check_id_maps(&overlapping, "alice", 1000, SUBUID).unwrap_err();
+    assert!(error.to_string().contains("inside"));
+    let shared = maps(&["1:100000:10", "20:100005:10"]);
+    let error = check_id_maps(&shared, "alice", 1000, SUBUID).unwrap_err();
+    assert!(error.to_string().contains("outside"));
+    let foreign = maps(&["0:1001:1"]);
+    assert!(check_id_maps(&foreign, "alice", 1000, SUBUID).is_err());
+    let beyond = maps(&["1:160000:10000"]);
+    assert!(check_id_maps(&beyond, "alice", 1000, SUBUID).is_err());
+}
@@ -0,0 +1,25 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::engine::image_digest;
+
+const DIGEST: &str = "sha256:b6ca290b6b4cdcca5b3db3ffa338ee0285c11744b4a6abaa9627746ee3291d8d";
+
+#[test]
+fn digest_is_extracted() {
+    let url = format!("docker.io/alpine@{DIGEST}");
+    assert_eq!(image_digest(&url).unwrap(), Some(DIGEST));
+}
+
+#[test]
+fn tags_are_not_pinned() {
+    assert_eq!(image_digest("docker.io/alpine:3.16").unwrap(), None);
+}
+
+#[test]
+fn invalid_digests_are_rejected() {
+    assert!(image_digest("alpine@sha256:1234").is_err());
+    assert!(image_digest("alpine@md5:b6ca290b6b4cdcca5b3db3ffa338ee02").is_err());
+    assert!(image_digest(&format!("alpine@{}", DIGEST.to_uppercase())).is_err());
+}
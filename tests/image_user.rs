@@ -0,0 +1,71 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use unbox::user::{resolve, unused_id, Credentials};
+
+fn rootfs(name: &str) -> TempDir {
+    let root = TempDir::new(name);
+    std::fs::create_dir_all(root.join("etc")).unwrap();
+    std::fs::write(
+        root.join("etc/passwd"),
+        "root:x:0:0:root:/root:/bin/sh\nnginx:x:101:102:nginx:/var/cache/nginx:/sbin/nologin\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("etc/group"),
+        "root:x:0:\nnginx:x:102:\nwww:x:33:\n",
+    )
+    .unwrap();
+    root
+}
+
+#[test]
+fn names_are_resolved_with_the_rootfs() {
+    let root = rootfs("user-names");
+    let nginx = Credentials {
+        uid: 101,
+        gid: 102,
+        home: Some("/var/cache/nginx".into()),
+    };
+    assert_eq!(resolve(&root, "nginx").unwrap(), nginx);
+    assert_eq!(resolve(&root, "101").unwrap(), nginx);
+    assert_eq!(resolve(&root, "nginx:www").unwrap().gid, 33);
+}
+
+#[test]
+fn numeric_ids_do_not_need_an_entry() {
+    let root = rootfs("user-numeric");
+    let expected = Credentials {
+        uid: 1000,
+        gid: 1000,
+        home: None,
+    };
+    assert_eq!(resolve(&root, "1000:1000").unwrap(), expected);
+    assert_eq!(resolve(&root, "1000").unwrap().gid, 0);
+}
+
+#[test]
+fn unknown_or_unmapped_users_are_rejected() {
+    let root = rootfs("user-unknown");
+    assert!(resolve(&root, "postgres").is_err());
+    assert!(resolve(&root, "nginx:postgres").is_err());
+    assert!(resolve(&root, "100000").is_err());
+}
+
+#[test]
+fn new_users_get_the_first_unused_ids_from_1000() {
+    let root = rootfs("user-unused");
+    assert_eq!(unused_id(&root, "etc/passwd").unwrap(), 1000);
+    std::fs::write(
+        root.join("etc/group"),
+        "root:x:0:\nusers:x:1000:\ndocker:x:1001:\n",
+    )
+    .unwrap();
+    assert_eq!(unused_id(&root, "etc/group").unwrap(), 1002);
+    std::fs::remove_file(root.join("etc/passwd")).unwrap();
+    assert_eq!(unused_id(&root, "etc/passwd").unwrap(), 1000);
+}
@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use common::TempDir;
+use unbox::files::list_dir;
+
+#[test]
+fn directories_are_listed_inside_of_the_rootfs() {
+    let root = TempDir::new("list-dir");
+    std::fs::create_dir_all(root.join("etc/apk")).unwrap();
+    std::fs::write(root.join("etc/os-release"), "ID=alpine\n").unwrap();
+    // Absolute links point into the rootfs, not to the directories of the host
+    symlink("/etc", root.join("config")).unwrap();
+
+    let list = |path: &str| list_dir(&root, Path::new(path)).unwrap();
+    assert_eq!(list("/etc"), ["apk", "os-release"]);
+    assert_eq!(list("/config"), ["apk", "os-release"]);
+    assert_eq!(list("../../../etc"), ["apk", "os-release"]);
+    assert_eq!(list("/"), ["config", "etc"]);
+    assert_eq!(list("/etc/os-release"), ["/etc/os-release"]);
+    assert!(list_dir(&root, Path::new("/missing")).is_err());
+}
@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::symlink;
+
+use common::TempDir;
+use unbox::os_release::{parse, read};
+
+#[test]
+fn os_release_files_are_parsed() {
+    let fedora = parse(
+        r#"
+NAME="Fedora Linux"
+VERSION="39 (Container Image)"
+ID=fedora
+VERSION_ID=39
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        (fedora.id.as_str(), fedora.to_string()),
+        ("fedora", "Fedora Linux 39".into())
+    );
+    let arch = parse("NAME=\"Arch Linux\"\nID=arch\nBUILD_ID=rolling\n").unwrap();
+    assert_eq!(arch.to_string(), "Arch Linux rolling");
+    let empty = parse("# nothing\n").unwrap();
+    assert_eq!(
+        (empty.id.as_str(), empty.to_string()),
+        ("linux", "Linux".into())
+    );
+    assert!(parse("not an os-release file").is_none());
+}
+
+#[test]
+fn os_release_is_read_inside_of_the_rootfs() {
+    let root = TempDir::new("os-release");
+    std::fs::create_dir_all(root.join("etc")).unwrap();
+    assert!(read(&root).is_none());
+    std::fs::create_dir_all(root.join("usr/lib")).unwrap();
+    std::fs::write(
+        root.join("usr/lib/os-release"),
+        "NAME=Alpine\nVERSION_ID=3.19\n",
+    )
+    .unwrap();
+    assert_eq!(read(&root).unwrap().to_string(), "Alpine 3.19");
+    // Absolute links point into the rootfs, not to the os-release of the host
+    symlink("/usr/lib/os-release", root.join("etc/os-release")).unwrap();
+    assert_eq!(read(&root).unwrap().to_string(), "Alpine 3.19");
+}
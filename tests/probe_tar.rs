@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use tar::{Builder, EntryType, Header};
+use unbox::archive::{Compression, DecoderOptions};
+use unbox::engine::*;
+use unbox::probe::probe_tar;
+
+fn link(builder: &mut Builder<Vec<u8>>, path: &str, target: &str) {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_cksum();
+    builder.append_link(&mut header, path, target).unwrap();
+}
+
+#[test]
+fn tarballs_are_probed_without_unpacking() {
+    let dir = TempDir::new("probe-tar");
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(8);
+    header.set_mode(0o755);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "usr/bin/busybox", &b"\x7fELF...."[..])
+        .unwrap();
+    // /bin/sh is only found by following both links
+    link(&mut builder, "bin", "usr/bin");
+    link(&mut builder, "usr/bin/sh", "/bin/busybox");
+    link(&mut builder, "usr/bin/bash", "missing");
+    let tar = dir.join("rootfs.tar");
+    std::fs::write(&tar, builder.into_inner().unwrap()).unwrap();
+
+    let report = probe_tar(&tar, &DecoderOptions::default()).unwrap();
+    assert_eq!(report.compression, Compression::None);
+    assert_eq!(report.entries, 4);
+    assert_eq!(report.bytes, 8);
+    assert_eq!(report.shells, ["/bin/sh"]);
+    assert!(!dir.join("usr").exists());
+}
+
+#[test]
+fn image_config_is_read_from_the_engine() {
+    let custom = CustomEngine {
+        engine_cmd: Some("printf".into()),
+        engine_config: Some(r#"%s {"Env":["PATH=/bin"],"Cmd":null,"WorkingDir":""}"#.into()),
+        ..Default::default()
+    };
+    let engine = EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap();
+    let config = image_config(&engine, "alpine").unwrap().unwrap();
+    assert_eq!(config.env, Some(vec!["PATH=/bin".to_string()]));
+    assert_eq!(config.cmd, None);
+    assert_eq!(config.workdir, None);
+    let custom = CustomEngine {
+        engine_cmd: Some("printf".into()),
+        ..Default::default()
+    };
+    let engine = EngineCommands::new(Engine::Custom, &custom, true, false, false).unwrap();
+    assert_eq!(image_config(&engine, "alpine").unwrap(), None);
+}
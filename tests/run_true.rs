@@ -10,6 +10,7 @@ fn run_true() {
         name: "exec-tests".into(),
         cmd: "true".into(),
         args: vec![],
+        ..Default::default()
     });
     nsexec(args).unwrap();
 }
@@ -20,6 +21,7 @@ fn run_bin_true() {
         name: "exec-tests".into(),
         cmd: "/bin/true".into(),
         args: vec![],
+        ..Default::default()
     });
     nsexec(args).unwrap();
 }
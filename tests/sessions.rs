@@ -0,0 +1,58 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use unbox::config::set_storage_root;
+use unbox::sessions::{active, kill, Kill, Session};
+
+#[test]
+fn sessions_of_finished_processes_are_pruned() {
+    let root = TempDir::new("sessions");
+    set_storage_root(&root);
+    let current = Session::current("dev", "/bin/sh").unwrap();
+    current.write().unwrap();
+    // Same pid but started at another time, like a pid reused after the session ended
+    let reused = Session {
+        pid: current.pid,
+        ticks: current.ticks + 1,
+        ..current.clone()
+    };
+    assert!(!reused.is_running());
+    let finished = Session {
+        pid: u32::MAX,
+        ..current.clone()
+    };
+    finished.write().unwrap();
+    // Records that can not be read are skipped but not removed
+    std::fs::write(root.join("sessions/broken.json"), "{\"name\":").unwrap();
+    std::fs::write(root.join("sessions/.1.json"), "{\"name\":").unwrap();
+
+    assert_eq!(active().unwrap(), std::slice::from_ref(&current));
+    let files = std::fs::read_dir(root.join("sessions")).unwrap().count();
+    assert_eq!(files, 3);
+
+    // The pid of the finished session is not taken for a running one
+    let missing = Kill {
+        target: u32::MAX.to_string(),
+        grace: std::time::Duration::ZERO,
+    };
+    assert!(kill(missing).is_err());
+
+    // The record removes the file once the session ends, when unbox is still there to do it
+    let ended = Session {
+        pid: u32::MAX - 1,
+        ..current.clone()
+    };
+    let file = root.join(format!("sessions/{}.json", ended.pid));
+    let record = ended.write().unwrap();
+    assert!(file.exists());
+    record.remove().unwrap();
+    assert!(!file.exists());
+    // A record already pruned by ps is not an error
+    let record = ended.write().unwrap();
+    std::fs::remove_file(&file).unwrap();
+    record.remove().unwrap();
+}
@@ -0,0 +1,32 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use unbox::config::{parse_shell_args, ShellArgs};
+
+fn split(args: &str) -> Vec<String> {
+    let ShellArgs(args) = parse_shell_args(args).unwrap();
+    args
+}
+
+#[test]
+fn shell_args_are_split_like_a_shell() {
+    assert_eq!(split("--noprofile  --norc"), ["--noprofile", "--norc"]);
+    assert_eq!(
+        split(r#"-C 'set -g fish_greeting ""'"#),
+        ["-C", r#"set -g fish_greeting """#]
+    );
+    assert_eq!(
+        split(r#"-c "echo \"hi\" there""#),
+        ["-c", r#"echo "hi" there"#]
+    );
+    assert_eq!(split(r"a\ b ''"), ["a b", ""]);
+    assert!(split("  ").is_empty());
+}
+
+#[test]
+fn unterminated_shell_args_are_rejected() {
+    assert!(parse_shell_args("-c 'echo").is_err());
+    assert!(parse_shell_args(r#"-c "echo \""#).is_err());
+    assert!(parse_shell_args(r"--norc \").is_err());
+}
@@ -0,0 +1,27 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::{archive, TempDir};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+#[test]
+fn later_archives_overlay_earlier_ones() {
+    let root = TempDir::new("layered-tars");
+    let base = archive(&[("etc/motd", "base\n"), ("etc/issue", "base\n")]);
+    let tools = archive(&[("etc/motd", "tools\n"), ("usr/bin/tool", "#!/bin/sh\n")]);
+    let mut stats = UnpackStats::default();
+    for archive in [base, tools] {
+        let root = root.to_str().unwrap();
+        let options = UnpackOptions::default();
+        stats.add(unpack_tar(&archive[..], None, root, &options, &NoProgress).unwrap());
+    }
+    assert_eq!(stats.entries, 4);
+    let motd = std::fs::read_to_string(root.join("etc/motd")).unwrap();
+    assert_eq!(motd, "tools\n");
+    assert!(root.join("etc/issue").exists());
+    assert!(root.join("usr/bin/tool").exists());
+}
@@ -0,0 +1,63 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use common::TempDir;
+use tar::{Builder, EntryType, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+/// Nested directories with a path longer than the 100 bytes of ustar
+fn long_dir() -> String {
+    (0..30)
+        .map(|i| format!("directory{i:02}"))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn long_archive() -> Vec<u8> {
+    let dir = long_dir();
+    let mut builder = Builder::new(Vec::new());
+    // Read-only directories can only get their permissions after their contents
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o555);
+    builder
+        .append_data(&mut header, format!("{dir}/"), &[][..])
+        .unwrap();
+    let content = b"deep\n";
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, format!("{dir}/file"), &content[..])
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn long_paths_are_unpacked_in_place() {
+    assert!(long_dir().len() > 200);
+    let root = TempDir::new("long-paths");
+    let options = UnpackOptions::default();
+    let archive = long_archive();
+    let stats = unpack_tar(
+        &archive[..],
+        None,
+        root.to_str().unwrap(),
+        &options,
+        &NoProgress,
+    )
+    .unwrap();
+    assert!(stats.skipped.is_empty());
+    let dir = root.join(long_dir());
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), b"deep\n");
+    let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o555);
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
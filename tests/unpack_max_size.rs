@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use tar::{Builder, Header};
+use unbox::create::parse_size;
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+fn tarball(files: &[(&str, usize)]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for (path, size) in files {
+        let mut header = Header::new_gnu();
+        header.set_size(*size as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, path, &vec![0; *size][..])
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn extraction_stops_at_the_limits() {
+    let root = TempDir::new("max-size-root");
+    let tar = tarball(&[("first", 600), ("second", 600), ("third", 600)]);
+    let unpack = |options: &UnpackOptions| {
+        unpack_tar(&tar[..], None, root.to_str().unwrap(), options, &NoProgress)
+    };
+
+    let options = UnpackOptions {
+        max_size: Some(1000),
+        ..Default::default()
+    };
+    let error = unpack(&options).unwrap_err();
+    assert!(is_too_large(&error));
+    assert!(root.join("first").exists());
+    assert!(!root.join("second").exists());
+
+    // What is left after a previous archive is what a later one can use
+    let options = UnpackOptions {
+        max_size: Some(3000),
+        ..Default::default()
+    };
+    assert_eq!(unpack(&options).unwrap().bytes, 1800);
+    let unpacked = UnpackStats {
+        entries: 3,
+        bytes: 1800,
+        ..Default::default()
+    };
+    let error = unpack(&options.after(&unpacked)).unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(TooLarge::Size)));
+
+    let options = UnpackOptions {
+        max_entries: Some(2),
+        ..Default::default()
+    };
+    let error = unpack(&options).unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(TooLarge::Entries)));
+}
+
+#[test]
+fn sizes_are_parsed() {
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("256k"), Ok(256 << 10));
+    assert_eq!(parse_size("4M"), Ok(4 << 20));
+    assert_eq!(parse_size("2G"), Ok(2 << 30));
+    for invalid in ["", "0", "G", "1T", "1.5M", "-1"] {
+        assert!(parse_size(invalid).is_err(), "{invalid}");
+    }
+    let error = parse_size("17179869184G").unwrap_err();
+    assert_eq!(error, "17179869184G is too large");
+}
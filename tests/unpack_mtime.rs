@@ -0,0 +1,58 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::MetadataExt;
+
+use common::TempDir;
+use tar::{Builder, EntryType, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+#[test]
+fn fixed_mtime_replaces_the_archived_ones() {
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    header.set_mtime(1_600_000_000);
+    header.set_cksum();
+    builder.append_data(&mut header, "etc", &[][..]).unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_mtime(1_600_000_000);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "etc/motd", &b"base\n"[..])
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_mtime(1_600_000_000);
+    builder
+        .append_link(&mut header, "etc/issue", "motd")
+        .unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    let root = TempDir::new("fixed-mtime");
+    let options = UnpackOptions {
+        mtime: Some(1_000_000_000),
+        ..Default::default()
+    };
+    unpack_tar(
+        &tar[..],
+        None,
+        root.to_str().unwrap(),
+        &options,
+        &NoProgress,
+    )
+    .unwrap();
+    for path in ["etc", "etc/motd", "etc/issue"] {
+        let meta = std::fs::symlink_metadata(root.join(path)).unwrap();
+        assert_eq!(meta.mtime(), 1_000_000_000, "{path}");
+    }
+}
@@ -0,0 +1,102 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::MetadataExt;
+
+use common::{record, TempDir};
+use tar::{Builder, EntryType, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+fn archive() -> Vec<u8> {
+    archive_with(|_| {})
+}
+
+/// Archive with the global header and a file, followed by what `extra` appends
+fn archive_with(extra: fn(&mut Builder<Vec<u8>>)) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    let global = record("mtime", "1000000000.5") + &record("comment", "Alpine 3.16 rootfs");
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XGlobalHeader);
+    header.set_size(global.len() as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "pax_global_header", global.as_bytes())
+        .unwrap();
+    let mut header = Header::new_ustar();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_mtime(1_600_000_000);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "etc/motd", &b"base\n"[..])
+        .unwrap();
+    extra(&mut builder);
+    builder.into_inner().unwrap()
+}
+
+fn unpack(name: &str, options: &UnpackOptions) -> (TempDir, UnpackStats) {
+    let root = TempDir::new(name);
+    let root_str = root.to_str().unwrap();
+    let stats = unpack_tar(&archive()[..], None, root_str, options, &NoProgress).unwrap();
+    (root, stats)
+}
+
+#[test]
+fn global_records_apply_to_the_following_entries() {
+    let (root, stats) = unpack("pax-global", &UnpackOptions::default());
+    assert_eq!(stats.entries, 1);
+    assert_eq!(stats.comment.as_deref(), Some("Alpine 3.16 rootfs"));
+    let meta = std::fs::metadata(root.join("etc/motd")).unwrap();
+    assert_eq!(meta.mtime(), 1_000_000_000);
+}
+
+#[test]
+fn explicit_mtime_wins_over_the_global_one() {
+    let options = UnpackOptions {
+        mtime: Some(1_200_000_000),
+        ..Default::default()
+    };
+    let (root, _) = unpack("pax-global-mtime", &options);
+    let meta = std::fs::metadata(root.join("etc/motd")).unwrap();
+    assert_eq!(meta.mtime(), 1_200_000_000);
+}
+
+#[test]
+fn entry_records_win_over_the_global_one() {
+    let tar = archive_with(|builder| {
+        let local = record("mtime", "1300000000");
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_size(local.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "PaxHeaders/etc/issue", local.as_bytes())
+            .unwrap();
+        let mut header = Header::new_ustar();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_mtime(1_600_000_000);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "etc/issue", &b"base\n"[..])
+            .unwrap();
+    });
+
+    let root = TempDir::new("pax-local-mtime");
+    let options = UnpackOptions::default();
+    unpack_tar(
+        &tar[..],
+        None,
+        root.to_str().unwrap(),
+        &options,
+        &NoProgress,
+    )
+    .unwrap();
+    let mtime = |path: &str| std::fs::metadata(root.join(path)).unwrap().mtime();
+    assert_eq!(mtime("etc/motd"), 1_000_000_000);
+    assert_eq!(mtime("etc/issue"), 1_300_000_000);
+}
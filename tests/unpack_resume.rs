@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::fs::File;
+
+use common::{archive, TempDir};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+#[test]
+fn resumed_extraction_skips_unpacked_files() {
+    let root = TempDir::new("resume-root");
+    remove_resume_index(&root);
+    let options = UnpackOptions {
+        resume: true,
+        ..Default::default()
+    };
+    let tar = archive(&[("etc/motd", "base\n"), ("etc/issue", "base\n")]);
+    let unpack = |tar: &[u8]| {
+        let root = root.to_str().unwrap();
+        unpack_tar(tar, None, root, &options, &NoProgress).unwrap()
+    };
+    unpack(&tar);
+
+    // Same size and mtime, so it counts as unpacked, unlike the truncated one
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+    std::fs::write(root.join("etc/motd"), "kept\n").unwrap();
+    File::options()
+        .write(true)
+        .open(root.join("etc/motd"))
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    std::fs::write(root.join("etc/issue"), "ba").unwrap();
+    let stats = unpack(&tar);
+    assert_eq!(stats.entries, 2);
+    let motd = std::fs::read_to_string(root.join("etc/motd")).unwrap();
+    assert_eq!(motd, "kept\n");
+    let issue = std::fs::read_to_string(root.join("etc/issue")).unwrap();
+    assert_eq!(issue, "base\n");
+
+    // Without the index everything is unpacked again
+    remove_resume_index(&root);
+    unpack(&tar);
+    let motd = std::fs::read_to_string(root.join("etc/motd")).unwrap();
+    assert_eq!(motd, "base\n");
+    remove_resume_index(&root);
+}
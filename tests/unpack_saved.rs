@@ -0,0 +1,44 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::{layer, saved_image, TempDir};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+#[test]
+fn saved_layers_are_applied_in_order() {
+    let dir = TempDir::new("saved-layers");
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    let tar = dir.join("image.tar");
+    std::fs::write(&tar, saved_image()).unwrap();
+    let options = UnpackOptions::default();
+    unpack_saved(&tar, root.to_str().unwrap(), &options, &NoProgress).unwrap();
+    assert!(!root.join("etc/old").exists());
+    assert!(!root.join("etc/.wh.old").exists());
+    assert!(root.join("etc/kept").exists());
+    assert!(root.join("etc/new").exists());
+}
+
+#[test]
+fn saved_layers_are_resolved_without_unpacking() {
+    let dir = TempDir::new("saved-layers-plan");
+    let tar = dir.join("image.tar");
+    std::fs::write(&tar, saved_image()).unwrap();
+    let layers = saved_layers(&tar).unwrap();
+    let paths: Vec<_> = layers.iter().map(|layer| layer.path.clone()).collect();
+    assert_eq!(
+        paths,
+        ["lower/layer.tar", "blobs/sha256/upper"].map(std::path::PathBuf::from)
+    );
+    assert_eq!(layers[0].digest, None);
+    assert_eq!(layers[1].digest.as_deref(), Some("sha256:upper"));
+    assert_eq!(
+        layers[1].size,
+        layer(&["etc/.wh.old", "etc/new"]).len() as u64
+    );
+    assert_eq!(layers[1].compression, unbox::archive::Compression::None);
+}
@@ -0,0 +1,57 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use common::TempDir;
+use tar::{Builder, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+fn setuid_archive() -> Vec<u8> {
+    let content = b"#!/bin/sh\n";
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o4755);
+    header.set_cksum();
+    let mut builder = Builder::new(Vec::new());
+    builder
+        .append_data(&mut header, "usr/bin/suid", &content[..])
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+fn unpack(name: &str, no_setuid: bool) -> u32 {
+    let root = TempDir::new(name);
+    let options = UnpackOptions {
+        no_setuid,
+        ..Default::default()
+    };
+    let archive = setuid_archive();
+    unpack_tar(
+        &archive[..],
+        None,
+        root.to_str().unwrap(),
+        &options,
+        &NoProgress,
+    )
+    .unwrap();
+    let mode = std::fs::metadata(root.join("usr/bin/suid"))
+        .unwrap()
+        .permissions()
+        .mode();
+    mode & 0o7777
+}
+
+#[test]
+fn unpack_preserves_setuid() {
+    assert_eq!(unpack("preserve-setuid", false), 0o4755);
+}
+
+#[test]
+fn unpack_clears_setuid() {
+    assert_eq!(unpack("clear-setuid", true), 0o755);
+}
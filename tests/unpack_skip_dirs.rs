@@ -0,0 +1,84 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::io::Write;
+
+use common::TempDir;
+use tar::{Builder, EntryType, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+#[test]
+fn mountpoints_are_left_empty() {
+    let root = TempDir::new("skip-dirs-root");
+    let mut builder = Builder::new(Vec::new());
+    for dir in ["./dev/", "./dev/pts/", "proc/"] {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, dir, &[][..]).unwrap();
+    }
+    // Device nodes can not be created without privileges, so they would fail the unpack
+    let mut null = Header::new_gnu();
+    null.set_entry_type(EntryType::Char);
+    null.set_device_major(1).unwrap();
+    null.set_device_minor(3).unwrap();
+    null.set_size(0);
+    null.set_mode(0o666);
+    null.set_cksum();
+    builder
+        .append_data(&mut null, "./dev/null", &[][..])
+        .unwrap();
+    for file in ["proc/version", "etc/hostname", "device"] {
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, file, &b"box"[..]).unwrap();
+    }
+    let tar = builder.into_inner().unwrap();
+
+    let options = UnpackOptions {
+        skip_dirs: MOUNTPOINT_DIRS,
+        ..Default::default()
+    };
+    let new_root = root.to_str().unwrap();
+    unpack_tar(&tar[..], None, new_root, &options, &NoProgress).unwrap();
+    assert!(root.join("dev").is_dir());
+    assert!(root.join("proc").is_dir());
+    assert_eq!(std::fs::read_dir(root.join("dev")).unwrap().count(), 0);
+    assert_eq!(std::fs::read_dir(root.join("proc")).unwrap().count(), 0);
+    assert!(root.join("etc/hostname").is_file());
+    assert!(root.join("device").is_file());
+}
+
+#[test]
+fn zip_mountpoints_are_left_empty() {
+    let dir = TempDir::new("skip-dirs-zip");
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    let path = dir.join("rootfs.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    let options = zip::write::FileOptions::default();
+    zip.add_directory("rootfs/proc", options).unwrap();
+    for file in ["rootfs/proc/version", "rootfs/etc/hostname"] {
+        zip.start_file(file, options).unwrap();
+        zip.write_all(b"box").unwrap();
+    }
+    zip.finish().unwrap();
+
+    let options = UnpackOptions {
+        skip_dirs: MOUNTPOINT_DIRS,
+        strip_components: 1,
+        ..Default::default()
+    };
+    unpack_zip(&path, root.to_str().unwrap(), &options, &NoProgress).unwrap();
+    assert!(root.join("proc").is_dir());
+    assert_eq!(std::fs::read_dir(root.join("proc")).unwrap().count(), 0);
+    assert!(root.join("etc/hostname").is_file());
+}
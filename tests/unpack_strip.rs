@@ -0,0 +1,116 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::os::unix::fs::MetadataExt;
+
+use common::TempDir;
+use tar::{Builder, EntryType, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+fn header(path: &str, kind: EntryType, size: usize) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_size(size as u64);
+    header.set_mode(0o644);
+    // Written as is, `set_path` would refuse the `..` components
+    let name = &mut header.as_gnu_mut().unwrap().name;
+    name[..path.len()].copy_from_slice(path.as_bytes());
+    header.set_cksum();
+    header
+}
+
+#[test]
+fn leading_components_are_stripped() {
+    let root = TempDir::new("strip-root");
+    let mut builder = Builder::new(Vec::new());
+    let entries = [
+        ("rootfs/", EntryType::Directory, ""),
+        ("rootfs/etc/", EntryType::Directory, ""),
+        ("rootfs/etc/motd", EntryType::Regular, "hello\n"),
+        ("README", EntryType::Regular, "outside of the rootfs\n"),
+        ("rootfs/../../escape", EntryType::Regular, "escaped\n"),
+    ];
+    for (path, kind, content) in entries {
+        let header = header(path, kind, content.len());
+        builder.append(&header, content.as_bytes()).unwrap();
+    }
+    let mut link = header("rootfs/etc/issue", EntryType::Link, 0);
+    link.set_link_name("rootfs/etc/motd").unwrap();
+    link.set_cksum();
+    builder.append(&link, &[][..]).unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    let options = UnpackOptions {
+        strip_components: 1,
+        ..Default::default()
+    };
+    let stats = unpack_tar(
+        &tar[..],
+        None,
+        root.to_str().unwrap(),
+        &options,
+        &NoProgress,
+    )
+    .unwrap();
+    assert_eq!(stats.skipped, [std::path::PathBuf::from("../../escape")]);
+    let motd = std::fs::read_to_string(root.join("etc/motd")).unwrap();
+    assert_eq!(motd, "hello\n");
+    let ino = |path: &str| std::fs::metadata(root.join(path)).unwrap().ino();
+    assert_eq!(ino("etc/issue"), ino("etc/motd"));
+    assert!(!root.join("rootfs").exists());
+    assert!(!root.join("README").exists());
+    assert!(!root.parent().unwrap().join("escape").exists());
+}
+
+#[test]
+fn stripped_entries_do_not_follow_links_out_of_the_root() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new("strip-links");
+    let outside = dir.join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("victim"), "host\n").unwrap();
+    std::fs::set_permissions(
+        outside.join("victim"),
+        std::fs::Permissions::from_mode(0o644),
+    )
+    .unwrap();
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+
+    let mut builder = Builder::new(Vec::new());
+    let mut link = header("top/lnk", EntryType::Symlink, 0);
+    link.set_link_name(&outside).unwrap();
+    link.set_cksum();
+    builder.append(&link, &[][..]).unwrap();
+    let mut victim = header("top/lnk/victim", EntryType::Regular, 6);
+    victim.set_mode(0o4777);
+    victim.set_cksum();
+    builder.append(&victim, &b"inside"[..]).unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    let options = UnpackOptions {
+        strip_components: 1,
+        no_setuid: true,
+        mtime: Some(1),
+        ..Default::default()
+    };
+    let root_str = root.to_str().unwrap();
+    unpack_tar(&tar[..], None, root_str, &options, &NoProgress).unwrap();
+    let host = std::fs::metadata(outside.join("victim")).unwrap();
+    assert_eq!(host.permissions().mode() & 0o7777, 0o644);
+    assert_ne!(host.mtime(), 1);
+    assert_eq!(
+        std::fs::read_to_string(outside.join("victim")).unwrap(),
+        "host\n"
+    );
+    // The absolute link is resolved as if the root was `/`
+    let inside = root.join(outside.strip_prefix("/").unwrap()).join("victim");
+    let meta = std::fs::metadata(&inside).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o777);
+    assert_eq!(meta.mtime(), 1);
+}
@@ -0,0 +1,75 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::TempDir;
+use tar::{Builder, Header};
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+
+fn layer(files: &[&str]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for file in files {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, file, &[][..]).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+fn unpack_layers(name: &str, whiteouts: bool) -> TempDir {
+    let root = TempDir::new(name);
+    let options = UnpackOptions {
+        whiteouts,
+        ..Default::default()
+    };
+    let lower = layer(&["etc/old", "opt/a", "opt/b"]);
+    let upper = layer(&["etc/.wh.old", "opt/.wh..wh..opq", "opt/c"]);
+    for archive in [lower, upper] {
+        let root = root.to_str().unwrap();
+        unpack_tar(&archive[..], None, root, &options, &NoProgress).unwrap();
+    }
+    root
+}
+
+#[test]
+fn whiteouts_are_applied() {
+    let root = unpack_layers("apply-whiteouts", true);
+    assert!(!root.join("etc/old").exists());
+    assert!(!root.join("etc/.wh.old").exists());
+    assert!(!root.join("opt/a").exists());
+    assert!(!root.join("opt/b").exists());
+    assert!(root.join("opt/c").exists());
+}
+
+#[test]
+fn whiteouts_are_unpacked_by_default() {
+    let root = unpack_layers("keep-whiteouts", false);
+    assert!(root.join("etc/old").exists());
+    assert!(root.join("etc/.wh.old").exists());
+    assert_eq!(remove_leftover_whiteouts(&root).unwrap(), 2);
+    assert!(!root.join("etc/.wh.old").exists());
+}
+
+#[test]
+fn whiteouts_only_hide_names() {
+    let root = TempDir::new("whiteout-names");
+    let options = UnpackOptions {
+        whiteouts: true,
+        ..Default::default()
+    };
+    let lower = layer(&["etc/motd"]);
+    let upper = layer(&["etc/.wh.", "etc/.wh..", "etc/.wh..."]);
+    for archive in [lower, upper] {
+        let root = root.to_str().unwrap();
+        unpack_tar(&archive[..], None, root, &options, &NoProgress).unwrap();
+    }
+    assert!(root.join("etc/motd").exists());
+    // They are not whiteouts, so they are unpacked and removed with the leftovers
+    assert_eq!(remove_leftover_whiteouts(&root).unwrap(), 3);
+    assert!(root.join("etc/motd").exists());
+}
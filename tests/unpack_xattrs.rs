@@ -0,0 +1,68 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use common::{record, TempDir, Warnings};
+use tar::{Builder, EntryType, Header};
+use unbox::unpack::*;
+
+fn archive() -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    let pax = record("SCHILY.xattr.user.origin", "unbox") + &record("SCHILY.xattr.bogus.name", "x");
+    for name in ["etc/motd", "etc/issue"] {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_size(pax.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "pax", pax.as_bytes())
+            .unwrap();
+        let mut header = Header::new_ustar();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, &b"base\n"[..])
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn xattrs_are_applied_and_failures_warned_once() {
+    let root = TempDir::new("xattrs");
+    let options = UnpackOptions {
+        xattrs: true,
+        ..Default::default()
+    };
+    let warnings = Warnings::default();
+    let root_str = root.to_str().unwrap();
+    unpack_tar(&archive()[..], None, root_str, &options, &warnings).unwrap();
+    for name in ["etc/motd", "etc/issue"] {
+        let origin = xattr::get(root.join(name), "user.origin").unwrap();
+        assert_eq!(origin.as_deref(), Some(&b"unbox"[..]));
+    }
+    let warnings = warnings.0.into_inner();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(warnings[0].contains("bogus.name"));
+}
+
+#[test]
+fn xattrs_are_skipped_by_default() {
+    let root = TempDir::new("no-xattrs");
+    let warnings = Warnings::default();
+    let root_str = root.to_str().unwrap();
+    unpack_tar(
+        &archive()[..],
+        None,
+        root_str,
+        &UnpackOptions::default(),
+        &warnings,
+    )
+    .unwrap();
+    let origin = xattr::get(root.join("etc/motd"), "user.origin").unwrap();
+    assert_eq!(origin, None);
+    assert!(warnings.0.into_inner().is_empty());
+}
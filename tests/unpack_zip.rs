@@ -0,0 +1,97 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+mod common;
+
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use common::TempDir;
+use unbox::progress::NoProgress;
+use unbox::unpack::*;
+use zip::write::FileOptions;
+
+fn zip_archive(path: &Path) {
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    zip.add_directory("usr/bin", FileOptions::default().unix_permissions(0o755))
+        .unwrap();
+    zip.start_file(
+        "usr/bin/script",
+        FileOptions::default().unix_permissions(0o750),
+    )
+    .unwrap();
+    zip.write_all(b"#!/bin/sh\n").unwrap();
+    zip.add_symlink("bin", "usr/bin", FileOptions::default())
+        .unwrap();
+    zip.start_file("../escape", FileOptions::default()).unwrap();
+    zip.finish().unwrap();
+}
+
+#[test]
+fn zip_preserves_modes_and_links() {
+    let dir = TempDir::new("zip-modes");
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    let zip = dir.join("rootfs.zip");
+    zip_archive(&zip);
+    assert!(is_zip(&zip));
+    let options = UnpackOptions::default();
+    let stats = unpack_zip(&zip, root.to_str().unwrap(), &options, &NoProgress).unwrap();
+    assert_eq!(stats.entries, 4);
+    assert_eq!(stats.skipped, [PathBuf::from("../escape")]);
+    let mode = std::fs::metadata(root.join("bin/script"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o7777, 0o750);
+    assert!(!dir.join("escape").exists());
+}
+
+#[test]
+fn zip_directories_do_not_follow_links_out_of_the_root() {
+    let dir = TempDir::new("zip-links");
+    let outside = dir.join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::set_permissions(&outside, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    let path = dir.join("links.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    zip.add_symlink("d", outside.to_str().unwrap(), FileOptions::default())
+        .unwrap();
+    zip.add_directory("d", FileOptions::default().unix_permissions(0o700))
+        .unwrap();
+    zip.finish().unwrap();
+
+    let options = UnpackOptions::default();
+    unpack_zip(&path, root.to_str().unwrap(), &options, &NoProgress).unwrap();
+    let mode = std::fs::metadata(&outside).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o755);
+    // The absolute link is resolved as if the root was `/`
+    let inside = root.join(outside.strip_prefix("/").unwrap());
+    let mode = std::fs::metadata(inside).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o700);
+}
+
+#[test]
+fn zip_stats_count_the_uncompressed_size() {
+    let dir = TempDir::new("zip-size");
+    let root = dir.join("root");
+    std::fs::create_dir_all(&root).unwrap();
+    let path = dir.join("zeros.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    zip.start_file("zeros", FileOptions::default()).unwrap();
+    zip.write_all(&[0; 4096]).unwrap();
+    zip.finish().unwrap();
+
+    let options = UnpackOptions {
+        max_size: Some(5000),
+        ..Default::default()
+    };
+    let stats = unpack_zip(&path, root.to_str().unwrap(), &options, &NoProgress).unwrap();
+    assert_eq!(stats.bytes, 4096);
+    // The next archive only gets what is left of the uncompressed size
+    assert_eq!(options.after(&stats).max_size, Some(5000 - 4096));
+}